@@ -1,5 +1,5 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use mssmt::{CompactMSSMT, Leaf, MemoryDb, MSSMT};
+use mssmt::{CompactMSSMT, Hasher, Leaf, MemoryDb, MSSMT};
 use sha2::Sha256;
 
 pub fn generate_random_key() -> [u8; 32] {
@@ -47,8 +47,67 @@ fn bench_insertion(c: &mut Criterion) {
         })
     });
 
+    // Benchmark regular tree batch insertion
+    group.bench_function("Regular Tree Batch", |b| {
+        b.iter(|| {
+            let db = Box::new(MemoryDb::<32, Sha256>::new());
+            let mut tree = MSSMT::<32, Sha256, ()>::new(db);
+            let batch: Vec<_> = (0..100)
+                .map(|_| (generate_random_key(), generate_random_leaf()))
+                .collect();
+            tree.insert_batch(batch).unwrap();
+        })
+    });
+
+    // Benchmark compact tree batch insertion
+    group.bench_function("Compact Tree Batch", |b| {
+        b.iter(|| {
+            let db = Box::new(MemoryDb::<32, Sha256>::new());
+            let mut tree = CompactMSSMT::<32, Sha256, ()>::new(db);
+            let batch: Vec<_> = (0..100)
+                .map(|_| (generate_random_key(), generate_random_leaf()))
+                .collect();
+            tree.insert_batch(batch).unwrap();
+        })
+    });
+
+    // Benchmark compact tree bulk construction
+    group.bench_function("Compact Tree From Leaves", |b| {
+        b.iter(|| {
+            let db = Box::new(MemoryDb::<32, Sha256>::new());
+            let leaves: Vec<_> = (0..100)
+                .map(|_| (generate_random_key(), generate_random_leaf()))
+                .collect();
+            CompactMSSMT::<32, Sha256, ()>::from_sorted_leaves(db, leaves).unwrap();
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_node_hashing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Node Hashing");
+    let (left, right, sum) = (
+        generate_random_key(),
+        generate_random_key(),
+        42u64.to_be_bytes(),
+    );
+
+    // Baseline of the hashing of a branch before it was fed piece by piece
+    group.bench_function("Branch Concatenated", |b| {
+        b.iter(|| {
+            <Sha256 as Hasher<32>>::hash(
+                &[left.as_slice(), right.as_slice(), sum.as_slice()].concat(),
+            )
+        })
+    });
+
+    group.bench_function("Branch Piecewise", |b| {
+        b.iter(|| <Sha256 as Hasher<32>>::hash_branch(&left, &right, &sum))
+    });
+
     group.finish();
 }
 
-criterion_group!(benches, bench_insertion);
+criterion_group!(benches, bench_insertion, bench_node_hashing);
 criterion_main!(benches);
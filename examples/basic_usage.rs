@@ -6,7 +6,7 @@
 //! - Getting the root hash
 //! - Verifying merkle proofs
 
-use mssmt::{verify_merkle_proof, ComputedNode, Leaf, MemoryDb, Node, TreeError, MSSMT};
+use mssmt::{verify_merkle_proof, ComputedNode, Leaf, MemoryDb, Node, Proof, TreeError, MSSMT};
 use sha2::Sha256;
 
 fn main() {
@@ -31,19 +31,20 @@ fn main() {
 
     // Get and verify a merkle proof for leaf1
     let proof = tree.merkle_proof([1; 32]).unwrap();
-    println!("Merkle proof length: {}", proof.len());
+    println!("Merkle proof length: {}", proof.nodes().len());
 
     // Verify the proof
     // Not necessary but for the sake of the example we'll use computed nodes for the proof verification
     // because it's most likely what you'll do in production
-    let result: Result<(), TreeError<()>> = verify_merkle_proof(
-        [1; 32],
-        leaf1,
+    let computed = Proof::new::<()>(
         proof
+            .nodes()
             .iter()
             .map(|node| Node::Computed(ComputedNode::new(node.hash(), node.sum())))
             .collect(),
-        root.hash(),
-    );
+    )
+    .unwrap();
+    let result: Result<(), TreeError<()>> =
+        verify_merkle_proof([1; 32], leaf1, computed, root.hash());
     println!("Proof verification: {}", result.is_ok());
 }
@@ -36,7 +36,7 @@ fn main() {
 
     // Get and verify a merkle proof for leaf1
     let proof = compact_tree.merkle_proof([1; 32]).unwrap();
-    println!("Merkle proof length: {}", proof.len());
+    println!("Merkle proof length: {}", proof.nodes().len());
 
     // Verify the proof
     let result: Result<(), TreeError<()>> = verify_merkle_proof([1; 32], leaf1, proof, root.hash());
@@ -4,6 +4,10 @@
 //! - Creating a custom hasher implementation
 //! - Using it with the tree
 //! - Basic tree operations with the custom hasher
+//!
+//! The crate already implements `Hasher` for SHA-256 and SHA-512/256, and for Keccak-256,
+//! BLAKE3, Poseidon and Pedersen behind the `keccak`, `blake3`, `poseidon` and `pedersen`
+//! features.
 
 use mssmt::{Hasher, Leaf, MemoryDb, MSSMT};
 use sha2::{Digest, Sha256};
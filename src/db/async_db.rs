@@ -0,0 +1,219 @@
+//! Asynchronous database trait for backends that are accessed over the network or through
+//! asynchronous IO.
+
+use std::{any::Any, sync::Arc};
+
+use async_trait::async_trait;
+
+use crate::{
+    db::{Db, MemoryDb},
+    node::{Branch, CompactLeaf, Hasher, Leaf, Node, Sum},
+    ThreadSafe, TreeError,
+};
+
+/// Asynchronous store for the tree nodes, used by
+/// [`AsyncMSSMT`](crate::AsyncMSSMT) and [`AsyncCompactMSSMT`](crate::AsyncCompactMSSMT).
+///
+/// It has the same operations as [`Db`] and the same requirements: nodes are stored by hash
+/// with a reference count and updates made in a transaction are applied all at once.
+#[cfg_attr(feature = "multi-thread", async_trait)]
+#[cfg_attr(not(feature = "multi-thread"), async_trait(?Send))]
+pub trait AsyncDb<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum = u64>:
+    ThreadSafe
+{
+    /// The error type for database operations
+    type DbError;
+
+    /// Get the root node of the tree
+    async fn get_root_node(
+        &self,
+    ) -> Result<Option<Branch<HASH_SIZE, H, S>>, TreeError<Self::DbError>>;
+
+    #[allow(clippy::type_complexity)]
+    /// Get the children of a node at the given height and key
+    async fn get_children(
+        &self,
+        height: usize,
+        key: [u8; HASH_SIZE],
+    ) -> Result<(Node<HASH_SIZE, H, S>, Node<HASH_SIZE, H, S>), TreeError<Self::DbError>>;
+
+    /// Insert a leaf node
+    async fn insert_leaf(
+        &mut self,
+        leaf: Leaf<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>>;
+
+    /// Insert a branch node
+    async fn insert_branch(
+        &mut self,
+        branch: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>>;
+
+    /// Insert a compact leaf node
+    async fn insert_compact_leaf(
+        &mut self,
+        compact_leaf: CompactLeaf<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>>;
+
+    /// Get the empty tree for this database
+    fn empty_tree(&self) -> Arc<[Node<HASH_SIZE, H, S>]>;
+
+    /// Update the root node of the tree
+    async fn update_root(
+        &mut self,
+        root: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>>;
+
+    /// Delete a branch node
+    async fn delete_branch(
+        &mut self,
+        key: &[u8; HASH_SIZE],
+    ) -> Result<(), TreeError<Self::DbError>>;
+
+    /// Delete a leaf node
+    async fn delete_leaf(&mut self, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<Self::DbError>>;
+
+    /// Delete a compact leaf node
+    async fn delete_compact_leaf(
+        &mut self,
+        key: &[u8; HASH_SIZE],
+    ) -> Result<(), TreeError<Self::DbError>>;
+
+    /// Start a transaction, see [`Db::begin`]. The default implementation does nothing.
+    async fn begin(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        Ok(())
+    }
+
+    /// Apply the updates made since [`AsyncDb::begin`]
+    ///
+    /// The trees call [`AsyncDb::rollback`] when it fails, see [`Db::commit`](crate::Db::commit).
+    async fn commit(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        Ok(())
+    }
+
+    /// Discard the updates made since [`AsyncDb::begin`], root included
+    async fn rollback(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// A synchronous [`Db`] behind the [`AsyncDb`] trait.
+///
+/// Every call completes right away on the calling thread, so it's meant for databases that
+/// don't block for long like [`MemoryDb`], e.g. as a stand-in for an asynchronous database
+/// in tests.
+#[derive(Debug, Clone, Default)]
+pub struct BlockingDb<D>(D);
+
+/// An in-memory [`AsyncDb`].
+pub type AsyncMemoryDb<const HASH_SIZE: usize, H, S = u64> = BlockingDb<MemoryDb<HASH_SIZE, H, S>>;
+
+impl<D> BlockingDb<D> {
+    /// Wraps `db`.
+    pub fn new(db: D) -> Self {
+        Self(db)
+    }
+
+    /// Returns the inner database.
+    pub fn inner(&self) -> &D {
+        &self.0
+    }
+
+    /// Consumes the wrapper and returns the inner database.
+    pub fn into_inner(self) -> D {
+        self.0
+    }
+}
+
+#[cfg_attr(feature = "multi-thread", async_trait)]
+#[cfg_attr(not(feature = "multi-thread"), async_trait(?Send))]
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone + ThreadSafe, S: Sum, D>
+    AsyncDb<HASH_SIZE, H, S> for BlockingDb<D>
+where
+    D: Db<HASH_SIZE, H, S> + 'static,
+    D::DbError: ThreadSafe,
+{
+    type DbError = D::DbError;
+
+    async fn get_root_node(
+        &self,
+    ) -> Result<Option<Branch<HASH_SIZE, H, S>>, TreeError<Self::DbError>> {
+        Ok(self.0.get_root_node())
+    }
+
+    async fn get_children(
+        &self,
+        height: usize,
+        key: [u8; HASH_SIZE],
+    ) -> Result<(Node<HASH_SIZE, H, S>, Node<HASH_SIZE, H, S>), TreeError<Self::DbError>> {
+        self.0.get_children(height, key)
+    }
+
+    async fn insert_leaf(
+        &mut self,
+        leaf: Leaf<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.0.insert_leaf(leaf)
+    }
+
+    async fn insert_branch(
+        &mut self,
+        branch: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.0.insert_branch(branch)
+    }
+
+    async fn insert_compact_leaf(
+        &mut self,
+        compact_leaf: CompactLeaf<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.0.insert_compact_leaf(compact_leaf)
+    }
+
+    fn empty_tree(&self) -> Arc<[Node<HASH_SIZE, H, S>]> {
+        self.0.empty_tree()
+    }
+
+    async fn update_root(
+        &mut self,
+        root: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.0.update_root(root)
+    }
+
+    async fn delete_branch(
+        &mut self,
+        key: &[u8; HASH_SIZE],
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.0.delete_branch(key)
+    }
+
+    async fn delete_leaf(&mut self, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<Self::DbError>> {
+        self.0.delete_leaf(key)
+    }
+
+    async fn delete_compact_leaf(
+        &mut self,
+        key: &[u8; HASH_SIZE],
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.0.delete_compact_leaf(key)
+    }
+
+    async fn begin(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        self.0.begin()
+    }
+
+    async fn commit(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        self.0.commit()
+    }
+
+    async fn rollback(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        self.0.rollback()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,693 @@
+//! Database caching the nodes read from another one.
+
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use lru::LruCache;
+
+use crate::{
+    db::{Db, GcReport, NodeKind, Write},
+    node::{Branch, CompactLeaf, Hasher, Leaf, Node, Sum},
+    ThreadSafe, TreeError,
+};
+
+const POISONED: &str = "The cache lock is poisoned";
+
+/// When the writes to a [`CachedDb`] reach the inner database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Every write is applied to the inner database right away.
+    WriteThrough,
+    /// Writes are buffered and applied together when a read misses the cache, when as many
+    /// writes as the cache capacity are buffered, on [`Db::commit`] and on
+    /// [`CachedDb::flush`]. Outside of a transaction they are applied in one transaction of
+    /// the inner database.
+    WriteBack,
+}
+
+/// A database keeping the children of the most recently read branches of another [`Db`] in
+/// memory.
+///
+/// Every lookup reads the branches from the root down to the leaf and the top levels are the
+/// same for every key, so a small cache saves most of the reads of a slow database. The cache
+/// holds at most `capacity` branches and evicts the least recently used one. As the nodes are
+/// stored by hash, a cached branch only changes when its record or the one of a child is
+/// removed from the inner database: the entries using a deleted node are read again from the
+/// inner database once the delete is committed to it, and the whole cache is cleared when a
+/// transaction is rolled back.
+pub struct CachedDb<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, D, S: Sum = u64> {
+    state: Mutex<State<HASH_SIZE, H, D, S>>,
+    policy: WritePolicy,
+}
+
+/// Height and hash of a cached branch.
+type CacheKey<const HASH_SIZE: usize> = (usize, [u8; HASH_SIZE]);
+
+/// Children of a cached branch.
+type Children<const HASH_SIZE: usize, H, S> = (Node<HASH_SIZE, H, S>, Node<HASH_SIZE, H, S>);
+
+struct State<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, D, S: Sum> {
+    db: D,
+    /// Children of the branches by height and hash.
+    cache: LruCache<CacheKey<HASH_SIZE>, Children<HASH_SIZE, H, S>>,
+    /// Entries using each node, as the branch or one of its children.
+    users: HashMap<[u8; HASH_SIZE], HashSet<CacheKey<HASH_SIZE>>>,
+    /// Writes not applied to the inner database yet, oldest first.
+    pending: Vec<Write<HASH_SIZE, H, S>>,
+    /// Hashes of the nodes deleted since the cache was last checked against the inner
+    /// database, at the end of the last transaction.
+    deleted: HashSet<[u8; HASH_SIZE]>,
+    /// Whether a transaction is open.
+    in_tx: bool,
+    hits: u64,
+    misses: u64,
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, D: Db<HASH_SIZE, H, S>, S: Sum>
+    State<HASH_SIZE, H, D, S>
+{
+    /// Applies the pending writes to the inner database, in a transaction of their own
+    /// outside of one.
+    fn flush(&mut self) -> Result<(), TreeError<D::DbError>> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        if self.in_tx {
+            return self.apply();
+        }
+        self.db.begin()?;
+        let result = match self.apply() {
+            Ok(()) => self.db.commit(),
+            Err(e) => self.db.rollback().and(Err(e)),
+        };
+        self.refresh();
+        result
+    }
+
+    /// Reads the cached branches using a deleted node again from the inner database, as a
+    /// node is only removed with its last reference, and evicts the ones that aren't stored
+    /// anymore. Only the entries using a deleted node are read.
+    fn refresh(&mut self) {
+        let users = &self.users;
+        let stale: HashSet<_> = self
+            .deleted
+            .drain()
+            .filter_map(|hash| users.get(&hash))
+            .flatten()
+            .copied()
+            .collect();
+        for cache_key in stale {
+            match self.db.get_children(cache_key.0, cache_key.1) {
+                // A branch has the same children hashes whatever their kind, the entry keeps
+                // its users.
+                Ok(children) => {
+                    if let Some(cached) = self.cache.peek_mut(&cache_key) {
+                        *cached = children;
+                    }
+                }
+                Err(_) => {
+                    if let Some(children) = self.cache.pop(&cache_key) {
+                        self.unindex(cache_key, &children);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Caches the children of a branch, evicting the least recently used one if the cache is
+    /// full.
+    fn cache(&mut self, cache_key: CacheKey<HASH_SIZE>, children: Children<HASH_SIZE, H, S>) {
+        for hash in [cache_key.1, children.0.hash(), children.1.hash()] {
+            self.users.entry(hash).or_default().insert(cache_key);
+        }
+        if let Some((evicted, old)) = self.cache.push(cache_key, children) {
+            if evicted != cache_key {
+                self.unindex(evicted, &old);
+            }
+        }
+    }
+
+    /// Removes an entry no longer cached from the users of its nodes.
+    fn unindex(&mut self, cache_key: CacheKey<HASH_SIZE>, children: &Children<HASH_SIZE, H, S>) {
+        for hash in [cache_key.1, children.0.hash(), children.1.hash()] {
+            if let Some(users) = self.users.get_mut(&hash) {
+                users.remove(&cache_key);
+                if users.is_empty() {
+                    self.users.remove(&hash);
+                }
+            }
+        }
+    }
+
+    /// Empties the cache.
+    fn clear(&mut self) {
+        self.cache.clear();
+        self.users.clear();
+        self.deleted.clear();
+    }
+
+    /// Returns `true` if the branch `hash` or one of its `children` has been deleted since the
+    /// cache was last refreshed.
+    fn uses_deleted(
+        &self,
+        hash: &[u8; HASH_SIZE],
+        (left, right): &Children<HASH_SIZE, H, S>,
+    ) -> bool {
+        [*hash, left.hash(), right.hash()]
+            .iter()
+            .any(|hash| self.deleted.contains(hash))
+    }
+
+    fn apply(&mut self) -> Result<(), TreeError<D::DbError>> {
+        for write in std::mem::take(&mut self.pending) {
+            apply(&mut self.db, write)?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies a write to `db`.
+fn apply<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, D: Db<HASH_SIZE, H, S>, S: Sum>(
+    db: &mut D,
+    write: Write<HASH_SIZE, H, S>,
+) -> Result<(), TreeError<D::DbError>> {
+    match write {
+        Write::Leaf(leaf) => db.insert_leaf(leaf),
+        Write::Branch(branch) => db.insert_branch(branch),
+        Write::CompactLeaf(compact_leaf) => db.insert_compact_leaf(compact_leaf),
+        Write::DeleteLeaf(key) => db.delete_leaf(&key),
+        Write::DeleteBranch(key) => db.delete_branch(&key),
+        Write::DeleteCompactLeaf(key) => db.delete_compact_leaf(&key),
+        Write::Root(root) => db.update_root(root),
+        Write::NamespaceRoot(namespace, root) => db.update_namespace_root(&namespace, root),
+    }
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, D: Db<HASH_SIZE, H, S>, S: Sum>
+    CachedDb<HASH_SIZE, H, D, S>
+{
+    /// Caches the children of at most `capacity` branches of `db`.
+    pub fn new(db: D, capacity: NonZeroUsize, policy: WritePolicy) -> Self {
+        Self {
+            state: Mutex::new(State {
+                db,
+                cache: LruCache::new(capacity),
+                users: HashMap::new(),
+                pending: Vec::new(),
+                deleted: HashSet::new(),
+                in_tx: false,
+                hits: 0,
+                misses: 0,
+            }),
+            policy,
+        }
+    }
+
+    /// Returns the write policy.
+    pub fn policy(&self) -> WritePolicy {
+        self.policy
+    }
+
+    /// Returns the number of reads served by the cache.
+    pub fn hits(&self) -> u64 {
+        self.lock().hits
+    }
+
+    /// Returns the number of reads that went to the inner database.
+    pub fn misses(&self) -> u64 {
+        self.lock().misses
+    }
+
+    /// Resets the hit and miss counters.
+    pub fn reset_stats(&mut self) {
+        let mut state = self.lock();
+        state.hits = 0;
+        state.misses = 0;
+    }
+
+    /// Returns the number of cached branches.
+    pub fn len(&self) -> usize {
+        self.lock().cache.len()
+    }
+
+    /// Returns `true` if no branch is cached.
+    pub fn is_empty(&self) -> bool {
+        self.lock().cache.is_empty()
+    }
+
+    /// Applies the buffered writes to the inner database.
+    pub fn flush(&mut self) -> Result<(), TreeError<D::DbError>> {
+        self.lock().flush()
+    }
+
+    /// Runs `f` with the inner database. Buffered writes are not applied to it yet.
+    pub fn with<R>(&self, f: impl FnOnce(&D) -> R) -> R {
+        f(&self.lock().db)
+    }
+
+    /// Applies the buffered writes and returns the inner database.
+    pub fn into_inner(self) -> Result<D, TreeError<D::DbError>> {
+        let mut state = self.state.into_inner().expect(POISONED);
+        state.flush()?;
+        Ok(state.db)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<HASH_SIZE, H, D, S>> {
+        self.state.lock().expect(POISONED)
+    }
+
+    /// Applies `write` to the inner database, or buffers it with the write-back policy.
+    fn write(&mut self, write: Write<HASH_SIZE, H, S>) -> Result<(), TreeError<D::DbError>> {
+        let state = self.state.get_mut().expect(POISONED);
+        if let Write::DeleteLeaf(key) | Write::DeleteBranch(key) | Write::DeleteCompactLeaf(key) =
+            &write
+        {
+            state.deleted.insert(*key);
+        }
+        match self.policy {
+            WritePolicy::WriteThrough => {
+                let result = apply(&mut state.db, write);
+                // The cache is checked once per transaction, each write being one outside of
+                // them.
+                if !state.in_tx {
+                    state.refresh();
+                }
+                result
+            }
+            WritePolicy::WriteBack => {
+                state.pending.push(write);
+                if state.pending.len() >= state.cache.cap().get() {
+                    state.flush()?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<
+        const HASH_SIZE: usize,
+        H: Hasher<HASH_SIZE> + Clone + ThreadSafe,
+        D: Db<HASH_SIZE, H, S> + 'static,
+        S: Sum,
+    > Db<HASH_SIZE, H, S> for CachedDb<HASH_SIZE, H, D, S>
+{
+    type DbError = D::DbError;
+
+    fn get_root_node(&self) -> Option<Branch<HASH_SIZE, H, S>> {
+        let state = self.lock();
+        state
+            .pending
+            .iter()
+            .rev()
+            .find_map(|write| match write {
+                Write::Root(root) => Some(root.clone()),
+                _ => None,
+            })
+            .or_else(|| state.db.get_root_node())
+    }
+
+    fn get_children(
+        &self,
+        height: usize,
+        key: [u8; HASH_SIZE],
+    ) -> Result<(Node<HASH_SIZE, H, S>, Node<HASH_SIZE, H, S>), TreeError<Self::DbError>> {
+        let mut state = self.lock();
+        // The entries using a node deleted by a pending write are refreshed by the flush.
+        if let Some(children) = state.cache.get(&(height, key)).cloned() {
+            if !state.uses_deleted(&key, &children) {
+                state.hits += 1;
+                return Ok(children);
+            }
+        }
+        state.misses += 1;
+        state.flush()?;
+        let children = state.db.get_children(height, key)?;
+        state.cache((height, key), children.clone());
+        Ok(children)
+    }
+
+    fn insert_leaf(&mut self, leaf: Leaf<HASH_SIZE, H, S>) -> Result<(), TreeError<Self::DbError>> {
+        self.write(Write::Leaf(leaf))
+    }
+
+    fn insert_branch(
+        &mut self,
+        branch: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.write(Write::Branch(branch))
+    }
+
+    fn insert_compact_leaf(
+        &mut self,
+        compact_leaf: CompactLeaf<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.write(Write::CompactLeaf(compact_leaf))
+    }
+
+    fn empty_tree(&self) -> Arc<[Node<HASH_SIZE, H, S>]> {
+        self.lock().db.empty_tree()
+    }
+
+    fn update_root(
+        &mut self,
+        root: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.write(Write::Root(root))
+    }
+
+    fn delete_branch(&mut self, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<Self::DbError>> {
+        self.write(Write::DeleteBranch(*key))
+    }
+
+    fn delete_leaf(&mut self, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<Self::DbError>> {
+        self.write(Write::DeleteLeaf(*key))
+    }
+
+    fn delete_compact_leaf(
+        &mut self,
+        key: &[u8; HASH_SIZE],
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.write(Write::DeleteCompactLeaf(*key))
+    }
+
+    fn contains(
+        &self,
+        kind: NodeKind,
+        hash: &[u8; HASH_SIZE],
+    ) -> Result<bool, TreeError<Self::DbError>> {
+        let mut state = self.lock();
+        state.flush()?;
+        state.db.contains(kind, hash)
+    }
+
+    fn begin(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        let state = self.state.get_mut().expect(POISONED);
+        state.flush()?;
+        state.db.begin()?;
+        state.in_tx = true;
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        let state = self.state.get_mut().expect(POISONED);
+        if let Err(e) = state.flush() {
+            state.pending.clear();
+            state.clear();
+            state.in_tx = false;
+            state.db.rollback()?;
+            return Err(e);
+        }
+        state.in_tx = false;
+        let result = state.db.commit();
+        state.refresh();
+        result
+    }
+
+    fn rollback(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        let state = self.state.get_mut().expect(POISONED);
+        // The cache may hold branches inserted by the transaction.
+        state.pending.clear();
+        state.clear();
+        state.in_tx = false;
+        state.db.rollback()
+    }
+
+    fn version(&self) -> Option<u64> {
+        self.lock().db.version()
+    }
+
+    fn get_root_at(
+        &self,
+        version: u64,
+    ) -> Result<Option<Branch<HASH_SIZE, H, S>>, TreeError<Self::DbError>> {
+        self.lock().db.get_root_at(version)
+    }
+
+    fn prune_versions(&mut self, version: u64) -> Result<(), TreeError<Self::DbError>> {
+        let state = self.state.get_mut().expect(POISONED);
+        state.flush()?;
+        state.db.prune_versions(version)
+    }
+
+    fn get_namespace_root(
+        &self,
+        namespace: &[u8],
+    ) -> Result<Option<Branch<HASH_SIZE, H, S>>, TreeError<Self::DbError>> {
+        let state = self.lock();
+        let pending = state.pending.iter().rev().find_map(|write| match write {
+            Write::NamespaceRoot(ns, root) if ns == namespace => Some(root.clone()),
+            Write::Root(root) if namespace.is_empty() => Some(root.clone()),
+            _ => None,
+        });
+        match pending {
+            Some(root) => Ok(Some(root)),
+            None => state.db.get_namespace_root(namespace),
+        }
+    }
+
+    fn update_namespace_root(
+        &mut self,
+        namespace: &[u8],
+        root: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.write(Write::NamespaceRoot(namespace.to_vec(), root))
+    }
+
+    fn sweep(
+        &mut self,
+        roots: &[[u8; HASH_SIZE]],
+        dry_run: bool,
+    ) -> Result<GcReport, TreeError<Self::DbError>> {
+        let state = self.state.get_mut().expect(POISONED);
+        state.flush()?;
+        let report = state.db.sweep(roots, dry_run)?;
+        if !dry_run {
+            // The cache may hold branches that were deleted.
+            state.clear();
+        }
+        Ok(report)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use super::{CachedDb, WritePolicy};
+    use crate::{
+        tests::test_leaves, tree::verify_merkle_proof, Branch, CompactMSSMT, Db, Leaf, MemoryDb,
+        Node, TreeError, VersionedDb, MSSMT,
+    };
+    use sha2::Sha256;
+
+    type Cached = CachedDb<32, Sha256, MemoryDb<32, Sha256>>;
+
+    fn cached(capacity: usize, policy: WritePolicy) -> Cached {
+        CachedDb::new(
+            MemoryDb::new(),
+            NonZeroUsize::new(capacity).unwrap(),
+            policy,
+        )
+    }
+
+    fn cache(tree: &MSSMT<32, Sha256, ()>) -> &Cached {
+        tree.db().as_any().downcast_ref().unwrap()
+    }
+
+    #[test]
+    fn test_cached_db_matches_inner_db() {
+        for policy in [WritePolicy::WriteThrough, WritePolicy::WriteBack] {
+            let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(cached(64, policy)));
+            let mut expected = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+            let leaves = test_leaves::<Sha256>();
+            for (key, leaf) in leaves.clone() {
+                tree.insert(key, leaf.clone()).unwrap();
+                expected.insert(key, leaf).unwrap();
+            }
+            tree.delete([3; 32]).unwrap();
+            expected.delete([3; 32]).unwrap();
+            let root = expected.root().unwrap().hash();
+            assert_eq!(tree.root().unwrap().hash(), root);
+            assert!(tree.get([3; 32]).unwrap().is_none());
+            let key = leaves[3].0;
+            let leaf = tree.get(key).unwrap().unwrap();
+            let proof = tree.merkle_proof(key).unwrap();
+            verify_merkle_proof::<32, Sha256, (), _>(key, leaf, proof, root).unwrap();
+
+            // The updates reached the inner database.
+            let stored = cache(&tree).with(|db| (db.get_branches().len(), db.get_leaves().len()));
+            let expected_db: &MemoryDb<32, Sha256> = expected.db().as_any().downcast_ref().unwrap();
+            assert_eq!(
+                stored,
+                (
+                    expected_db.get_branches().len(),
+                    expected_db.get_leaves().len()
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_cached_db_hits() {
+        let mut tree = CompactMSSMT::<32, Sha256, ()>::new(Box::new(CachedDb::new(
+            MemoryDb::new(),
+            NonZeroUsize::new(8).unwrap(),
+            WritePolicy::WriteThrough,
+        )));
+        tree.insert_batch(test_leaves::<Sha256>()).unwrap();
+        let stats = |tree: &CompactMSSMT<32, Sha256, ()>| {
+            let db: &Cached = tree.db().as_any().downcast_ref().unwrap();
+            (db.hits(), db.misses(), db.len())
+        };
+        let (hits, misses, _) = stats(&tree);
+        tree.get([1; 32]).unwrap();
+        let (_, first_misses, len) = stats(&tree);
+        assert!(first_misses > misses);
+        assert!(len <= 8);
+        // The same lookup again is served by the cache.
+        tree.get([1; 32]).unwrap();
+        let (second_hits, second_misses, _) = stats(&tree);
+        assert_eq!(second_misses, first_misses);
+        assert!(second_hits > hits);
+    }
+
+    #[test]
+    fn test_cached_db_shared_subtree_deletes() {
+        // The keys only differ on the first bit of their path, so the two children of the
+        // root are the same subtree, stored once.
+        let (key_a, mut key_b) = ([0; 32], [0; 32]);
+        key_b[0] = 1;
+        let leaf = Leaf::<32, Sha256>::new(vec![1; 32], 1);
+        for policy in [WritePolicy::WriteThrough, WritePolicy::WriteBack] {
+            let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(cached(1024, policy)));
+            tree.insert(key_a, leaf.clone()).unwrap();
+            tree.insert(key_b, leaf.clone()).unwrap();
+            tree.get(key_a).unwrap();
+
+            // The subtree still used by `key_b` stays cached, only the new root is read.
+            tree.delete(key_a).unwrap();
+            let misses = cache(&tree).misses();
+            assert_eq!(tree.get(key_b).unwrap().unwrap().hash(), leaf.hash());
+            assert_eq!(cache(&tree).misses(), misses + 1);
+            assert!(tree.get(key_a).unwrap().is_none());
+
+            // Its branches go with their last reference.
+            tree.delete(key_b).unwrap();
+            assert!(tree.get(key_b).unwrap().is_none());
+            assert!(cache(&tree).with(|db| db.get_branches().is_empty()));
+        }
+    }
+
+    #[test]
+    fn test_cached_db_users_follow_evictions() {
+        for policy in [WritePolicy::WriteThrough, WritePolicy::WriteBack] {
+            let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(cached(8, policy)));
+            let leaves: Vec<_> = (1..=16u8)
+                .map(|i| ([i; 32], Leaf::new(vec![i; 32], i as u64)))
+                .collect();
+            for (key, leaf) in leaves.clone() {
+                tree.insert(key, leaf).unwrap();
+                tree.get(key).unwrap();
+            }
+            for (key, _) in &leaves[1..] {
+                tree.delete(*key).unwrap();
+            }
+            assert_eq!(tree.get(leaves[0].0).unwrap().unwrap().sum(), 1);
+            // Each of the entries left is used by its branch and two children at most.
+            let state = cache(&tree).lock();
+            let users: usize = state.users.values().map(|users| users.len()).sum();
+            assert!(state.cache.len() <= 8);
+            assert!(users <= 3 * state.cache.len());
+            assert!(state
+                .cache
+                .iter()
+                .all(|(cache_key, _)| state.users[&cache_key.1].contains(cache_key)));
+        }
+    }
+
+    #[test]
+    fn test_cached_db_write_back() {
+        let mut db = cached(4, WritePolicy::WriteBack);
+        let leaf = Leaf::<32, Sha256>::new(vec![1; 32], 1);
+        db.insert_leaf(leaf.clone()).unwrap();
+        db.insert_leaf(leaf.clone()).unwrap();
+        assert!(db.with(|db| db.get_leaves().is_empty()));
+        db.flush().unwrap();
+        assert_eq!(db.with(|db| db.get_leaves().len()), 1);
+
+        // The buffered writes are applied all at once or not at all.
+        for _ in 0..3 {
+            db.delete_leaf(&leaf.hash()).unwrap();
+        }
+        assert_eq!(db.flush().unwrap_err(), TreeError::NodeNotFound);
+        assert_eq!(db.with(|db| db.get_leaves().len()), 1);
+
+        // A full buffer is applied.
+        for i in 2..6 {
+            db.insert_leaf(Leaf::new(vec![i; 32], i as u64)).unwrap();
+        }
+        assert_eq!(db.with(|db| db.get_leaves().len()), 5);
+    }
+
+    #[test]
+    fn test_cached_db_gc() {
+        let mut db = cached(4, WritePolicy::WriteBack);
+        let leaf = Leaf::<32, Sha256>::new(vec![1; 32], 1);
+        let root = Branch::new(Node::Leaf(leaf.clone()), Node::new_empty_leaf());
+        db.insert_leaf(leaf.clone()).unwrap();
+        db.insert_branch(root.clone()).unwrap();
+        db.update_root(root.clone()).unwrap();
+        db.get_children(255, root.hash()).unwrap();
+        // The buffered writes are applied before looking for orphans.
+        db.insert_leaf(Leaf::new(vec![2; 32], 2)).unwrap();
+        assert_eq!(db.gc(false).unwrap().leaves, 1);
+        assert!(db.is_empty());
+        assert_eq!(db.with(|db| db.get_leaves().len()), 1);
+        let (left, _) = db.get_children(255, root.hash()).unwrap();
+        assert_eq!(left.hash(), leaf.hash());
+    }
+
+    #[test]
+    fn test_cached_db_rollback() {
+        let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(cached(64, WritePolicy::WriteBack)));
+        tree.insert([1; 32], Leaf::new(vec![1; 32], 1)).unwrap();
+        let root = tree.root().unwrap();
+        let mut db = cached(64, WritePolicy::WriteBack);
+        db.update_root(root.clone()).unwrap();
+
+        let leaf = Leaf::<32, Sha256>::new(vec![2; 32], 2);
+        db.begin().unwrap();
+        db.insert_leaf(leaf.clone()).unwrap();
+        db.update_root(Branch::new(Node::Leaf(leaf), Node::new_empty_leaf()))
+            .unwrap();
+        db.rollback().unwrap();
+        db.flush().unwrap();
+        assert!(db.with(|db| db.get_leaves().is_empty()));
+        assert_eq!(db.get_root_node().unwrap().hash(), root.hash());
+    }
+
+    #[test]
+    fn test_cached_db_versions() {
+        let db = CachedDb::new(
+            VersionedDb::<32, Sha256, MemoryDb<32, Sha256>>::new(MemoryDb::new()),
+            NonZeroUsize::new(64).unwrap(),
+            WritePolicy::WriteBack,
+        );
+        let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(db));
+        tree.insert([1; 32], Leaf::new(vec![1; 32], 1)).unwrap();
+        let root = tree.root().unwrap().hash();
+        tree.insert([2; 32], Leaf::new(vec![2; 32], 2)).unwrap();
+        assert_eq!(tree.version(), Some(2));
+        assert_eq!(tree.root_at(1).unwrap().hash(), root);
+        assert!(tree.get_at(1, [2; 32]).unwrap().is_none());
+    }
+}
@@ -0,0 +1,834 @@
+//! File-backed database built on the [`redb`] embedded key-value store.
+
+use std::{any::Any, fmt::Display, marker::PhantomData, path::Path, sync::Arc};
+
+use redb::{Database, Durability, ReadableTable, TableDefinition, WriteTransaction};
+
+use crate::{
+    db::{orphans, Db, GcReport, NodeKind},
+    node::{Branch, CompactLeaf, ComputedNode, Hasher, Leaf, Node, Sum},
+    tree::EmptyTree,
+    ThreadSafe, TreeError,
+};
+
+/// Branches by hash: `left hash || left sum || right hash || right sum`.
+const BRANCHES: TableDefinition<&[u8], &[u8]> = TableDefinition::new("branches");
+/// Leaves by hash: `sum || value`.
+const LEAVES: TableDefinition<&[u8], &[u8]> = TableDefinition::new("leaves");
+/// Compact leaves by hash: `key || leaf hash || leaf sum || leaf value`.
+const COMPACT_LEAVES: TableDefinition<&[u8], &[u8]> = TableDefinition::new("compact_leaves");
+/// Reference counts of the nodes referenced more than once, by `kind || hash`.
+const REFS: TableDefinition<&[u8], u64> = TableDefinition::new("refs");
+/// Tree metadata. The root is stored as `hash || branch`.
+const META: TableDefinition<&str, &[u8]> = TableDefinition::new("meta");
+const ROOT_KEY: &str = "root";
+/// Roots of the trees stored under a namespace other than the empty one, by namespace:
+/// `hash || branch`.
+const ROOTS: TableDefinition<&[u8], &[u8]> = TableDefinition::new("roots");
+
+/// Error of the [`FileDb`].
+#[derive(Debug)]
+pub enum FileDbError {
+    /// Error of the underlying store
+    Storage(Box<redb::Error>),
+    /// A stored record can't be decoded
+    Corrupted,
+}
+
+impl Display for FileDbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileDbError::Storage(e) => write!(f, "Storage error: {}", e),
+            FileDbError::Corrupted => write!(f, "Corrupted record"),
+        }
+    }
+}
+
+impl std::error::Error for FileDbError {}
+
+impl<E: Into<redb::Error>> From<E> for FileDbError {
+    fn from(e: E) -> Self {
+        FileDbError::Storage(Box::new(e.into()))
+    }
+}
+
+fn storage_error<E: Into<redb::Error>>(e: E) -> TreeError<FileDbError> {
+    TreeError::DbError(FileDbError::from(e))
+}
+
+/// A database storing the tree in a single file that survives process restarts.
+///
+/// A [`Db::begin`] transaction is a single write transaction of the store, committed
+/// durably. Outside of them, node updates are written without syncing the file and
+/// [`Db::update_root`] and [`Db::update_namespace_root`] commit them durably with the new
+/// root. After a crash the store is
+/// back to the last commit, so the stored root and nodes always match.
+pub struct FileDb<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum = u64> {
+    db: Database,
+    empty_tree: Arc<[Node<HASH_SIZE, H, S>]>,
+    root: Option<Branch<HASH_SIZE, H, S>>,
+    /// Open transaction and the root to restore if it's rolled back.
+    tx: Option<(WriteTransaction, Option<Branch<HASH_SIZE, H, S>>)>,
+    _phantom: PhantomData<H>,
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> FileDb<HASH_SIZE, H, S> {
+    /// Opens the database stored at `path`, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, FileDbError> {
+        let db = Database::create(path)?;
+        // Create the tables so they can always be opened for reading.
+        let tx = db.begin_write()?;
+        tx.open_table(BRANCHES)?;
+        tx.open_table(LEAVES)?;
+        tx.open_table(COMPACT_LEAVES)?;
+        tx.open_table(REFS)?;
+        tx.open_table(ROOTS)?;
+        let root = match tx.open_table(META)?.get(ROOT_KEY)? {
+            Some(record) => {
+                let (hash, record) = split_hash::<HASH_SIZE>(record.value())?;
+                Some(decode_branch(hash, record)?)
+            }
+            None => None,
+        };
+        tx.commit()?;
+        Ok(Self {
+            db,
+            empty_tree: EmptyTree::<HASH_SIZE, H, S>::empty_tree(),
+            root,
+            tx: None,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Runs `f` in the open transaction, or in a write transaction that is committed with the
+    /// given durability.
+    fn write(
+        &self,
+        durability: Durability,
+        f: impl FnOnce(&WriteTransaction) -> Result<(), TreeError<FileDbError>>,
+    ) -> Result<(), TreeError<FileDbError>> {
+        if let Some((tx, _)) = &self.tx {
+            return f(tx);
+        }
+        let mut tx = self.db.begin_write().map_err(storage_error)?;
+        tx.set_durability(durability);
+        f(&tx)?;
+        tx.commit().map_err(storage_error)
+    }
+
+    /// Inserts the record of a node, or adds a reference to it if it's already stored, in a
+    /// transaction that is made durable by the next root update. Fails with
+    /// [`TreeError::AmbiguousNode`] if a conflicting kind of node is stored under `key`.
+    fn insert(
+        &self,
+        kind: NodeKind,
+        key: [u8; HASH_SIZE],
+        record: &[u8],
+    ) -> Result<(), TreeError<FileDbError>> {
+        self.write(Durability::None, |tx| {
+            if let Some(other) = kind.conflicting() {
+                let other = tx.open_table(table(other)).map_err(storage_error)?;
+                if other.get(key.as_slice()).map_err(storage_error)?.is_some() {
+                    return Err(TreeError::AmbiguousNode);
+                }
+            }
+            let mut table = tx.open_table(table(kind)).map_err(storage_error)?;
+            if table.get(key.as_slice()).map_err(storage_error)?.is_none() {
+                table
+                    .insert(key.as_slice(), record)
+                    .map_err(storage_error)?;
+                return Ok(());
+            }
+            let mut refs = tx.open_table(REFS).map_err(storage_error)?;
+            let ref_key = ref_key(kind, &key);
+            let count = refs
+                .get(ref_key.as_slice())
+                .map_err(storage_error)?
+                .map_or(1, |count| count.value());
+            refs.insert(ref_key.as_slice(), count + 1)
+                .map_err(storage_error)?;
+            Ok(())
+        })
+    }
+
+    /// Removes a reference to the node `key` and its record if it was the last one, in a
+    /// transaction that is made durable by the next root update.
+    fn remove(&self, kind: NodeKind, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<FileDbError>> {
+        self.write(Durability::None, |tx| {
+            let mut table = tx.open_table(table(kind)).map_err(storage_error)?;
+            if table.get(key.as_slice()).map_err(storage_error)?.is_none() {
+                return Err(TreeError::NodeNotFound);
+            }
+            let mut refs = tx.open_table(REFS).map_err(storage_error)?;
+            let ref_key = ref_key(kind, key);
+            let count = refs
+                .get(ref_key.as_slice())
+                .map_err(storage_error)?
+                .map(|count| count.value());
+            match count {
+                Some(count) if count > 2 => refs.insert(ref_key.as_slice(), count - 1).map(|_| ()),
+                Some(_) => refs.remove(ref_key.as_slice()).map(|_| ()),
+                None => table.remove(key.as_slice()).map(|_| ()),
+            }
+            .map_err(storage_error)
+        })
+    }
+
+    /// Root of the tree stored under a non-empty `namespace` in the `roots` table.
+    fn stored<T: ReadableTable<&'static [u8], &'static [u8]>>(
+        hash: &[u8; HASH_SIZE],
+        table: T,
+    ) -> Result<bool, TreeError<FileDbError>> {
+        Ok(table.get(hash.as_slice()).map_err(storage_error)?.is_some())
+    }
+
+    fn namespace_root<T: ReadableTable<&'static [u8], &'static [u8]>>(
+        namespace: &[u8],
+        roots: T,
+    ) -> Result<Option<Branch<HASH_SIZE, H, S>>, TreeError<FileDbError>> {
+        let Some(record) = roots.get(namespace).map_err(storage_error)? else {
+            return Ok(None);
+        };
+        let (hash, record) = split_hash::<HASH_SIZE>(record.value()).map_err(TreeError::DbError)?;
+        Ok(Some(
+            decode_branch(hash, record).map_err(TreeError::DbError)?,
+        ))
+    }
+
+    /// Nodes of the branches, leaves and compact leaves `tables` that can't be reached from
+    /// the root, the namespace roots of the `roots_table` or `roots`.
+    #[allow(clippy::type_complexity)]
+    fn orphans<T: ReadableTable<&'static [u8], &'static [u8]>>(
+        &self,
+        roots: &[[u8; HASH_SIZE]],
+        tables: [T; 3],
+        roots_table: T,
+    ) -> Result<Vec<(NodeKind, [u8; HASH_SIZE])>, TreeError<FileDbError>> {
+        let mut stored = Vec::new();
+        for (kind, table) in NodeKind::ALL.into_iter().zip(&tables) {
+            for entry in table.iter().map_err(storage_error)? {
+                let (key, _) = entry.map_err(storage_error)?;
+                let (hash, _) = split_hash::<HASH_SIZE>(key.value()).map_err(TreeError::DbError)?;
+                stored.push((kind, hash));
+            }
+        }
+        let mut all_roots: Vec<_> = self.root.iter().map(Branch::hash).collect();
+        for entry in roots_table.iter().map_err(storage_error)? {
+            let (_, record) = entry.map_err(storage_error)?;
+            let (hash, _) = split_hash::<HASH_SIZE>(record.value()).map_err(TreeError::DbError)?;
+            all_roots.push(hash);
+        }
+        all_roots.extend_from_slice(roots);
+        orphans(stored, all_roots, |kind, hash| {
+            let Some(record) = tables[kind as usize]
+                .get(hash.as_slice())
+                .map_err(storage_error)?
+            else {
+                return Ok(Vec::new());
+            };
+            Ok(match kind {
+                NodeKind::Branch => {
+                    let branch: Branch<HASH_SIZE, H, S> =
+                        decode_branch(*hash, record.value()).map_err(TreeError::DbError)?;
+                    vec![branch.left().hash(), branch.right().hash()]
+                }
+                NodeKind::Leaf => Vec::new(),
+                NodeKind::CompactLeaf => {
+                    let compact_leaf: CompactLeaf<HASH_SIZE, H, S> =
+                        decode_compact_leaf(*hash, record.value()).map_err(TreeError::DbError)?;
+                    vec![compact_leaf.leaf().hash()]
+                }
+            })
+        })
+    }
+
+    /// Children of the branch `key` at `height`, read from the branches, leaves and compact
+    /// leaves tables. A branch or a leaf is used over the compact leaf standing for the same
+    /// subtree, and a hash held by both a branch and a leaf fails with
+    /// [`TreeError::AmbiguousNode`].
+    #[allow(clippy::type_complexity)]
+    fn children<T: ReadableTable<&'static [u8], &'static [u8]>>(
+        &self,
+        height: usize,
+        key: [u8; HASH_SIZE],
+        [branches, leaves, compact_leaves]: [T; 3],
+    ) -> Result<(Node<HASH_SIZE, H, S>, Node<HASH_SIZE, H, S>), TreeError<FileDbError>> {
+        let get_node = |height: usize, key: [u8; HASH_SIZE]| {
+            if key == self.empty_tree[height].hash() {
+                return Ok(self.empty_tree[height].clone());
+            }
+            let key_bytes = key.as_slice();
+            let branch = branches.get(key_bytes).map_err(storage_error)?;
+            let leaf = leaves.get(key_bytes).map_err(storage_error)?;
+            let node = match (branch, leaf) {
+                (Some(_), Some(_)) => return Err(TreeError::AmbiguousNode),
+                (Some(record), None) => {
+                    Node::Branch(decode_branch(key, record.value()).map_err(TreeError::DbError)?)
+                }
+                (None, Some(record)) => {
+                    Node::Leaf(decode_leaf(key, record.value()).map_err(TreeError::DbError)?)
+                }
+                (None, None) => match compact_leaves.get(key_bytes).map_err(storage_error)? {
+                    Some(record) => Node::Compact(
+                        decode_compact_leaf(key, record.value()).map_err(TreeError::DbError)?,
+                    ),
+                    None => return Err(TreeError::NodeNotFound),
+                },
+            };
+            Ok::<_, TreeError<FileDbError>>(node)
+        };
+        if let Node::Branch(branch) = get_node(height, key)? {
+            Ok((
+                get_node(height + 1, branch.left().hash())?,
+                get_node(height + 1, branch.right().hash())?,
+            ))
+        } else {
+            Err(TreeError::ExpectedBranch)
+        }
+    }
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone + ThreadSafe, S: Sum> Db<HASH_SIZE, H, S>
+    for FileDb<HASH_SIZE, H, S>
+{
+    type DbError = FileDbError;
+
+    fn get_root_node(&self) -> Option<Branch<HASH_SIZE, H, S>> {
+        self.root.clone()
+    }
+
+    fn get_children(
+        &self,
+        height: usize,
+        key: [u8; HASH_SIZE],
+    ) -> Result<(Node<HASH_SIZE, H, S>, Node<HASH_SIZE, H, S>), TreeError<Self::DbError>> {
+        // The updates of the open transaction are only visible through it.
+        if let Some((tx, _)) = &self.tx {
+            let open = |table| tx.open_table(table).map_err(storage_error);
+            return self.children(
+                height,
+                key,
+                [open(BRANCHES)?, open(LEAVES)?, open(COMPACT_LEAVES)?],
+            );
+        }
+        let tx = self.db.begin_read().map_err(storage_error)?;
+        let open = |table| tx.open_table(table).map_err(storage_error);
+        self.children(
+            height,
+            key,
+            [open(BRANCHES)?, open(LEAVES)?, open(COMPACT_LEAVES)?],
+        )
+    }
+
+    fn insert_leaf(&mut self, leaf: Leaf<HASH_SIZE, H, S>) -> Result<(), TreeError<Self::DbError>> {
+        let mut record = leaf.sum().to_be_bytes().as_ref().to_vec();
+        record.extend_from_slice(leaf.value());
+        self.insert(NodeKind::Leaf, leaf.hash(), &record)
+    }
+
+    fn insert_branch(
+        &mut self,
+        branch: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.insert(NodeKind::Branch, branch.hash(), &encode_branch(&branch))
+    }
+
+    fn insert_compact_leaf(
+        &mut self,
+        compact_leaf: CompactLeaf<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        let leaf = compact_leaf.leaf();
+        let mut record = compact_leaf.key().to_vec();
+        record.extend_from_slice(&leaf.hash());
+        record.extend_from_slice(leaf.sum().to_be_bytes().as_ref());
+        record.extend_from_slice(leaf.value());
+        self.insert(NodeKind::CompactLeaf, compact_leaf.hash(), &record)
+    }
+
+    fn empty_tree(&self) -> Arc<[Node<HASH_SIZE, H, S>]> {
+        self.empty_tree.clone()
+    }
+
+    fn update_root(
+        &mut self,
+        root: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        // The durable commit also persists all the node updates written since the last one.
+        self.write(Durability::Immediate, |tx| {
+            let mut meta = tx.open_table(META).map_err(storage_error)?;
+            let mut record = root.hash().to_vec();
+            record.extend_from_slice(&encode_branch(&root));
+            meta.insert(ROOT_KEY, record.as_slice())
+                .map_err(storage_error)?;
+            Ok(())
+        })?;
+        self.root = Some(root);
+        Ok(())
+    }
+
+    fn begin(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        debug_assert!(self.tx.is_none(), "Transactions don't nest");
+        let mut tx = self.db.begin_write().map_err(storage_error)?;
+        tx.set_durability(Durability::Immediate);
+        self.tx = Some((tx, self.root.clone()));
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        if let Some((tx, root)) = self.tx.take() {
+            if let Err(e) = tx.commit() {
+                self.root = root;
+                return Err(storage_error(e));
+            }
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        if let Some((tx, root)) = self.tx.take() {
+            self.root = root;
+            tx.abort().map_err(storage_error)?;
+        }
+        Ok(())
+    }
+
+    fn delete_branch(&mut self, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<Self::DbError>> {
+        self.remove(NodeKind::Branch, key)
+    }
+
+    fn delete_leaf(&mut self, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<Self::DbError>> {
+        self.remove(NodeKind::Leaf, key)
+    }
+
+    fn delete_compact_leaf(
+        &mut self,
+        key: &[u8; HASH_SIZE],
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.remove(NodeKind::CompactLeaf, key)
+    }
+
+    fn contains(
+        &self,
+        kind: NodeKind,
+        hash: &[u8; HASH_SIZE],
+    ) -> Result<bool, TreeError<Self::DbError>> {
+        if let Some((tx, _)) = &self.tx {
+            return Self::stored(hash, tx.open_table(table(kind)).map_err(storage_error)?);
+        }
+        let tx = self.db.begin_read().map_err(storage_error)?;
+        Self::stored(hash, tx.open_table(table(kind)).map_err(storage_error)?)
+    }
+
+    fn get_namespace_root(
+        &self,
+        namespace: &[u8],
+    ) -> Result<Option<Branch<HASH_SIZE, H, S>>, TreeError<Self::DbError>> {
+        if namespace.is_empty() {
+            return Ok(self.root.clone());
+        }
+        if let Some((tx, _)) = &self.tx {
+            return Self::namespace_root(namespace, tx.open_table(ROOTS).map_err(storage_error)?);
+        }
+        let tx = self.db.begin_read().map_err(storage_error)?;
+        Self::namespace_root(namespace, tx.open_table(ROOTS).map_err(storage_error)?)
+    }
+
+    fn update_namespace_root(
+        &mut self,
+        namespace: &[u8],
+        root: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        if namespace.is_empty() {
+            return self.update_root(root);
+        }
+        self.write(Durability::Immediate, |tx| {
+            let mut roots = tx.open_table(ROOTS).map_err(storage_error)?;
+            let mut record = root.hash().to_vec();
+            record.extend_from_slice(&encode_branch(&root));
+            roots
+                .insert(namespace, record.as_slice())
+                .map_err(storage_error)?;
+            Ok(())
+        })
+    }
+
+    /// Runs in the open transaction, or in a durable write transaction of its own.
+    fn sweep(
+        &mut self,
+        roots: &[[u8; HASH_SIZE]],
+        dry_run: bool,
+    ) -> Result<GcReport, TreeError<Self::DbError>> {
+        let mut orphans = Vec::new();
+        self.write(Durability::Immediate, |tx| {
+            let open = |table| tx.open_table(table).map_err(storage_error);
+            orphans = self.orphans(
+                roots,
+                [open(BRANCHES)?, open(LEAVES)?, open(COMPACT_LEAVES)?],
+                open(ROOTS)?,
+            )?;
+            if dry_run {
+                return Ok(());
+            }
+            let mut refs = tx.open_table(REFS).map_err(storage_error)?;
+            for (kind, hash) in &orphans {
+                open(table(*kind))?
+                    .remove(hash.as_slice())
+                    .map_err(storage_error)?;
+                refs.remove(ref_key(*kind, hash).as_slice())
+                    .map_err(storage_error)?;
+            }
+            Ok(())
+        })?;
+        Ok(GcReport::new(&orphans))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Table of the records of the nodes of `kind`.
+fn table(kind: NodeKind) -> TableDefinition<'static, &'static [u8], &'static [u8]> {
+    match kind {
+        NodeKind::Branch => BRANCHES,
+        NodeKind::Leaf => LEAVES,
+        NodeKind::CompactLeaf => COMPACT_LEAVES,
+    }
+}
+
+fn ref_key<const HASH_SIZE: usize>(kind: NodeKind, key: &[u8; HASH_SIZE]) -> Vec<u8> {
+    let mut ref_key = vec![kind as u8];
+    ref_key.extend_from_slice(key);
+    ref_key
+}
+
+fn encode_branch<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum>(
+    branch: &Branch<HASH_SIZE, H, S>,
+) -> Vec<u8> {
+    let mut record = Vec::with_capacity(2 * (HASH_SIZE + S::SIZE));
+    for child in [branch.left(), branch.right()] {
+        record.extend_from_slice(&child.hash());
+        record.extend_from_slice(child.sum().to_be_bytes().as_ref());
+    }
+    record
+}
+
+/// Decodes a branch. The children are [`Node::Computed`] nodes as only their hash and sum
+/// are stored.
+fn decode_branch<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum>(
+    hash: [u8; HASH_SIZE],
+    record: &[u8],
+) -> Result<Branch<HASH_SIZE, H, S>, FileDbError> {
+    let (left_hash, record) = split_hash::<HASH_SIZE>(record)?;
+    let (left_sum, record) = split_sum::<S>(record)?;
+    let (right_hash, record) = split_hash::<HASH_SIZE>(record)?;
+    let (right_sum, record) = split_sum::<S>(record)?;
+    if !record.is_empty() {
+        return Err(FileDbError::Corrupted);
+    }
+    let sum = left_sum
+        .checked_add(right_sum)
+        .ok_or(FileDbError::Corrupted)?;
+    // SAFETY: the hash is the key the branch was stored under.
+    Ok(unsafe {
+        Branch::new_with_hash(
+            Node::Computed(ComputedNode::new(left_hash, left_sum)),
+            Node::Computed(ComputedNode::new(right_hash, right_sum)),
+            hash,
+            sum,
+        )
+    })
+}
+
+fn decode_leaf<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum>(
+    hash: [u8; HASH_SIZE],
+    record: &[u8],
+) -> Result<Leaf<HASH_SIZE, H, S>, FileDbError> {
+    let (sum, value) = split_sum::<S>(record)?;
+    // SAFETY: the hash is the key the leaf was stored under.
+    Ok(unsafe { Leaf::new_with_hash(value.to_vec(), sum, hash) })
+}
+
+fn decode_compact_leaf<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum>(
+    hash: [u8; HASH_SIZE],
+    record: &[u8],
+) -> Result<CompactLeaf<HASH_SIZE, H, S>, FileDbError> {
+    let (key, record) = split_hash::<HASH_SIZE>(record)?;
+    let (leaf_hash, record) = split_hash::<HASH_SIZE>(record)?;
+    let leaf = decode_leaf(leaf_hash, record)?;
+    // SAFETY: the hash is the key the compact leaf was stored under.
+    Ok(unsafe { CompactLeaf::new_with_hash(hash, leaf, key) })
+}
+
+fn split_hash<const HASH_SIZE: usize>(
+    record: &[u8],
+) -> Result<([u8; HASH_SIZE], &[u8]), FileDbError> {
+    let (hash, rest) = record
+        .split_at_checked(HASH_SIZE)
+        .ok_or(FileDbError::Corrupted)?;
+    Ok((hash.try_into().expect("slice has HASH_SIZE bytes"), rest))
+}
+
+fn split_sum<S: Sum>(record: &[u8]) -> Result<(S, &[u8]), FileDbError> {
+    let (sum, rest) = record
+        .split_at_checked(S::SIZE)
+        .ok_or(FileDbError::Corrupted)?;
+    Ok((S::from_be_bytes(sum).ok_or(FileDbError::Corrupted)?, rest))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FileDb, LEAVES};
+    use crate::{
+        tests::test_leaves, tree::verify_merkle_proof, Branch, CompactLeaf, CompactMSSMT, Db,
+        GcReport, Leaf, MemoryDb, Node, SharedDb, TreeError, MSSMT,
+    };
+    use sha2::Sha256;
+
+    #[test]
+    fn test_file_db_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.redb");
+        let mut expected = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        let leaves = test_leaves::<Sha256>();
+        {
+            let db = FileDb::<32, Sha256>::open(&path).unwrap();
+            let mut tree = MSSMT::<32, Sha256, _>::new(Box::new(db));
+            for (key, leaf) in leaves.clone() {
+                tree.insert(key, leaf.clone()).unwrap();
+                expected.insert(key, leaf).unwrap();
+            }
+            tree.delete([3; 32]).unwrap();
+            expected.delete([3; 32]).unwrap();
+        }
+
+        let tree = MSSMT::<32, Sha256, _>::new(Box::new(FileDb::open(&path).unwrap()));
+        let root = tree.root().unwrap();
+        assert_eq!(root.hash(), expected.root().unwrap().hash());
+        assert_eq!(root.sum(), expected.root().unwrap().sum());
+        assert!(!tree.contains([3; 32]).unwrap());
+        let (key, expected_leaf) = leaves[3].clone();
+        let leaf = tree.get(key).unwrap().unwrap();
+        assert_eq!(leaf.value(), expected_leaf.value());
+        let proof = tree.merkle_proof(key).unwrap();
+        verify_merkle_proof::<32, Sha256, (), _>(key, leaf, proof, root.hash()).unwrap();
+        assert!(tree.verify_integrity().is_ok());
+    }
+
+    #[test]
+    fn test_file_db_compact_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.redb");
+        let mut expected = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        let leaves = test_leaves::<Sha256>();
+        {
+            let db = FileDb::<32, Sha256>::open(&path).unwrap();
+            let mut tree = CompactMSSMT::<32, Sha256, _>::new(Box::new(db));
+            tree.insert_batch(leaves.to_vec()).unwrap();
+            expected.insert_batch(leaves.to_vec()).unwrap();
+            tree.delete([2; 32]).unwrap();
+            expected.delete([2; 32]).unwrap();
+        }
+
+        let mut tree = CompactMSSMT::<32, Sha256, _>::new(Box::new(FileDb::open(&path).unwrap()));
+        assert_eq!(tree.root().unwrap().hash(), expected.root().unwrap().hash());
+        assert_eq!(tree.get(leaves[3].0).unwrap().unwrap().sum(), 4);
+        tree.insert([2; 32], Leaf::new(vec![2; 32], 2)).unwrap();
+        expected.insert([2; 32], Leaf::new(vec![2; 32], 2)).unwrap();
+        assert_eq!(tree.root().unwrap().hash(), expected.root().unwrap().hash());
+        assert!(tree.verify_integrity().is_ok());
+    }
+
+    #[test]
+    fn test_file_db_delete_missing_node() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = FileDb::<32, Sha256>::open(dir.path().join("tree.redb")).unwrap();
+        assert!(db.get_root_node().is_none());
+        assert!(matches!(
+            db.delete_branch(&[1; 32]),
+            Err(TreeError::NodeNotFound)
+        ));
+        assert!(matches!(
+            db.get_children(0, [1; 32]),
+            Err(TreeError::NodeNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_file_db_transaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.redb");
+        let leaf = Leaf::<32, Sha256>::new(vec![1; 32], 1);
+        let branch = Branch::new(Node::Leaf(leaf.clone()), Node::new_empty_leaf());
+        {
+            let mut db = FileDb::<32, Sha256>::open(&path).unwrap();
+            db.begin().unwrap();
+            db.insert_leaf(leaf.clone()).unwrap();
+            db.insert_branch(branch.clone()).unwrap();
+            db.update_root(branch.clone()).unwrap();
+            // The transaction sees its own updates.
+            let (left, _) = db.get_children(255, branch.hash()).unwrap();
+            assert_eq!(left.hash(), leaf.hash());
+            db.rollback().unwrap();
+            assert!(db.get_root_node().is_none());
+            assert!(matches!(
+                db.get_children(255, branch.hash()),
+                Err(TreeError::NodeNotFound)
+            ));
+
+            db.begin().unwrap();
+            db.insert_leaf(leaf.clone()).unwrap();
+            db.insert_branch(branch.clone()).unwrap();
+            db.update_root(branch.clone()).unwrap();
+            db.commit().unwrap();
+        }
+
+        let db = FileDb::<32, Sha256>::open(&path).unwrap();
+        assert_eq!(db.get_root_node().unwrap().hash(), branch.hash());
+        let (left, _) = db.get_children(255, branch.hash()).unwrap();
+        assert_eq!(left.hash(), leaf.hash());
+    }
+
+    #[test]
+    fn test_file_db_ref_counts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = FileDb::<32, Sha256>::open(dir.path().join("tree.redb")).unwrap();
+        let leaf = Leaf::<32, Sha256>::new(vec![1; 32], 1);
+        let branch = Branch::new(Node::Leaf(leaf.clone()), Node::new_empty_leaf());
+        db.insert_leaf(leaf).unwrap();
+        db.insert_branch(branch.clone()).unwrap();
+        db.insert_branch(branch.clone()).unwrap();
+        db.insert_branch(branch.clone()).unwrap();
+        for _ in 0..2 {
+            db.delete_branch(&branch.hash()).unwrap();
+            assert!(db.get_children(255, branch.hash()).is_ok());
+        }
+        db.delete_branch(&branch.hash()).unwrap();
+        assert!(matches!(
+            db.get_children(255, branch.hash()),
+            Err(TreeError::NodeNotFound)
+        ));
+        assert!(matches!(
+            db.delete_branch(&branch.hash()),
+            Err(TreeError::NodeNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_file_db_ambiguous_node() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = FileDb::<32, Sha256>::open(dir.path().join("tree.redb")).unwrap();
+        let child = Leaf::<32, Sha256>::new(vec![1; 32], 1);
+        let branch = Branch::new(Node::Leaf(child.clone()), Node::new_empty_leaf());
+        // A leaf colliding with the branch.
+        let leaf = unsafe { Leaf::<32, Sha256>::new_with_hash(vec![2; 32], 2, branch.hash()) };
+        db.insert_leaf(child.clone()).unwrap();
+        db.insert_branch(branch.clone()).unwrap();
+        assert!(matches!(
+            db.insert_leaf(leaf),
+            Err(TreeError::AmbiguousNode)
+        ));
+        assert!(db.get_children(255, branch.hash()).is_ok());
+
+        // A file holding both anyway.
+        let root = Branch::new(Node::Branch(branch.clone()), Node::new_empty_leaf());
+        db.insert_branch(root.clone()).unwrap();
+        let tx = db.db.begin_write().unwrap();
+        tx.open_table(LEAVES)
+            .unwrap()
+            .insert(branch.hash().as_slice(), [0; 8 + 32].as_slice())
+            .unwrap();
+        tx.commit().unwrap();
+        assert!(matches!(
+            db.get_children(254, root.hash()),
+            Err(TreeError::AmbiguousNode)
+        ));
+    }
+
+    #[test]
+    fn test_file_db_missing_child() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = FileDb::<32, Sha256>::open(dir.path().join("tree.redb")).unwrap();
+        let leaf = Leaf::<32, Sha256>::new(vec![1; 32], 1);
+        let branch = Branch::new(Node::Leaf(leaf.clone()), Node::new_empty_leaf());
+        db.insert_leaf(leaf.clone()).unwrap();
+        db.insert_branch(branch.clone()).unwrap();
+        let (left, right) = db.get_children(255, branch.hash()).unwrap();
+        assert_eq!(left.hash(), leaf.hash());
+        assert_eq!(right.hash(), db.empty_tree()[256].hash());
+        // A child that isn't stored isn't read as an empty subtree.
+        db.delete_leaf(&leaf.hash()).unwrap();
+        assert!(matches!(
+            db.get_children(255, branch.hash()),
+            Err(TreeError::NodeNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_file_db_gc() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.redb");
+        let mut db = FileDb::<32, Sha256>::open(&path).unwrap();
+        let leaf = Leaf::<32, Sha256>::new(vec![1; 32], 1);
+        let root = Branch::new(Node::Leaf(leaf.clone()), Node::new_empty_leaf());
+        db.insert_leaf(leaf.clone()).unwrap();
+        db.insert_branch(root.clone()).unwrap();
+        db.update_root(root.clone()).unwrap();
+        let namespaced_root = Branch::new(Node::new_empty_leaf(), Node::Leaf(leaf.clone()));
+        db.insert_leaf(leaf.clone()).unwrap();
+        db.insert_branch(namespaced_root.clone()).unwrap();
+        db.update_namespace_root(b"other", namespaced_root.clone())
+            .unwrap();
+        let orphan = Leaf::<32, Sha256>::new(vec![2; 32], 2);
+        db.insert_leaf(orphan.clone()).unwrap();
+        db.insert_leaf(orphan.clone()).unwrap();
+        db.insert_compact_leaf(CompactLeaf::new(1, [0; 32], orphan.clone()))
+            .unwrap();
+
+        let report = GcReport {
+            branches: 0,
+            leaves: 1,
+            compact_leaves: 1,
+        };
+        assert_eq!(db.gc(true).unwrap(), report);
+        assert_eq!(db.gc(false).unwrap(), report);
+        drop(db);
+
+        let mut db = FileDb::<32, Sha256>::open(&path).unwrap();
+        assert_eq!(db.gc(true).unwrap().total(), 0);
+        assert!(matches!(
+            db.delete_leaf(&orphan.hash()),
+            Err(TreeError::NodeNotFound)
+        ));
+        let (left, _) = db.get_children(255, root.hash()).unwrap();
+        assert_eq!(left.hash(), leaf.hash());
+        let (_, right) = db.get_children(255, namespaced_root.hash()).unwrap();
+        assert_eq!(right.hash(), leaf.hash());
+    }
+
+    #[test]
+    fn test_file_db_namespaces() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.redb");
+        let mut expected = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        {
+            let shared = SharedDb::new(FileDb::<32, Sha256>::open(&path).unwrap());
+            let mut btc = MSSMT::<32, Sha256, _>::new(Box::new(shared.namespace("btc").unwrap()));
+            let mut eth = MSSMT::<32, Sha256, _>::new(Box::new(shared.namespace("eth").unwrap()));
+            for (key, leaf) in test_leaves::<Sha256>() {
+                btc.insert(key, leaf.clone()).unwrap();
+                eth.insert(key, leaf.clone()).unwrap();
+                expected.insert(key, leaf).unwrap();
+            }
+            btc.delete([3; 32]).unwrap();
+        }
+
+        let shared = SharedDb::new(FileDb::<32, Sha256>::open(&path).unwrap());
+        assert!(shared.with(|db| db.get_root_node()).is_none());
+        let btc = MSSMT::<32, Sha256, _>::new(Box::new(shared.namespace("btc").unwrap()));
+        let eth = MSSMT::<32, Sha256, _>::new(Box::new(shared.namespace("eth").unwrap()));
+        assert_eq!(eth.root().unwrap().hash(), expected.root().unwrap().hash());
+        assert!(!btc.contains([3; 32]).unwrap());
+        assert_eq!(eth.get([3; 32]).unwrap().unwrap().sum(), 3);
+    }
+}
@@ -1,145 +1,428 @@
 use std::{any::Any, collections::HashMap, sync::Arc};
 
-use typenum::Unsigned;
-
 use crate::{
-    db::Db,
-    node::{Branch, CompactLeaf, Hasher, Leaf, Node},
-    tree::{EmptyTree, TreeSize},
+    db::{orphans, Db, GcReport, NodeKind},
+    node::{Branch, CompactLeaf, Hasher, Leaf, Node, Sum},
+    tree::EmptyTree,
     ThreadSafe, TreeError,
 };
 
 /// A simple in-memory database implementation for testing
+///
+/// Each hash maps to a single record holding the nodes stored under it, so a node of any kind
+/// is found with one lookup. A compact leaf has the hash of the subtree it stands for, so it
+/// can share its record with the equivalent branch or leaf.
+/// A branch and a leaf can't: storing one under the hash of the other fails with
+/// [`TreeError::AmbiguousNode`].
+#[derive(Debug, Clone)]
+pub struct MemoryDb<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum = u64> {
+    nodes: HashMap<[u8; HASH_SIZE], Record<HASH_SIZE, H, S>>,
+    /// Number of nodes of each kind, indexed by [`NodeKind`].
+    counts: [usize; 3],
+    empty_tree: Arc<[Node<HASH_SIZE, H, S>]>,
+    root: Option<Branch<HASH_SIZE, H, S>>,
+    /// Roots of the trees stored under a namespace other than the empty one.
+    namespaces: HashMap<Vec<u8>, Branch<HASH_SIZE, H, S>>,
+    /// Changes made by the open transaction, undone by a rollback.
+    journal: Option<Vec<Change<HASH_SIZE, H, S>>>,
+}
+
+/// Nodes stored under a hash, indexed by [`NodeKind`], with the number of references to them.
+type Record<const HASH_SIZE: usize, H, S> = [Option<(Node<HASH_SIZE, H, S>, usize)>; 3];
+
+/// The nodes of one kind stored in a [`MemoryDb`], borrowed from it.
+pub struct StoredNodes<'a, const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum, T> {
+    nodes: &'a HashMap<[u8; HASH_SIZE], Record<HASH_SIZE, H, S>>,
+    kind: NodeKind,
+    len: usize,
+    select: fn(&Node<HASH_SIZE, H, S>) -> Option<&T>,
+}
+
+impl<'a, const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum, T: 'a>
+    StoredNodes<'a, HASH_SIZE, H, S, T>
+{
+    fn new(
+        db: &'a MemoryDb<HASH_SIZE, H, S>,
+        kind: NodeKind,
+        select: fn(&Node<HASH_SIZE, H, S>) -> Option<&T>,
+    ) -> Self {
+        Self {
+            nodes: &db.nodes,
+            kind,
+            len: db.counts[kind as usize],
+            select,
+        }
+    }
+
+    /// Returns the node stored under `hash`, if any.
+    pub fn get(&self, hash: &[u8; HASH_SIZE]) -> Option<&'a T> {
+        let (node, _) = self.nodes.get(hash)?[self.kind as usize].as_ref()?;
+        (self.select)(node)
+    }
+
+    /// Returns `true` if a node is stored under `hash`.
+    pub fn contains_key(&self, hash: &[u8; HASH_SIZE]) -> bool {
+        self.get(hash).is_some()
+    }
+
+    /// Iterates over the nodes and their hashes, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a [u8; HASH_SIZE], &'a T)> + 'a {
+        let (kind, select) = (self.kind, self.select);
+        self.nodes.iter().filter_map(move |(hash, record)| {
+            let (node, _) = record[kind as usize].as_ref()?;
+            Some((hash, select(node)?))
+        })
+    }
+
+    /// Iterates over the hashes of the nodes.
+    pub fn keys(&self) -> impl Iterator<Item = &'a [u8; HASH_SIZE]> + 'a {
+        self.iter().map(|(hash, _)| hash)
+    }
+
+    /// Iterates over the nodes.
+    pub fn values(&self) -> impl Iterator<Item = &'a T> + 'a {
+        self.iter().map(|(_, node)| node)
+    }
+
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no node of this kind is stored.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// A change to the database, holding the value it replaced.
 #[derive(Debug, Clone)]
-pub struct MemoryDb<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> {
-    branches: HashMap<[u8; HASH_SIZE], Branch<HASH_SIZE, H>>,
-    leaves: HashMap<[u8; HASH_SIZE], Leaf<HASH_SIZE, H>>,
-    compact_leaves: HashMap<[u8; HASH_SIZE], CompactLeaf<HASH_SIZE, H>>,
-    empty_tree: Arc<[Node<HASH_SIZE, H>; TreeSize::USIZE]>,
-    root: Option<Branch<HASH_SIZE, H>>,
+enum Change<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> {
+    Node([u8; HASH_SIZE], Option<Record<HASH_SIZE, H, S>>),
+    Root(Option<Branch<HASH_SIZE, H, S>>),
+    NamespaceRoot(Vec<u8>, Option<Branch<HASH_SIZE, H, S>>),
 }
 
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> MemoryDb<HASH_SIZE, H> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> MemoryDb<HASH_SIZE, H, S> {
     pub fn new() -> Self {
         Self {
-            branches: HashMap::new(),
-            leaves: HashMap::new(),
-            compact_leaves: HashMap::new(),
-            empty_tree: EmptyTree::<HASH_SIZE, H>::empty_tree(),
+            nodes: HashMap::new(),
+            counts: [0; 3],
+            empty_tree: EmptyTree::<HASH_SIZE, H, S>::empty_tree(),
             root: None,
+            namespaces: HashMap::new(),
+            journal: None,
         }
     }
-    pub fn get_branches(&self) -> &HashMap<[u8; HASH_SIZE], Branch<HASH_SIZE, H>> {
-        &self.branches
+    /// Returns the stored branches by hash.
+    pub fn get_branches(&self) -> StoredNodes<'_, HASH_SIZE, H, S, Branch<HASH_SIZE, H, S>> {
+        StoredNodes::new(self, NodeKind::Branch, |node| match node {
+            Node::Branch(branch) => Some(branch),
+            _ => None,
+        })
+    }
+    /// Returns the stored leaves by hash.
+    pub fn get_leaves(&self) -> StoredNodes<'_, HASH_SIZE, H, S, Leaf<HASH_SIZE, H, S>> {
+        StoredNodes::new(self, NodeKind::Leaf, |node| match node {
+            Node::Leaf(leaf) => Some(leaf),
+            _ => None,
+        })
     }
-    pub fn get_leaves(&self) -> &HashMap<[u8; HASH_SIZE], Leaf<HASH_SIZE, H>> {
-        &self.leaves
+    /// Returns the stored compact leaves by hash.
+    pub fn get_compact_leaves(
+        &self,
+    ) -> StoredNodes<'_, HASH_SIZE, H, S, CompactLeaf<HASH_SIZE, H, S>> {
+        StoredNodes::new(self, NodeKind::CompactLeaf, |node| match node {
+            Node::Compact(compact_leaf) => Some(compact_leaf),
+            _ => None,
+        })
     }
-    pub fn get_compact_leaves(&self) -> &HashMap<[u8; HASH_SIZE], CompactLeaf<HASH_SIZE, H>> {
-        &self.compact_leaves
+
+    /// Records a change in the open transaction, if any.
+    fn record(&mut self, change: Change<HASH_SIZE, H, S>) {
+        if let Some(journal) = &mut self.journal {
+            journal.push(change);
+        }
     }
+
+    /// Stores a node, or adds a reference to it if it's already stored.
+    fn insert(&mut self, kind: NodeKind, node: Node<HASH_SIZE, H, S>) -> Result<(), TreeError<()>> {
+        let hash = node.hash();
+        let old = self.nodes.get(&hash).cloned();
+        let mut record = old.clone().unwrap_or_default();
+        if kind
+            .conflicting()
+            .is_some_and(|other| record[other as usize].is_some())
+        {
+            return Err(TreeError::AmbiguousNode);
+        }
+        let refs = record[kind as usize].as_ref().map_or(0, |(_, refs)| *refs);
+        record[kind as usize] = Some((node, refs + 1));
+        self.set(hash, Some(record));
+        self.record(Change::Node(hash, old));
+        Ok(())
+    }
+
+    /// Removes a reference to the node `hash` of `kind` and the node with the last one.
+    fn remove(&mut self, kind: NodeKind, hash: &[u8; HASH_SIZE]) -> Result<(), TreeError<()>> {
+        let Some(old) = self.nodes.get(hash).cloned() else {
+            return Err(TreeError::NodeNotFound);
+        };
+        let mut record = old.clone();
+        match &mut record[kind as usize] {
+            None => return Err(kind.expected()),
+            Some((_, refs)) if *refs > 1 => *refs -= 1,
+            slot => *slot = None,
+        }
+        self.replace(*hash, record, old);
+        Ok(())
+    }
+
+    /// Removes the node `hash` of `kind` whatever the number of references to it.
+    fn purge(&mut self, kind: NodeKind, hash: &[u8; HASH_SIZE]) {
+        if let Some(old) = self.nodes.get(hash).cloned() {
+            let mut record = old.clone();
+            record[kind as usize] = None;
+            self.replace(*hash, record, old);
+        }
+    }
+
+    /// Replaces the record `old` of `hash`, removing it if it holds no node anymore.
+    fn replace(
+        &mut self,
+        hash: [u8; HASH_SIZE],
+        record: Record<HASH_SIZE, H, S>,
+        old: Record<HASH_SIZE, H, S>,
+    ) {
+        let empty = record.iter().all(Option::is_none);
+        self.set(hash, (!empty).then_some(record));
+        self.record(Change::Node(hash, Some(old)));
+    }
+
+    /// Sets or removes the record of `hash`, counting the nodes it adds and removes.
+    fn set(&mut self, hash: [u8; HASH_SIZE], record: Option<Record<HASH_SIZE, H, S>>) {
+        let stored = |record: Option<&Record<HASH_SIZE, H, S>>, kind: NodeKind| {
+            usize::from(record.is_some_and(|record| record[kind as usize].is_some()))
+        };
+        for kind in NodeKind::ALL {
+            self.counts[kind as usize] += stored(record.as_ref(), kind);
+            self.counts[kind as usize] -= stored(self.nodes.get(&hash), kind);
+        }
+        match record {
+            Some(record) => self.nodes.insert(hash, record),
+            None => self.nodes.remove(&hash),
+        };
+    }
+}
+
+/// Restores the value replaced by a change in `map`.
+fn restore<K: Eq + std::hash::Hash, V>(map: &mut HashMap<K, V>, key: K, old: Option<V>) {
+    match old {
+        Some(value) => map.insert(key, value),
+        None => map.remove(&key),
+    };
 }
 
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Default for MemoryDb<HASH_SIZE, H> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Default
+    for MemoryDb<HASH_SIZE, H, S>
+{
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone + ThreadSafe> Db<HASH_SIZE, H>
-    for MemoryDb<HASH_SIZE, H>
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone + ThreadSafe, S: Sum> Db<HASH_SIZE, H, S>
+    for MemoryDb<HASH_SIZE, H, S>
 {
     type DbError = ();
 
-    fn get_root_node(&self) -> Option<Branch<HASH_SIZE, H>> {
+    fn get_root_node(&self) -> Option<Branch<HASH_SIZE, H, S>> {
         self.root.clone()
     }
 
+    /// Looks up the record of the branch, then the ones of its children that aren't empty
+    /// subtrees.
     fn get_children(
         &self,
         height: usize,
         key: [u8; HASH_SIZE],
-    ) -> Result<(Node<HASH_SIZE, H>, Node<HASH_SIZE, H>), TreeError<Self::DbError>> {
-        let get_node = |height: usize, key: [u8; HASH_SIZE]| {
-            if key == self.empty_tree()[height].hash() {
-                self.empty_tree()[height].clone()
-            } else if let Some(node) = self.branches.get(&key) {
-                Node::Branch(node.clone())
-            } else if let Some(leaf) = self.leaves.get(&key) {
-                Node::Leaf(leaf.clone())
-            } else if let Some(compact) = self.compact_leaves.get(&key) {
-                Node::Compact(compact.clone())
-            } else {
-                self.empty_tree()[height].clone()
+    ) -> Result<(Node<HASH_SIZE, H, S>, Node<HASH_SIZE, H, S>), TreeError<Self::DbError>> {
+        let branch = match self.nodes.get(&key) {
+            Some(record) => match &record[NodeKind::Branch as usize] {
+                Some((Node::Branch(branch), _)) => branch,
+                _ => return Err(TreeError::ExpectedBranch),
+            },
+            // Only the nodes that aren't stored are compared with the empty tree.
+            None if key == self.empty_tree[height].hash() => {
+                let empty = self.empty_tree[height + 1].clone();
+                return Ok((empty.clone(), empty));
             }
+            None => return Err(TreeError::NodeNotFound),
         };
-        let node = get_node(height, key);
-        if key != self.empty_tree()[height].hash()
-            && node.hash() == self.empty_tree()[height].hash()
-        {
-            return Err(TreeError::NodeNotFound);
-        }
-        if let Node::Branch(branch) = node {
-            Ok((
-                get_node(height + 1, branch.left().hash()),
-                get_node(height + 1, branch.right().hash()),
-            ))
-        } else {
-            Err(TreeError::ExpectedBranch)
-        }
+        let child = |node: &Node<HASH_SIZE, H, S>| {
+            let empty = &self.empty_tree[height + 1];
+            if node.hash() == empty.hash() {
+                return Ok(empty.clone());
+            }
+            let record = self
+                .nodes
+                .get(&node.hash())
+                .ok_or(TreeError::NodeNotFound)?;
+            // The node of the kind the branch holds, else a branch or a leaf is used over the
+            // compact leaf standing for the same subtree.
+            let stored = NodeKind::of(node)
+                .and_then(|kind| record[kind as usize].as_ref())
+                .or_else(|| record.iter().flatten().next())
+                .expect("Records hold at least one node");
+            Ok(stored.0.clone())
+        };
+        Ok((child(branch.left())?, child(branch.right())?))
     }
 
-    fn insert_leaf(&mut self, leaf: Leaf<HASH_SIZE, H>) -> Result<(), TreeError<Self::DbError>> {
-        self.leaves.insert(leaf.hash(), leaf);
-        Ok(())
+    fn insert_leaf(&mut self, leaf: Leaf<HASH_SIZE, H, S>) -> Result<(), TreeError<Self::DbError>> {
+        self.insert(NodeKind::Leaf, Node::Leaf(leaf))
     }
 
     fn insert_branch(
         &mut self,
-        branch: Branch<HASH_SIZE, H>,
+        branch: Branch<HASH_SIZE, H, S>,
     ) -> Result<(), TreeError<Self::DbError>> {
-        self.branches.insert(branch.hash(), branch);
-        Ok(())
+        self.insert(NodeKind::Branch, Node::Branch(branch))
     }
 
     fn insert_compact_leaf(
         &mut self,
-        compact_leaf: CompactLeaf<HASH_SIZE, H>,
+        compact_leaf: CompactLeaf<HASH_SIZE, H, S>,
     ) -> Result<(), TreeError<Self::DbError>> {
-        self.compact_leaves
-            .insert(compact_leaf.hash(), compact_leaf);
-        Ok(())
+        self.insert(NodeKind::CompactLeaf, Node::Compact(compact_leaf))
     }
 
-    fn empty_tree(&self) -> Arc<[Node<HASH_SIZE, H>; TreeSize::USIZE]> {
+    fn empty_tree(&self) -> Arc<[Node<HASH_SIZE, H, S>]> {
         self.empty_tree.clone()
     }
 
-    fn update_root(&mut self, root: Branch<HASH_SIZE, H>) -> Result<(), TreeError<Self::DbError>> {
-        self.root = Some(root);
+    fn update_root(
+        &mut self,
+        root: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        let old = self.root.replace(root);
+        self.record(Change::Root(old));
         Ok(())
     }
 
     fn delete_branch(&mut self, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<Self::DbError>> {
-        self.branches.remove(key).ok_or(TreeError::NodeNotFound)?;
-        Ok(())
+        self.remove(NodeKind::Branch, key)
     }
 
     fn delete_leaf(&mut self, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<Self::DbError>> {
-        self.leaves.remove(key).ok_or(TreeError::NodeNotFound)?;
-        Ok(())
+        self.remove(NodeKind::Leaf, key)
     }
 
     fn delete_compact_leaf(
         &mut self,
         key: &[u8; HASH_SIZE],
     ) -> Result<(), TreeError<Self::DbError>> {
-        self.compact_leaves
-            .remove(key)
-            .ok_or(TreeError::NodeNotFound)?;
+        self.remove(NodeKind::CompactLeaf, key)
+    }
+
+    fn contains(
+        &self,
+        kind: NodeKind,
+        hash: &[u8; HASH_SIZE],
+    ) -> Result<bool, TreeError<Self::DbError>> {
+        Ok(self
+            .nodes
+            .get(hash)
+            .is_some_and(|record| record[kind as usize].is_some()))
+    }
+
+    fn begin(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        debug_assert!(self.journal.is_none(), "Transactions don't nest");
+        self.journal = Some(Vec::new());
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        self.journal = None;
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        // Undo the changes from the last one so each key gets its value from before the
+        // transaction back.
+        for change in self.journal.take().unwrap_or_default().into_iter().rev() {
+            match change {
+                Change::Node(key, old) => self.set(key, old),
+                Change::Root(old) => self.root = old,
+                Change::NamespaceRoot(namespace, old) => {
+                    restore(&mut self.namespaces, namespace, old)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get_namespace_root(
+        &self,
+        namespace: &[u8],
+    ) -> Result<Option<Branch<HASH_SIZE, H, S>>, TreeError<Self::DbError>> {
+        if namespace.is_empty() {
+            return Ok(self.root.clone());
+        }
+        Ok(self.namespaces.get(namespace).cloned())
+    }
+
+    fn update_namespace_root(
+        &mut self,
+        namespace: &[u8],
+        root: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        if namespace.is_empty() {
+            return self.update_root(root);
+        }
+        let old = self.namespaces.insert(namespace.to_vec(), root);
+        self.record(Change::NamespaceRoot(namespace.to_vec(), old));
         Ok(())
     }
 
+    fn sweep(
+        &mut self,
+        roots: &[[u8; HASH_SIZE]],
+        dry_run: bool,
+    ) -> Result<GcReport, TreeError<Self::DbError>> {
+        let stored = self
+            .nodes
+            .iter()
+            .flat_map(|(hash, record)| {
+                NodeKind::ALL
+                    .into_iter()
+                    .zip(record)
+                    .filter_map(move |(kind, slot)| slot.as_ref().map(|_| (kind, *hash)))
+            })
+            .collect();
+        let roots = self
+            .root
+            .iter()
+            .chain(self.namespaces.values())
+            .map(Branch::hash)
+            .chain(roots.iter().copied());
+        let orphans = orphans(stored, roots, |kind, hash| {
+            Ok(match &self.nodes[hash][kind as usize] {
+                Some((Node::Branch(branch), _)) => {
+                    vec![branch.left().hash(), branch.right().hash()]
+                }
+                Some((Node::Compact(compact_leaf), _)) => vec![compact_leaf.leaf().hash()],
+                _ => Vec::new(),
+            })
+        })?;
+        if !dry_run {
+            for (kind, hash) in &orphans {
+                self.purge(*kind, hash);
+            }
+        }
+        Ok(GcReport::new(&orphans))
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -148,7 +431,10 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone + ThreadSafe> Db<HASH_
 #[cfg(test)]
 mod test {
     use super::Db;
-    use crate::{Branch, Leaf, MemoryDb, Node, TreeError};
+    use crate::{
+        tests::test_leaves, Branch, CompactLeaf, CompactMSSMT, ComputedNode, GcReport, Leaf,
+        MemoryDb, Node, TreeError,
+    };
     use hex_literal::hex;
     use sha2::Sha256;
 
@@ -209,6 +495,97 @@ mod test {
         assert_eq!(db.get_branches().len(), 1);
     }
 
+    #[test]
+    fn test_memory_db_rollback() {
+        let mut db = MemoryDb::<32, Sha256>::new();
+        let leaf = Leaf::<32, Sha256>::new(vec![1, 2, 3], 1);
+        let branch = Branch::new(Node::new_empty_leaf(), Node::new_empty_leaf());
+        db.insert_leaf(leaf.clone()).unwrap();
+
+        db.begin().unwrap();
+        db.delete_leaf(&leaf.hash()).unwrap();
+        db.insert_leaf(leaf.clone()).unwrap();
+        db.insert_branch(branch.clone()).unwrap();
+        db.update_root(branch.clone()).unwrap();
+        db.rollback().unwrap();
+        assert_eq!(db.get_leaves().len(), 1);
+        assert!(db.get_branches().is_empty());
+        assert!(db.get_root_node().is_none());
+
+        db.begin().unwrap();
+        db.insert_branch(branch.clone()).unwrap();
+        db.update_root(branch.clone()).unwrap();
+        db.commit().unwrap();
+        // Nothing is left to undo after a commit.
+        db.rollback().unwrap();
+        assert_eq!(db.get_branches().len(), 1);
+        assert_eq!(db.get_root_node().unwrap().hash(), branch.hash());
+    }
+
+    #[test]
+    fn test_memory_db_len() {
+        let assert_len = |db: &MemoryDb<32, Sha256>| {
+            assert_eq!(db.get_branches().len(), db.get_branches().iter().count());
+            assert_eq!(db.get_leaves().len(), db.get_leaves().iter().count());
+            assert_eq!(
+                db.get_compact_leaves().len(),
+                db.get_compact_leaves().iter().count()
+            );
+        };
+        let mut tree = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        tree.insert_batch(test_leaves::<Sha256>()).unwrap();
+        let mut db = tree
+            .db()
+            .as_any()
+            .downcast_ref::<MemoryDb<32, Sha256>>()
+            .unwrap()
+            .clone();
+        assert_len(&db);
+        assert_eq!(db.get_leaves().len(), 4);
+        assert_eq!(db.get_compact_leaves().len(), 4);
+
+        let leaf = Leaf::<32, Sha256>::new(vec![1, 2, 3], 1);
+        db.begin().unwrap();
+        db.insert_leaf(leaf.clone()).unwrap();
+        db.insert_leaf(leaf.clone()).unwrap();
+        assert_eq!(db.get_leaves().len(), 5);
+        db.delete_leaf(&leaf.hash()).unwrap();
+        assert_eq!(db.get_leaves().len(), 5);
+        db.rollback().unwrap();
+        assert_len(&db);
+        assert_eq!(db.get_leaves().len(), 4);
+
+        db.update_root(Branch::new(Node::new_empty_leaf(), Node::new_empty_leaf()))
+            .unwrap();
+        db.gc(false).unwrap();
+        assert_len(&db);
+        assert!(db.get_compact_leaves().is_empty());
+    }
+
+    #[test]
+    fn test_memory_db_ref_counts() {
+        let mut db = MemoryDb::<32, Sha256>::new();
+        let leaf = Leaf::<32, Sha256>::new(vec![1, 2, 3], 1);
+        db.insert_leaf(leaf.clone()).unwrap();
+        db.insert_leaf(leaf.clone()).unwrap();
+
+        db.begin().unwrap();
+        db.delete_leaf(&leaf.hash()).unwrap();
+        db.delete_leaf(&leaf.hash()).unwrap();
+        assert!(db.get_leaves().is_empty());
+        db.rollback().unwrap();
+
+        // Both references are back.
+        db.delete_leaf(&leaf.hash()).unwrap();
+        assert_eq!(db.get_leaves().len(), 1);
+        db.delete_leaf(&leaf.hash()).unwrap();
+        assert!(db.get_leaves().is_empty());
+        assert_eq!(
+            db.delete_leaf(&leaf.hash()).unwrap_err(),
+            TreeError::NodeNotFound
+        );
+    }
+
     #[test]
     fn test_memory_db_get_children_leaf() {
         let mut db = MemoryDb::<32, Sha256>::new();
@@ -219,4 +596,125 @@ mod test {
             TreeError::ExpectedBranch
         );
     }
+
+    #[test]
+    fn test_memory_db_get_children_missing() {
+        let mut db = MemoryDb::<32, Sha256>::new();
+        let leaf = Leaf::<32, Sha256>::new(vec![1, 2, 3], 1);
+        let branch = Branch::new(Node::Leaf(leaf.clone()), Node::new_empty_leaf());
+        db.insert_leaf(leaf.clone()).unwrap();
+        db.insert_branch(branch.clone()).unwrap();
+        assert_eq!(
+            db.get_children(255, branch.hash()).unwrap().0.hash(),
+            leaf.hash()
+        );
+        // The branch holds the leaf, but its record is gone.
+        db.delete_leaf(&leaf.hash()).unwrap();
+        assert_eq!(
+            db.get_children(255, branch.hash()).unwrap_err(),
+            TreeError::NodeNotFound
+        );
+
+        // A child only known by hash, never stored.
+        let computed = ComputedNode::new(leaf.hash(), 1);
+        let branch = Branch::new(Node::Computed(computed), Node::new_empty_leaf());
+        db.insert_branch(branch.clone()).unwrap();
+        assert_eq!(
+            db.get_children(255, branch.hash()).unwrap_err(),
+            TreeError::NodeNotFound
+        );
+    }
+
+    #[test]
+    fn test_memory_db_ambiguous_node() {
+        let mut db = MemoryDb::<32, Sha256>::new();
+        let branch = Branch::new(Node::new_leaf(vec![4, 5, 6], 2), Node::new_empty_leaf());
+        // A leaf colliding with the branch.
+        let leaf = unsafe { Leaf::<32, Sha256>::new_with_hash(vec![1, 2, 3], 1, branch.hash()) };
+        db.insert_branch(branch.clone()).unwrap();
+        assert_eq!(
+            db.insert_leaf(leaf.clone()).unwrap_err(),
+            TreeError::AmbiguousNode
+        );
+        assert_eq!(
+            db.delete_leaf(&branch.hash()).unwrap_err(),
+            TreeError::ExpectedLeaf
+        );
+
+        // A compact leaf standing for the branch shares its hash.
+        let compact_leaf =
+            unsafe { CompactLeaf::new_with_hash(branch.hash(), leaf.clone(), [0; 32]) };
+        db.insert_compact_leaf(compact_leaf).unwrap();
+        // The children a branch only knows by hash are looked up.
+        let child = ComputedNode::new(branch.hash(), branch.sum());
+        let root = Branch::new(Node::Computed(child), Node::new_empty_leaf());
+        db.insert_branch(root.clone()).unwrap();
+        let (left, _) = db.get_children(255, root.hash()).unwrap();
+        assert!(matches!(left, Node::Branch(_)));
+
+        db.delete_branch(&branch.hash()).unwrap();
+        let (left, _) = db.get_children(255, root.hash()).unwrap();
+        assert!(matches!(left, Node::Compact(_)));
+        assert_eq!(
+            db.get_children(1, branch.hash()).unwrap_err(),
+            TreeError::ExpectedBranch
+        );
+        db.insert_leaf(leaf).unwrap();
+        assert_eq!(
+            db.insert_branch(branch).unwrap_err(),
+            TreeError::AmbiguousNode
+        );
+    }
+
+    #[test]
+    fn test_memory_db_gc() {
+        let mut db = MemoryDb::<32, Sha256>::new();
+        let leaf = Leaf::<32, Sha256>::new(vec![1, 2, 3], 1);
+        let root = Branch::new(Node::Leaf(leaf.clone()), Node::new_empty_leaf());
+        db.insert_leaf(leaf.clone()).unwrap();
+        db.insert_branch(root.clone()).unwrap();
+        db.update_root(root.clone()).unwrap();
+        let namespaced_leaf = Leaf::<32, Sha256>::new(vec![4, 5, 6], 2);
+        let namespaced_root =
+            Branch::new(Node::new_empty_leaf(), Node::Leaf(namespaced_leaf.clone()));
+        db.insert_leaf(namespaced_leaf).unwrap();
+        db.insert_branch(namespaced_root.clone()).unwrap();
+        db.update_namespace_root(b"other", namespaced_root).unwrap();
+        // Nodes no root uses.
+        let orphan = Leaf::<32, Sha256>::new(vec![7, 8, 9], 3);
+        db.insert_leaf(orphan.clone()).unwrap();
+        db.insert_leaf(orphan.clone()).unwrap();
+        db.insert_branch(Branch::new(
+            Node::Leaf(orphan.clone()),
+            Node::new_empty_leaf(),
+        ))
+        .unwrap();
+        db.insert_compact_leaf(CompactLeaf::new(1, [0; 32], leaf.clone()))
+            .unwrap();
+
+        let report = GcReport {
+            branches: 1,
+            leaves: 1,
+            compact_leaves: 1,
+        };
+        assert_eq!(db.gc(true).unwrap(), report);
+        assert_eq!(db.get_leaves().len(), 3);
+
+        db.begin().unwrap();
+        assert_eq!(db.gc(false).unwrap(), report);
+        db.rollback().unwrap();
+        assert_eq!(db.gc(true).unwrap(), report);
+
+        // Orphans are removed whatever their number of references.
+        assert_eq!(db.gc(false).unwrap(), report);
+        assert_eq!(db.gc(true).unwrap().total(), 0);
+        assert_eq!(db.get_leaves().len(), 2);
+        assert_eq!(db.get_branches().len(), 2);
+        assert!(db.get_compact_leaves().is_empty());
+        assert!(!db.get_leaves().contains_key(&orphan.hash()));
+        assert_eq!(
+            db.get_children(255, root.hash()).unwrap().0.hash(),
+            leaf.hash()
+        );
+    }
 }
@@ -1,15 +1,31 @@
 //! Database trait and implementations for the Merkle Sum Sparse Merkle Tree
 
+#[cfg(feature = "async")]
+mod async_db;
+mod cached;
+#[cfg(feature = "file-db")]
+mod file;
 mod memory;
+mod shared;
+mod versioned;
 
+#[cfg(feature = "async")]
+pub use async_db::*;
+pub use cached::*;
+#[cfg(feature = "file-db")]
+pub use file::*;
 pub use memory::*;
+pub use shared::*;
+pub use versioned::*;
 
-use std::{any::Any, sync::Arc};
-use typenum::Unsigned;
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use crate::{
-    node::{Branch, CompactLeaf, Hasher, Leaf, Node},
-    tree::TreeSize,
+    node::{Branch, CompactLeaf, Hasher, Leaf, Node, Sum},
     TreeError,
 };
 
@@ -24,16 +40,142 @@ pub trait ThreadSafe {}
 #[cfg(not(feature = "multi-thread"))]
 impl<T> ThreadSafe for T {}
 
+/// Kind of a stored node, as each kind is stored and deleted separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeKind {
+    /// A [`Branch`]
+    Branch,
+    /// A [`Leaf`]
+    Leaf,
+    /// A [`CompactLeaf`]
+    CompactLeaf,
+}
+
+impl NodeKind {
+    pub(crate) const ALL: [NodeKind; 3] = [NodeKind::Branch, NodeKind::Leaf, NodeKind::CompactLeaf];
+
+    /// Kind of the nodes that can't be stored under the hash of a node of this kind. A compact
+    /// leaf has the hash of the branch or leaf it stands for, so it conflicts with neither.
+    pub(crate) fn conflicting(self) -> Option<NodeKind> {
+        match self {
+            NodeKind::Branch => Some(NodeKind::Leaf),
+            NodeKind::Leaf => Some(NodeKind::Branch),
+            NodeKind::CompactLeaf => None,
+        }
+    }
+
+    /// Kind of `node`, `None` for a node only known by hash.
+    pub(crate) fn of<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum>(
+        node: &Node<HASH_SIZE, H, S>,
+    ) -> Option<NodeKind> {
+        match node {
+            Node::Branch(_) => Some(NodeKind::Branch),
+            Node::Leaf(_) => Some(NodeKind::Leaf),
+            Node::Compact(_) => Some(NodeKind::CompactLeaf),
+            Node::Computed(_) => None,
+        }
+    }
+
+    /// Error of an operation expecting a node of this kind.
+    pub(crate) fn expected<E>(self) -> TreeError<E> {
+        match self {
+            NodeKind::Branch => TreeError::ExpectedBranch,
+            NodeKind::Leaf => TreeError::ExpectedLeaf,
+            NodeKind::CompactLeaf => TreeError::ExpectedCompactLeaf,
+        }
+    }
+}
+
+/// Number of nodes by kind found by [`Db::gc`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// Number of branches
+    pub branches: usize,
+    /// Number of leaves
+    pub leaves: usize,
+    /// Number of compact leaves
+    pub compact_leaves: usize,
+}
+
+impl GcReport {
+    /// Counts the nodes of `orphans` by kind.
+    pub(crate) fn new<const HASH_SIZE: usize>(orphans: &[(NodeKind, [u8; HASH_SIZE])]) -> Self {
+        let mut report = Self::default();
+        for (kind, _) in orphans {
+            match kind {
+                NodeKind::Branch => report.branches += 1,
+                NodeKind::Leaf => report.leaves += 1,
+                NodeKind::CompactLeaf => report.compact_leaves += 1,
+            }
+        }
+        report
+    }
+
+    /// Returns the number of nodes of all kinds.
+    pub fn total(&self) -> usize {
+        self.branches + self.leaves + self.compact_leaves
+    }
+}
+
+/// Returns the nodes of `stored` that can't be reached from `roots`.
+///
+/// `pointers` returns the hashes a stored node points to: the children of a branch and the
+/// leaf of a compact leaf. Nodes are reached by hash, so all the kinds of node stored under a
+/// reachable hash are kept.
+#[allow(clippy::type_complexity)]
+pub(crate) fn orphans<const HASH_SIZE: usize, E>(
+    stored: Vec<(NodeKind, [u8; HASH_SIZE])>,
+    roots: impl IntoIterator<Item = [u8; HASH_SIZE]>,
+    mut pointers: impl FnMut(NodeKind, &[u8; HASH_SIZE]) -> Result<Vec<[u8; HASH_SIZE]>, TreeError<E>>,
+) -> Result<Vec<(NodeKind, [u8; HASH_SIZE])>, TreeError<E>> {
+    let mut kinds: HashMap<[u8; HASH_SIZE], Vec<NodeKind>> = HashMap::new();
+    for (kind, hash) in &stored {
+        kinds.entry(*hash).or_default().push(*kind);
+    }
+    let mut reachable = HashSet::new();
+    let mut stack: Vec<_> = roots.into_iter().collect();
+    while let Some(hash) = stack.pop() {
+        if !reachable.insert(hash) {
+            continue;
+        }
+        for kind in kinds.get(&hash).into_iter().flatten() {
+            stack.extend(pointers(*kind, &hash)?);
+        }
+    }
+    Ok(stored
+        .into_iter()
+        .filter(|(_, hash)| !reachable.contains(hash))
+        .collect())
+}
+
+/// A write to a [`Db`], buffered to be applied later.
+pub(crate) enum Write<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> {
+    Leaf(Leaf<HASH_SIZE, H, S>),
+    Branch(Branch<HASH_SIZE, H, S>),
+    CompactLeaf(CompactLeaf<HASH_SIZE, H, S>),
+    DeleteLeaf([u8; HASH_SIZE]),
+    DeleteBranch([u8; HASH_SIZE]),
+    DeleteCompactLeaf([u8; HASH_SIZE]),
+    Root(Branch<HASH_SIZE, H, S>),
+    NamespaceRoot(Vec<u8>, Branch<HASH_SIZE, H, S>),
+}
+
 /// Store for the tree nodes
 ///
+/// Nodes are stored by hash, so identical subtrees at different places of a tree share
+/// their nodes. Implementations count the references to each node: a node is inserted once
+/// for each place it's used at and only removed when it has been deleted as many times.
+///
 /// This trait must be implemented by any storage backend used with the tree.
 /// It provides the basic operations needed to store and retrieve nodes.
-pub trait Db<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone>: ThreadSafe {
+pub trait Db<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum = u64>:
+    ThreadSafe
+{
     /// The error type for database operations
     type DbError;
 
     /// Get the root node of the tree
-    fn get_root_node(&self) -> Option<Branch<HASH_SIZE, H>>;
+    fn get_root_node(&self) -> Option<Branch<HASH_SIZE, H, S>>;
 
     #[allow(clippy::type_complexity)]
     /// Get the children of a node at the given height and key
@@ -41,28 +183,31 @@ pub trait Db<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone>: ThreadSafe {
         &self,
         height: usize,
         key: [u8; HASH_SIZE],
-    ) -> Result<(Node<HASH_SIZE, H>, Node<HASH_SIZE, H>), TreeError<Self::DbError>>;
+    ) -> Result<(Node<HASH_SIZE, H, S>, Node<HASH_SIZE, H, S>), TreeError<Self::DbError>>;
 
     /// Insert a leaf node
-    fn insert_leaf(&mut self, leaf: Leaf<HASH_SIZE, H>) -> Result<(), TreeError<Self::DbError>>;
+    fn insert_leaf(&mut self, leaf: Leaf<HASH_SIZE, H, S>) -> Result<(), TreeError<Self::DbError>>;
 
     /// Insert a branch node
     fn insert_branch(
         &mut self,
-        branch: Branch<HASH_SIZE, H>,
+        branch: Branch<HASH_SIZE, H, S>,
     ) -> Result<(), TreeError<Self::DbError>>;
 
     /// Insert a compact leaf node
     fn insert_compact_leaf(
         &mut self,
-        compact_leaf: CompactLeaf<HASH_SIZE, H>,
+        compact_leaf: CompactLeaf<HASH_SIZE, H, S>,
     ) -> Result<(), TreeError<Self::DbError>>;
 
     /// Get the empty tree for this database
-    fn empty_tree(&self) -> Arc<[Node<HASH_SIZE, H>; TreeSize::USIZE]>;
+    fn empty_tree(&self) -> Arc<[Node<HASH_SIZE, H, S>]>;
 
     /// Update the root node of the tree
-    fn update_root(&mut self, root: Branch<HASH_SIZE, H>) -> Result<(), TreeError<Self::DbError>>;
+    fn update_root(
+        &mut self,
+        root: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>>;
 
     /// Delete a branch node
     fn delete_branch(&mut self, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<Self::DbError>>;
@@ -76,5 +221,110 @@ pub trait Db<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone>: ThreadSafe {
         key: &[u8; HASH_SIZE],
     ) -> Result<(), TreeError<Self::DbError>>;
 
+    /// Check that a node of `kind` is stored under `hash`, for the audits to find the missing
+    /// nodes.
+    ///
+    /// The default implementation can't tell and returns `true`, so the audits only find the
+    /// missing branches, through [`Db::get_children`].
+    fn contains(
+        &self,
+        _kind: NodeKind,
+        _hash: &[u8; HASH_SIZE],
+    ) -> Result<bool, TreeError<Self::DbError>> {
+        Ok(true)
+    }
+
+    /// Start a transaction. The updates made until [`Db::commit`] or [`Db::rollback`] are
+    /// applied all at once or not at all. Transactions don't nest.
+    ///
+    /// The trees run each insert, delete and batch in a transaction. The default
+    /// implementation does nothing, so a backend without transactions applies the updates
+    /// one by one and keeps the ones made before an error.
+    fn begin(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        Ok(())
+    }
+
+    /// Apply the updates made since [`Db::begin`]
+    ///
+    /// The trees call [`Db::rollback`] when it fails, so a backend that applied part of the
+    /// updates before failing must be able to undo them there.
+    fn commit(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        Ok(())
+    }
+
+    /// Discard the updates made since [`Db::begin`], root included
+    fn rollback(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        Ok(())
+    }
+
+    /// Get the version of the current root, for databases that keep the previous versions
+    /// of the tree like [`VersionedDb`]. The default implementation keeps no versions.
+    fn version(&self) -> Option<u64> {
+        None
+    }
+
+    /// Get the root node of the tree at `version`, `None` being the empty tree
+    fn get_root_at(
+        &self,
+        _version: u64,
+    ) -> Result<Option<Branch<HASH_SIZE, H, S>>, TreeError<Self::DbError>> {
+        Err(TreeError::VersionNotFound)
+    }
+
+    /// Forget the versions before `version`, deleting the nodes that only they use
+    fn prune_versions(&mut self, _version: u64) -> Result<(), TreeError<Self::DbError>> {
+        Ok(())
+    }
+
+    /// Get the root node of the tree stored under `namespace`, so that several trees can
+    /// share the nodes of one database. The empty namespace is the tree of
+    /// [`Db::get_root_node`].
+    ///
+    /// The default implementation only has the empty namespace and returns
+    /// [`TreeError::NamespacesNotSupported`] for the others.
+    fn get_namespace_root(
+        &self,
+        namespace: &[u8],
+    ) -> Result<Option<Branch<HASH_SIZE, H, S>>, TreeError<Self::DbError>> {
+        if namespace.is_empty() {
+            Ok(self.get_root_node())
+        } else {
+            Err(TreeError::NamespacesNotSupported)
+        }
+    }
+
+    /// Update the root node of the tree stored under `namespace`
+    fn update_namespace_root(
+        &mut self,
+        namespace: &[u8],
+        root: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        if namespace.is_empty() {
+            self.update_root(root)
+        } else {
+            Err(TreeError::NamespacesNotSupported)
+        }
+    }
+
+    /// Delete the nodes that can't be reached from the roots of the trees stored in the
+    /// database, the roots of the versions it retains or `roots`, and count them by kind.
+    /// With `dry_run` the nodes are only counted.
+    ///
+    /// The default implementation can't list the nodes and returns
+    /// [`TreeError::GcNotSupported`].
+    fn sweep(
+        &mut self,
+        _roots: &[[u8; HASH_SIZE]],
+        _dry_run: bool,
+    ) -> Result<GcReport, TreeError<Self::DbError>> {
+        Err(TreeError::GcNotSupported)
+    }
+
+    /// Delete the nodes no tree uses anymore, see [`Db::sweep`]. They are left by the updates
+    /// that failed without a transaction and by the nodes inserted more times than deleted.
+    fn gc(&mut self, dry_run: bool) -> Result<GcReport, TreeError<Self::DbError>> {
+        self.sweep(&[], dry_run)
+    }
+
     fn as_any(&self) -> &dyn Any;
 }
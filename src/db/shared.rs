@@ -0,0 +1,600 @@
+//! Database shared by several trees stored under their own namespaces.
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+};
+
+use crate::{
+    db::{Db, GcReport, NodeKind},
+    node::{Branch, CompactLeaf, Hasher, Leaf, Node, Sum},
+    ThreadSafe, TreeError,
+};
+
+const POISONED: &str = "The shared database lock is poisoned";
+
+/// A database shared by several trees, each one having its own root under a namespace.
+///
+/// Each [`SharedDb::namespace`] handle is a [`Db`] for one [`MSSMT`](crate::MSSMT) or
+/// [`CompactMSSMT`](crate::CompactMSSMT). The roots are stored with
+/// [`Db::update_namespace_root`] and the nodes are shared by all the trees: as they are
+/// stored by hash with a reference count, the subtrees the trees have in common are stored
+/// once.
+///
+/// A tree update holds the database for the whole of its transaction, the other trees wait
+/// for it to end before reading or updating the database.
+pub struct SharedDb<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, D, S: Sum = u64> {
+    shared: Arc<Shared<HASH_SIZE, H, D, S>>,
+}
+
+/// Handle storing one tree in a [`SharedDb`].
+///
+/// Dropping a handle with a transaction open rolls it back.
+pub struct NamespaceDb<
+    const HASH_SIZE: usize,
+    H: Hasher<HASH_SIZE> + Clone,
+    D: Db<HASH_SIZE, H, S>,
+    S: Sum = u64,
+> {
+    shared: Arc<Shared<HASH_SIZE, H, D, S>>,
+    namespace: Vec<u8>,
+    id: usize,
+}
+
+struct Shared<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, D, S: Sum> {
+    state: Mutex<State<HASH_SIZE, H, D, S>>,
+    /// Notified when a transaction ends.
+    tx_ended: Condvar,
+}
+
+struct State<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, D, S: Sum> {
+    db: D,
+    /// Roots of the namespaces that were opened, `None` being the empty tree.
+    roots: HashMap<Vec<u8>, Option<Branch<HASH_SIZE, H, S>>>,
+    /// Handle with an open transaction and the root of its namespace before it.
+    tx: Option<(usize, Option<Branch<HASH_SIZE, H, S>>)>,
+    next_id: usize,
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, D, S: Sum> Shared<HASH_SIZE, H, D, S> {
+    /// Locks the state once no handle other than `id` has a transaction open.
+    fn lock(&self, id: Option<usize>) -> MutexGuard<'_, State<HASH_SIZE, H, D, S>> {
+        let mut state = self.state.lock().expect(POISONED);
+        while state
+            .tx
+            .as_ref()
+            .is_some_and(|(owner, _)| Some(*owner) != id)
+        {
+            state = self.tx_ended.wait(state).expect(POISONED);
+        }
+        state
+    }
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, D: Db<HASH_SIZE, H, S>, S: Sum>
+    SharedDb<HASH_SIZE, H, D, S>
+{
+    /// Shares `db` between trees.
+    pub fn new(db: D) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    db,
+                    roots: HashMap::new(),
+                    tx: None,
+                    next_id: 0,
+                }),
+                tx_ended: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Returns a handle to the tree stored under `namespace`, loading its root from the
+    /// database. The empty namespace is the tree of [`Db::get_root_node`].
+    ///
+    /// Handles to the same namespace update the same tree.
+    pub fn namespace(
+        &self,
+        namespace: impl Into<Vec<u8>>,
+    ) -> Result<NamespaceDb<HASH_SIZE, H, D, S>, TreeError<D::DbError>> {
+        let namespace = namespace.into();
+        let mut state = self.shared.lock(None);
+        if !state.roots.contains_key(&namespace) {
+            let root = state.db.get_namespace_root(&namespace)?;
+            state.roots.insert(namespace.clone(), root);
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        Ok(NamespaceDb {
+            shared: self.shared.clone(),
+            namespace,
+            id,
+        })
+    }
+
+    /// Runs `f` with the shared database once no transaction is open.
+    pub fn with<R>(&self, f: impl FnOnce(&D) -> R) -> R {
+        f(&self.shared.lock(None).db)
+    }
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, D, S: Sum> Clone
+    for SharedDb<HASH_SIZE, H, D, S>
+{
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, D: Db<HASH_SIZE, H, S>, S: Sum>
+    NamespaceDb<HASH_SIZE, H, D, S>
+{
+    /// Returns the namespace of the tree.
+    pub fn namespace(&self) -> &[u8] {
+        &self.namespace
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<HASH_SIZE, H, D, S>> {
+        self.shared.lock(Some(self.id))
+    }
+
+    /// Ends the transaction of this handle, restoring the root of the namespace if `restore`.
+    fn end_tx(&self, state: &mut State<HASH_SIZE, H, D, S>, restore: bool) {
+        if let Some((_, root)) = state.tx.take() {
+            if restore {
+                state.roots.insert(self.namespace.clone(), root);
+            }
+            self.shared.tx_ended.notify_all();
+        }
+    }
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, D: Db<HASH_SIZE, H, S>, S: Sum> Drop
+    for NamespaceDb<HASH_SIZE, H, D, S>
+{
+    /// Rolls back the transaction left open, e.g. by a panic, so the other handles don't
+    /// wait for it forever.
+    fn drop(&mut self) {
+        // The other handles already fail on a poisoned lock.
+        let Ok(mut state) = self.shared.state.lock() else {
+            return;
+        };
+        if state
+            .tx
+            .as_ref()
+            .is_some_and(|(owner, _)| *owner == self.id)
+        {
+            // The error can't be returned, the nodes a failed rollback leaves are orphans
+            // for `Db::gc`.
+            let _ = state.db.rollback();
+            self.end_tx(&mut state, true);
+        }
+    }
+}
+
+impl<
+        const HASH_SIZE: usize,
+        H: Hasher<HASH_SIZE> + Clone + ThreadSafe,
+        D: Db<HASH_SIZE, H, S> + 'static,
+        S: Sum,
+    > Db<HASH_SIZE, H, S> for NamespaceDb<HASH_SIZE, H, D, S>
+{
+    type DbError = D::DbError;
+
+    fn get_root_node(&self) -> Option<Branch<HASH_SIZE, H, S>> {
+        self.lock().roots[&self.namespace].clone()
+    }
+
+    fn get_children(
+        &self,
+        height: usize,
+        key: [u8; HASH_SIZE],
+    ) -> Result<(Node<HASH_SIZE, H, S>, Node<HASH_SIZE, H, S>), TreeError<Self::DbError>> {
+        self.lock().db.get_children(height, key)
+    }
+
+    fn insert_leaf(&mut self, leaf: Leaf<HASH_SIZE, H, S>) -> Result<(), TreeError<Self::DbError>> {
+        self.lock().db.insert_leaf(leaf)
+    }
+
+    fn insert_branch(
+        &mut self,
+        branch: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.lock().db.insert_branch(branch)
+    }
+
+    fn insert_compact_leaf(
+        &mut self,
+        compact_leaf: CompactLeaf<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.lock().db.insert_compact_leaf(compact_leaf)
+    }
+
+    fn empty_tree(&self) -> Arc<[Node<HASH_SIZE, H, S>]> {
+        self.lock().db.empty_tree()
+    }
+
+    fn update_root(
+        &mut self,
+        root: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        let mut state = self.lock();
+        state
+            .db
+            .update_namespace_root(&self.namespace, root.clone())?;
+        state.roots.insert(self.namespace.clone(), Some(root));
+        Ok(())
+    }
+
+    fn delete_branch(&mut self, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<Self::DbError>> {
+        self.lock().db.delete_branch(key)
+    }
+
+    fn delete_leaf(&mut self, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<Self::DbError>> {
+        self.lock().db.delete_leaf(key)
+    }
+
+    fn delete_compact_leaf(
+        &mut self,
+        key: &[u8; HASH_SIZE],
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.lock().db.delete_compact_leaf(key)
+    }
+
+    fn contains(
+        &self,
+        kind: NodeKind,
+        hash: &[u8; HASH_SIZE],
+    ) -> Result<bool, TreeError<Self::DbError>> {
+        self.lock().db.contains(kind, hash)
+    }
+
+    fn begin(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        let mut state = self.lock();
+        state.db.begin()?;
+        let root = state.roots[&self.namespace].clone();
+        state.tx = Some((self.id, root));
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        let mut state = self.lock();
+        let result = state.db.commit();
+        self.end_tx(&mut state, result.is_err());
+        result
+    }
+
+    fn rollback(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        let mut state = self.lock();
+        let result = state.db.rollback();
+        self.end_tx(&mut state, true);
+        result
+    }
+
+    /// The nodes of the trees of all the namespaces are kept.
+    fn sweep(
+        &mut self,
+        roots: &[[u8; HASH_SIZE]],
+        dry_run: bool,
+    ) -> Result<GcReport, TreeError<Self::DbError>> {
+        self.lock().db.sweep(roots, dry_run)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{any::Any, sync::Arc};
+
+    use super::{NamespaceDb, SharedDb};
+    use crate::{
+        tests::test_leaves, tree::verify_merkle_proof, Branch, CompactLeaf, CompactMSSMT, Db, Leaf,
+        MemoryDb, Node, ThreadSafe, TreeError, MSSMT,
+    };
+    use sha2::Sha256;
+
+    type Shared = SharedDb<32, Sha256, MemoryDb<32, Sha256>>;
+
+    /// Number of branches and leaves stored in the [`MemoryDb`].
+    fn node_count(db: &MemoryDb<32, Sha256>) -> (usize, usize) {
+        (db.get_branches().len(), db.get_leaves().len())
+    }
+
+    #[test]
+    fn test_shared_db_namespaces() {
+        let shared = Shared::new(MemoryDb::new());
+        let mut btc = MSSMT::<32, Sha256, ()>::new(Box::new(shared.namespace("btc").unwrap()));
+        let mut eth = MSSMT::<32, Sha256, ()>::new(Box::new(shared.namespace("eth").unwrap()));
+        let mut expected = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        let leaves = test_leaves::<Sha256>();
+        for (key, leaf) in leaves.clone() {
+            btc.insert(key, leaf.clone()).unwrap();
+            eth.insert(key, leaf.clone()).unwrap();
+            expected.insert(key, leaf).unwrap();
+        }
+        let root = expected.root().unwrap();
+        assert_eq!(btc.root().unwrap().hash(), root.hash());
+        assert_eq!(eth.root().unwrap().hash(), root.hash());
+        // The identical trees store their nodes once.
+        let expected_db = expected.db().as_any().downcast_ref().unwrap();
+        assert_eq!(shared.with(node_count), node_count(expected_db));
+
+        for (key, _) in &leaves {
+            btc.delete(*key).unwrap();
+        }
+        assert_eq!(btc.root().unwrap().hash(), btc.db().empty_tree()[0].hash());
+        let key = leaves[3].0;
+        let leaf = eth.get(key).unwrap().unwrap();
+        let proof = eth.merkle_proof(key).unwrap();
+        verify_merkle_proof::<32, Sha256, (), _>(key, leaf, proof, root.hash()).unwrap();
+
+        // A new handle to a namespace gets its current root.
+        let eth = MSSMT::<32, Sha256, ()>::new(Box::new(shared.namespace("eth").unwrap()));
+        assert_eq!(eth.root().unwrap().hash(), root.hash());
+        let default = MSSMT::<32, Sha256, ()>::new(Box::new(shared.namespace("").unwrap()));
+        assert_eq!(
+            default.root().unwrap().hash(),
+            default.db().empty_tree()[0].hash()
+        );
+    }
+
+    #[test]
+    fn test_shared_db_compact_trees() {
+        let shared = Shared::new(MemoryDb::new());
+        let mut trees = ["epoch-1", "epoch-2"].map(|namespace| {
+            CompactMSSMT::<32, Sha256, ()>::new(Box::new(shared.namespace(namespace).unwrap()))
+        });
+        let leaves = test_leaves::<Sha256>();
+        trees[0].insert_batch(leaves.to_vec()).unwrap();
+        trees[1].insert_batch(leaves[..2].to_vec()).unwrap();
+        let mut expected = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        expected.insert_batch(leaves.to_vec()).unwrap();
+        assert_eq!(
+            trees[0].root().unwrap().hash(),
+            expected.root().unwrap().hash()
+        );
+        assert!(trees[1].get(leaves[3].0).unwrap().is_none());
+
+        for (key, _) in &leaves {
+            trees[0].delete(*key).unwrap();
+        }
+        for (key, leaf) in &leaves[..2] {
+            assert_eq!(trees[1].get(*key).unwrap().unwrap().sum(), leaf.sum());
+        }
+    }
+
+    #[test]
+    fn test_shared_db_rollback() {
+        let shared = Shared::new(MemoryDb::new());
+        let mut btc = shared.namespace("btc").unwrap();
+        let eth = shared.namespace("eth").unwrap();
+        let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(shared.namespace("eth").unwrap()));
+        tree.insert([1; 32], Leaf::new(vec![1; 32], 1)).unwrap();
+        let root = eth.get_root_node().unwrap();
+
+        btc.begin().unwrap();
+        btc.update_root(root.clone()).unwrap();
+        assert_eq!(btc.get_root_node().unwrap().hash(), root.hash());
+        btc.rollback().unwrap();
+        assert!(btc.get_root_node().is_none());
+        assert_eq!(eth.get_root_node().unwrap().hash(), root.hash());
+    }
+
+    #[test]
+    fn test_shared_db_drop_in_transaction() {
+        let shared = Shared::new(MemoryDb::new());
+        let leaves = test_leaves::<Sha256>();
+        let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(shared.namespace("btc").unwrap()));
+        tree.insert(leaves[0].0, leaves[0].1.clone()).unwrap();
+
+        let mut btc = shared.namespace("btc").unwrap();
+        btc.begin().unwrap();
+        btc.insert_leaf(leaves[1].1.clone()).unwrap();
+        btc.update_root(Branch::new(
+            Node::Leaf(leaves[1].1.clone()),
+            Node::new_empty_leaf(),
+        ))
+        .unwrap();
+        drop(btc);
+
+        // The other handles don't wait for the dropped transaction, which left nothing.
+        tree.insert(leaves[2].0, leaves[2].1.clone()).unwrap();
+        let mut expected = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        expected
+            .insert_batch([leaves[0].clone(), leaves[2].clone()])
+            .unwrap();
+        assert_eq!(tree.root().unwrap().hash(), expected.root().unwrap().hash());
+        let expected_db = expected.db().as_any().downcast_ref().unwrap();
+        assert_eq!(shared.with(node_count), node_count(expected_db));
+    }
+
+    #[cfg(feature = "multi-thread")]
+    #[test]
+    fn test_shared_db_threads() {
+        let shared = Shared::new(MemoryDb::new());
+        let mut expected = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        for (key, leaf) in test_leaves::<Sha256>() {
+            expected.insert(key, leaf).unwrap();
+        }
+        std::thread::scope(|scope| {
+            for namespace in 0..4u8 {
+                let db = shared.namespace([namespace]).unwrap();
+                scope.spawn(move || {
+                    let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(db));
+                    for (key, leaf) in test_leaves::<Sha256>() {
+                        tree.insert(key, leaf).unwrap();
+                    }
+                });
+            }
+        });
+        for namespace in 0..4u8 {
+            let tree =
+                MSSMT::<32, Sha256, ()>::new(Box::new(shared.namespace([namespace]).unwrap()));
+            assert_eq!(tree.root().unwrap().hash(), expected.root().unwrap().hash());
+        }
+    }
+
+    #[cfg(feature = "multi-thread")]
+    #[test]
+    fn test_shared_db_same_namespace_threads() {
+        let leaves: Vec<_> = (1..=64u8)
+            .map(|i| ([i; 32], Leaf::<32, Sha256>::new(vec![i; 32], i as u64)))
+            .collect();
+        let mut expected = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        expected.insert_batch(leaves.clone()).unwrap();
+        let expected = expected.root().unwrap().hash();
+        let shared = Shared::new(MemoryDb::new());
+        // Each thread updates the trees through its own handles, so the root can change
+        // between two of its updates.
+        std::thread::scope(|scope| {
+            for chunk in leaves.chunks(8) {
+                let regular = shared.namespace("regular").unwrap();
+                let compact = shared.namespace("compact").unwrap();
+                scope.spawn(move || {
+                    let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(regular));
+                    let mut compact_tree = CompactMSSMT::<32, Sha256, ()>::new(Box::new(compact));
+                    for (i, (key, leaf)) in chunk.iter().enumerate() {
+                        tree.insert_batch([(*key, leaf.clone())]).unwrap();
+                        if i % 2 == 0 {
+                            compact_tree.insert(*key, leaf.clone()).unwrap();
+                        } else {
+                            compact_tree.insert_batch([(*key, leaf.clone())]).unwrap();
+                        }
+                    }
+                });
+            }
+        });
+        let tree = MSSMT::<32, Sha256, ()>::new(Box::new(shared.namespace("regular").unwrap()));
+        assert_eq!(tree.root().unwrap().hash(), expected);
+        let tree =
+            CompactMSSMT::<32, Sha256, ()>::new(Box::new(shared.namespace("compact").unwrap()));
+        assert_eq!(tree.root().unwrap().hash(), expected);
+    }
+
+    type Handle = NamespaceDb<32, Sha256, MemoryDb<32, Sha256>>;
+
+    /// A handle that lets another one commit `race` right before each of its transactions.
+    struct RacingDb<F> {
+        db: Handle,
+        race: F,
+    }
+
+    impl<F: FnMut() + ThreadSafe + 'static> Db<32, Sha256> for RacingDb<F> {
+        type DbError = ();
+
+        fn get_root_node(&self) -> Option<Branch<32, Sha256>> {
+            self.db.get_root_node()
+        }
+
+        fn get_children(
+            &self,
+            height: usize,
+            key: [u8; 32],
+        ) -> Result<(Node<32, Sha256>, Node<32, Sha256>), TreeError<()>> {
+            self.db.get_children(height, key)
+        }
+
+        fn insert_leaf(&mut self, leaf: Leaf<32, Sha256>) -> Result<(), TreeError<()>> {
+            self.db.insert_leaf(leaf)
+        }
+
+        fn insert_branch(&mut self, branch: Branch<32, Sha256>) -> Result<(), TreeError<()>> {
+            self.db.insert_branch(branch)
+        }
+
+        fn insert_compact_leaf(
+            &mut self,
+            compact_leaf: CompactLeaf<32, Sha256>,
+        ) -> Result<(), TreeError<()>> {
+            self.db.insert_compact_leaf(compact_leaf)
+        }
+
+        fn empty_tree(&self) -> Arc<[Node<32, Sha256>]> {
+            self.db.empty_tree()
+        }
+
+        fn update_root(&mut self, root: Branch<32, Sha256>) -> Result<(), TreeError<()>> {
+            self.db.update_root(root)
+        }
+
+        fn delete_branch(&mut self, key: &[u8; 32]) -> Result<(), TreeError<()>> {
+            self.db.delete_branch(key)
+        }
+
+        fn delete_leaf(&mut self, key: &[u8; 32]) -> Result<(), TreeError<()>> {
+            self.db.delete_leaf(key)
+        }
+
+        fn delete_compact_leaf(&mut self, key: &[u8; 32]) -> Result<(), TreeError<()>> {
+            self.db.delete_compact_leaf(key)
+        }
+
+        fn begin(&mut self) -> Result<(), TreeError<()>> {
+            (self.race)();
+            self.db.begin()
+        }
+
+        fn commit(&mut self) -> Result<(), TreeError<()>> {
+            self.db.commit()
+        }
+
+        fn rollback(&mut self) -> Result<(), TreeError<()>> {
+            self.db.rollback()
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn test_shared_db_update_after_another_handle() {
+        let leaves = test_leaves::<Sha256>();
+        let mut expected = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        expected.insert_batch(leaves.to_vec()).unwrap();
+        let expected = expected.root().unwrap().hash();
+
+        let shared = Shared::new(MemoryDb::new());
+        // Another handle inserts the odd leaves while this one inserts the even ones.
+        let odd = || leaves.clone().into_iter().step_by(2);
+        let mut other =
+            MSSMT::<32, Sha256, ()>::new(Box::new(shared.namespace("regular").unwrap()));
+        let mut other_leaves = odd();
+        let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(RacingDb {
+            db: shared.namespace("regular").unwrap(),
+            race: move || {
+                if let Some((key, leaf)) = other_leaves.next() {
+                    other.insert(key, leaf).unwrap();
+                }
+            },
+        }));
+        let mut other =
+            CompactMSSMT::<32, Sha256, ()>::new(Box::new(shared.namespace("compact").unwrap()));
+        let mut other_leaves = odd();
+        let mut compact_tree = CompactMSSMT::<32, Sha256, ()>::new(Box::new(RacingDb {
+            db: shared.namespace("compact").unwrap(),
+            race: move || {
+                if let Some((key, leaf)) = other_leaves.next() {
+                    other.insert(key, leaf).unwrap();
+                }
+            },
+        }));
+        for (i, (key, leaf)) in leaves.iter().skip(1).step_by(2).enumerate() {
+            tree.insert_batch([(*key, leaf.clone())]).unwrap();
+            if i % 2 == 0 {
+                compact_tree.insert(*key, leaf.clone()).unwrap();
+            } else {
+                compact_tree.insert_batch([(*key, leaf.clone())]).unwrap();
+            }
+        }
+        assert_eq!(tree.root().unwrap().hash(), expected);
+        assert_eq!(compact_tree.root().unwrap().hash(), expected);
+    }
+}
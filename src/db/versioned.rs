@@ -0,0 +1,438 @@
+//! Database keeping the previous versions of the tree.
+
+use std::{any::Any, collections::BTreeMap, sync::Arc};
+
+use crate::{
+    db::{Db, GcReport, MemoryDb, NodeKind},
+    node::{Branch, CompactLeaf, Hasher, Leaf, Node, Sum},
+    ThreadSafe, TreeError,
+};
+
+/// A change to the version history.
+enum Change<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> {
+    /// A node deletion was deferred to the pruning of the version.
+    Deleted(u64),
+    /// The root of the version was pruned.
+    PrunedRoot(u64, Option<Branch<HASH_SIZE, H, S>>),
+    /// The deferred deletions of the version were applied.
+    PrunedNodes(u64, Vec<(NodeKind, [u8; HASH_SIZE])>),
+}
+
+/// A database keeping every committed version of the tree on top of another [`Db`].
+///
+/// Each commit gets the next version number: a root update outside of a transaction, or a
+/// transaction that updates the root. The nodes deleted by an update stay in the inner
+/// database until all the versions using them are pruned with [`Db::prune_versions`], so the
+/// trees can be queried at any retained version with `root_at`, `get_at` and
+/// `merkle_proof_at`.
+///
+/// The version history and the deferred deletions are only kept in memory, so the inner
+/// database is a [`MemoryDb`]: over a persistent one, every past version and the nodes only
+/// they use would be lost on reopen. It also counts the references to the nodes, which a
+/// deleted node inserted back before it's pruned relies on.
+pub struct VersionedDb<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, D, S: Sum = u64> {
+    db: D,
+    /// Root of each retained version, `None` being the empty tree.
+    roots: BTreeMap<u64, Option<Branch<HASH_SIZE, H, S>>>,
+    /// Nodes deleted from the tree by the first version that doesn't use them.
+    deleted: BTreeMap<u64, Vec<(NodeKind, [u8; HASH_SIZE])>>,
+    /// Changes made by the open transaction, undone by a rollback.
+    journal: Option<Vec<Change<HASH_SIZE, H, S>>>,
+    /// Whether the open transaction updated the root.
+    root_updated: bool,
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone + ThreadSafe, S: Sum>
+    VersionedDb<HASH_SIZE, H, MemoryDb<HASH_SIZE, H, S>, S>
+{
+    /// Wraps `db`, its current root being version 0.
+    pub fn new(db: MemoryDb<HASH_SIZE, H, S>) -> Self {
+        Self {
+            roots: BTreeMap::from([(0, db.get_root_node())]),
+            db,
+            deleted: BTreeMap::new(),
+            journal: None,
+            root_updated: false,
+        }
+    }
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, D: Db<HASH_SIZE, H, S>, S: Sum>
+    VersionedDb<HASH_SIZE, H, D, S>
+{
+    /// Returns the inner database.
+    pub fn inner(&self) -> &D {
+        &self.db
+    }
+
+    /// Consumes the wrapper and returns the inner database. Nodes that were only kept for
+    /// the previous versions stay in it.
+    pub fn into_inner(self) -> D {
+        self.db
+    }
+
+    fn current(&self) -> u64 {
+        *self
+            .roots
+            .keys()
+            .next_back()
+            .expect("The current version is never pruned")
+    }
+
+    /// Keeps the node for the versions before the next one.
+    fn delete(
+        &mut self,
+        kind: NodeKind,
+        key: &[u8; HASH_SIZE],
+    ) -> Result<(), TreeError<D::DbError>> {
+        let version = self.current() + 1;
+        self.deleted.entry(version).or_default().push((kind, *key));
+        if let Some(journal) = &mut self.journal {
+            journal.push(Change::Deleted(version));
+        }
+        Ok(())
+    }
+
+    fn add_version(&mut self) {
+        let version = self.current() + 1;
+        self.roots.insert(version, self.db.get_root_node());
+    }
+
+    /// Undoes the changes of the open transaction, from the last one.
+    fn undo(&mut self) {
+        self.root_updated = false;
+        for change in self.journal.take().unwrap_or_default().into_iter().rev() {
+            match change {
+                Change::Deleted(version) => {
+                    let nodes = self.deleted.get_mut(&version).expect("Deleted nodes");
+                    nodes.pop();
+                    if nodes.is_empty() {
+                        self.deleted.remove(&version);
+                    }
+                }
+                Change::PrunedRoot(version, root) => {
+                    self.roots.insert(version, root);
+                }
+                Change::PrunedNodes(version, nodes) => {
+                    self.deleted.insert(version, nodes);
+                }
+            }
+        }
+    }
+}
+
+impl<
+        const HASH_SIZE: usize,
+        H: Hasher<HASH_SIZE> + Clone + ThreadSafe,
+        D: Db<HASH_SIZE, H, S> + 'static,
+        S: Sum,
+    > Db<HASH_SIZE, H, S> for VersionedDb<HASH_SIZE, H, D, S>
+{
+    type DbError = D::DbError;
+
+    fn get_root_node(&self) -> Option<Branch<HASH_SIZE, H, S>> {
+        self.db.get_root_node()
+    }
+
+    fn get_children(
+        &self,
+        height: usize,
+        key: [u8; HASH_SIZE],
+    ) -> Result<(Node<HASH_SIZE, H, S>, Node<HASH_SIZE, H, S>), TreeError<Self::DbError>> {
+        self.db.get_children(height, key)
+    }
+
+    fn insert_leaf(&mut self, leaf: Leaf<HASH_SIZE, H, S>) -> Result<(), TreeError<Self::DbError>> {
+        self.db.insert_leaf(leaf)
+    }
+
+    fn insert_branch(
+        &mut self,
+        branch: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.db.insert_branch(branch)
+    }
+
+    fn insert_compact_leaf(
+        &mut self,
+        compact_leaf: CompactLeaf<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.db.insert_compact_leaf(compact_leaf)
+    }
+
+    fn empty_tree(&self) -> Arc<[Node<HASH_SIZE, H, S>]> {
+        self.db.empty_tree()
+    }
+
+    fn update_root(
+        &mut self,
+        root: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.db.update_root(root)?;
+        if self.journal.is_some() {
+            self.root_updated = true;
+        } else {
+            self.add_version();
+        }
+        Ok(())
+    }
+
+    fn delete_branch(&mut self, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<Self::DbError>> {
+        self.delete(NodeKind::Branch, key)
+    }
+
+    fn delete_leaf(&mut self, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<Self::DbError>> {
+        self.delete(NodeKind::Leaf, key)
+    }
+
+    fn delete_compact_leaf(
+        &mut self,
+        key: &[u8; HASH_SIZE],
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.delete(NodeKind::CompactLeaf, key)
+    }
+
+    fn contains(
+        &self,
+        kind: NodeKind,
+        hash: &[u8; HASH_SIZE],
+    ) -> Result<bool, TreeError<Self::DbError>> {
+        self.db.contains(kind, hash)
+    }
+
+    fn begin(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        self.db.begin()?;
+        self.journal = Some(Vec::new());
+        self.root_updated = false;
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        if let Err(e) = self.db.commit() {
+            self.undo();
+            return Err(e);
+        }
+        self.journal = None;
+        if std::mem::take(&mut self.root_updated) {
+            self.add_version();
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<(), TreeError<Self::DbError>> {
+        self.undo();
+        self.db.rollback()
+    }
+
+    fn version(&self) -> Option<u64> {
+        Some(self.current())
+    }
+
+    fn get_root_at(
+        &self,
+        version: u64,
+    ) -> Result<Option<Branch<HASH_SIZE, H, S>>, TreeError<Self::DbError>> {
+        self.roots
+            .get(&version)
+            .cloned()
+            .ok_or(TreeError::VersionNotFound)
+    }
+
+    /// The current version is always kept.
+    fn prune_versions(&mut self, version: u64) -> Result<(), TreeError<Self::DbError>> {
+        let version = version.min(self.current());
+        let roots = self.roots.split_off(&version);
+        let pruned_roots = std::mem::replace(&mut self.roots, roots);
+        // The nodes deleted by a version are only used by the versions before it.
+        let deleted = self.deleted.split_off(&(version + 1));
+        let pruned_nodes = std::mem::replace(&mut self.deleted, deleted);
+        if let Some(journal) = &mut self.journal {
+            for (pruned, root) in pruned_roots {
+                journal.push(Change::PrunedRoot(pruned, root));
+            }
+            for (deleted_at, nodes) in &pruned_nodes {
+                journal.push(Change::PrunedNodes(*deleted_at, nodes.clone()));
+            }
+        }
+        for (kind, key) in pruned_nodes.values().flatten() {
+            match kind {
+                NodeKind::Branch => self.db.delete_branch(key)?,
+                NodeKind::Leaf => self.db.delete_leaf(key)?,
+                NodeKind::CompactLeaf => self.db.delete_compact_leaf(key)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// The nodes of the retained versions are kept.
+    fn sweep(
+        &mut self,
+        roots: &[[u8; HASH_SIZE]],
+        dry_run: bool,
+    ) -> Result<GcReport, TreeError<Self::DbError>> {
+        let mut roots = roots.to_vec();
+        roots.extend(self.roots.values().flatten().map(Branch::hash));
+        self.db.sweep(&roots, dry_run)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::VersionedDb;
+    use crate::{
+        tree::verify_merkle_proof, Branch, CompactMSSMT, Db, Leaf, MemoryDb, Node, TreeError, MSSMT,
+    };
+    use sha2::Sha256;
+
+    type Versioned = VersionedDb<32, Sha256, MemoryDb<32, Sha256>>;
+
+    fn leaf(i: u8) -> Leaf<32, Sha256> {
+        Leaf::new(vec![i; 32], i as u64)
+    }
+
+    /// Sorted keys of the nodes stored in the [`MemoryDb`] of a tree, versioned or not.
+    fn stored_nodes(db: &dyn Db<32, Sha256, DbError = ()>) -> [Vec<[u8; 32]>; 3] {
+        let db = match db.as_any().downcast_ref::<Versioned>() {
+            Some(db) => db.inner(),
+            None => db.as_any().downcast_ref::<MemoryDb<32, Sha256>>().unwrap(),
+        };
+        let sorted = |mut keys: Vec<[u8; 32]>| {
+            keys.sort();
+            keys
+        };
+        [
+            sorted(db.get_branches().keys().copied().collect()),
+            sorted(db.get_leaves().keys().copied().collect()),
+            sorted(db.get_compact_leaves().keys().copied().collect()),
+        ]
+    }
+
+    #[test]
+    fn test_versioned_mssmt() {
+        let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(Versioned::new(MemoryDb::new())));
+        let mut unversioned = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        let mut roots = vec![tree.root().unwrap().hash()];
+        assert_eq!(tree.version(), Some(0));
+        for (key, leaf) in [1, 2, 3, 2].map(|i| ([i; 32], leaf(i))) {
+            tree.insert(key, leaf.clone()).unwrap();
+            unversioned.insert(key, leaf).unwrap();
+            roots.push(tree.root().unwrap().hash());
+        }
+        tree.delete([1; 32]).unwrap();
+        unversioned.delete([1; 32]).unwrap();
+        roots.push(tree.root().unwrap().hash());
+        tree.insert([2; 32], leaf(20)).unwrap();
+        unversioned.insert([2; 32], leaf(20)).unwrap();
+        roots.push(tree.root().unwrap().hash());
+        assert_eq!(tree.version(), Some(6));
+
+        for (version, root) in roots.iter().enumerate() {
+            assert_eq!(tree.root_at(version as u64).unwrap().hash(), *root);
+        }
+        assert!(tree.get_at(0, [1; 32]).unwrap().is_none());
+        assert_eq!(tree.get_at(3, [1; 32]).unwrap().unwrap().sum(), 1);
+        assert!(tree.get_at(5, [1; 32]).unwrap().is_none());
+        assert_eq!(tree.get_at(5, [2; 32]).unwrap().unwrap().sum(), 2);
+        assert_eq!(tree.get_at(6, [2; 32]).unwrap().unwrap().sum(), 20);
+        let proof = tree.merkle_proof_at(3, [1; 32]).unwrap();
+        verify_merkle_proof::<32, Sha256, (), _>([1; 32], leaf(1), proof, roots[3]).unwrap();
+        assert_eq!(tree.root_at(7).unwrap_err(), TreeError::VersionNotFound);
+
+        tree.prune_versions(5).unwrap();
+        assert_eq!(tree.root_at(4).unwrap_err(), TreeError::VersionNotFound);
+        assert_eq!(tree.get_at(5, [2; 32]).unwrap().unwrap().sum(), 2);
+        assert_eq!(tree.get_at(6, [2; 32]).unwrap().unwrap().sum(), 20);
+
+        // Once only the current version is left, the same nodes are stored as without
+        // versions.
+        tree.prune_versions(u64::MAX).unwrap();
+        assert_eq!(tree.version(), Some(6));
+        assert_eq!(stored_nodes(tree.db()), stored_nodes(unversioned.db()));
+    }
+
+    #[test]
+    fn test_versioned_compact_mssmt() {
+        let mut tree =
+            CompactMSSMT::<32, Sha256, ()>::new(Box::new(Versioned::new(MemoryDb::new())));
+        tree.insert_batch((1..=4).map(|i| ([i; 32], leaf(i))))
+            .unwrap();
+        let root = tree.root().unwrap().hash();
+        tree.delete([1; 32]).unwrap();
+        tree.insert([5; 32], leaf(5)).unwrap();
+        // The nodes of the leaf inserted back must survive the prune.
+        tree.insert([1; 32], leaf(1)).unwrap();
+
+        assert_eq!(tree.root_at(1).unwrap().hash(), root);
+        assert!(tree.get_at(1, [5; 32]).unwrap().is_none());
+        assert_eq!(tree.get_at(2, [4; 32]).unwrap().unwrap().sum(), 4);
+        let proof = tree.merkle_proof_at(1, [4; 32]).unwrap();
+        verify_merkle_proof::<32, Sha256, (), _>([4; 32], leaf(4), proof, root).unwrap();
+
+        tree.prune_versions(tree.version().unwrap()).unwrap();
+        let expected = CompactMSSMT::from_sorted_leaves(
+            Box::new(MemoryDb::new()),
+            (1..=5).map(|i| ([i; 32], leaf(i))),
+        )
+        .unwrap();
+        assert_eq!(tree.root().unwrap().hash(), expected.root().unwrap().hash());
+        assert_eq!(stored_nodes(tree.db()), stored_nodes(expected.db()));
+        assert_eq!(tree.get([1; 32]).unwrap().unwrap().sum(), 1);
+    }
+
+    #[test]
+    fn test_versioned_db_gc() {
+        let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(Versioned::new(MemoryDb::new())));
+        for i in 1..=4 {
+            tree.insert([i; 32], leaf(i)).unwrap();
+        }
+        tree.delete([1; 32]).unwrap();
+        tree.insert([2; 32], leaf(20)).unwrap();
+        // Only the previous versions use the nodes deleted from the tree.
+        let mut inner = tree
+            .db()
+            .as_any()
+            .downcast_ref::<Versioned>()
+            .unwrap()
+            .inner()
+            .clone();
+        assert!(inner.gc(true).unwrap().total() > 0);
+        assert_eq!(tree.gc(false).unwrap().total(), 0);
+        assert_eq!(tree.get_at(4, [1; 32]).unwrap().unwrap().sum(), 1);
+
+        tree.prune_versions(tree.version().unwrap()).unwrap();
+        assert_eq!(tree.gc(true).unwrap().total(), 0);
+        assert!(tree.get_at(6, [1; 32]).unwrap().is_none());
+        assert_eq!(tree.get([2; 32]).unwrap().unwrap().sum(), 20);
+    }
+
+    #[test]
+    fn test_versioned_db_rollback() {
+        let root = Branch::new(Node::Leaf(leaf(1)), Node::new_empty_leaf());
+        let mut db = Versioned::new(MemoryDb::new());
+        db.insert_leaf(leaf(1)).unwrap();
+        db.update_root(root.clone()).unwrap();
+
+        db.begin().unwrap();
+        db.delete_leaf(&leaf(1).hash()).unwrap();
+        db.update_root(root.clone()).unwrap();
+        db.rollback().unwrap();
+        assert_eq!(db.version(), Some(1));
+        // The deletion was rolled back so the leaf survives the prune.
+        db.prune_versions(1).unwrap();
+        assert_eq!(db.inner().get_leaves().len(), 1);
+        assert_eq!(db.get_root_at(0).unwrap_err(), TreeError::VersionNotFound);
+
+        db.begin().unwrap();
+        db.delete_leaf(&leaf(1).hash()).unwrap();
+        db.update_root(root).unwrap();
+        db.commit().unwrap();
+        assert_eq!(db.version(), Some(2));
+        assert_eq!(db.inner().get_leaves().len(), 1);
+        db.prune_versions(2).unwrap();
+        assert!(db.inner().get_leaves().is_empty());
+    }
+}
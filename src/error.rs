@@ -22,6 +22,37 @@ pub enum TreeError<DbError> {
     SumOverflow,
     /// Invalid merkle proof
     InvalidMerkleProof,
+    /// The version of the tree is not retained by the database
+    VersionNotFound,
+    /// The database stores a single tree and has no namespaces
+    NamespacesNotSupported,
+    /// Another kind of node is stored under the hash of the node
+    AmbiguousNode,
+    /// The database can't list its nodes to collect the unreachable ones
+    GcNotSupported,
+    /// The database already holds a tree
+    DbNotEmpty,
+}
+
+impl<DbError> TreeError<DbError> {
+    /// Converts the database error with `f`, keeping the other errors.
+    pub fn map_db_error<E>(self, f: impl FnOnce(DbError) -> E) -> TreeError<E> {
+        match self {
+            TreeError::NodeNotFound => TreeError::NodeNotFound,
+            TreeError::ExpectedBranch => TreeError::ExpectedBranch,
+            TreeError::ExpectedLeaf => TreeError::ExpectedLeaf,
+            TreeError::ExpectedCompactLeaf => TreeError::ExpectedCompactLeaf,
+            TreeError::ExpectedEmptyLeaf => TreeError::ExpectedEmptyLeaf,
+            TreeError::DbError(e) => TreeError::DbError(f(e)),
+            TreeError::SumOverflow => TreeError::SumOverflow,
+            TreeError::InvalidMerkleProof => TreeError::InvalidMerkleProof,
+            TreeError::VersionNotFound => TreeError::VersionNotFound,
+            TreeError::NamespacesNotSupported => TreeError::NamespacesNotSupported,
+            TreeError::AmbiguousNode => TreeError::AmbiguousNode,
+            TreeError::GcNotSupported => TreeError::GcNotSupported,
+            TreeError::DbNotEmpty => TreeError::DbNotEmpty,
+        }
+    }
 }
 
 impl<DbError: Display> Display for TreeError<DbError> {
@@ -35,8 +66,39 @@ impl<DbError: Display> Display for TreeError<DbError> {
             TreeError::DbError(e) => write!(f, "Database error: {}", e),
             TreeError::SumOverflow => write!(f, "Sum overflow"),
             TreeError::InvalidMerkleProof => write!(f, "Invalid merkle proof"),
+            TreeError::VersionNotFound => write!(f, "Version not found"),
+            TreeError::NamespacesNotSupported => write!(f, "Namespaces not supported"),
+            TreeError::AmbiguousNode => write!(f, "Another kind of node has the same hash"),
+            TreeError::GcNotSupported => write!(f, "Garbage collection not supported"),
+            TreeError::DbNotEmpty => write!(f, "Database already holds a tree"),
         }
     }
 }
 
 impl<DbError: Debug + Display> Error for TreeError<DbError> {}
+
+/// Error type for decoding encoded proofs
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DecodeError {
+    /// The input ended before the proof was fully decoded
+    UnexpectedEof,
+    /// The input has bytes left after the proof
+    TrailingBytes,
+    /// The number of nodes doesn't match the number of non-empty siblings
+    InvalidNodeCount,
+    /// The encoding version is not supported
+    UnsupportedVersion(u8),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::UnexpectedEof => write!(f, "Unexpected end of input"),
+            DecodeError::TrailingBytes => write!(f, "Trailing bytes after the proof"),
+            DecodeError::InvalidNodeCount => write!(f, "Invalid number of proof nodes"),
+            DecodeError::UnsupportedVersion(v) => write!(f, "Unsupported encoding version {}", v),
+        }
+    }
+}
+
+impl Error for DecodeError {}
@@ -12,12 +12,38 @@
 mod db;
 mod error;
 mod node;
+mod proof;
 mod tree;
 
-pub use db::{Db, MemoryDb, ThreadSafe};
-pub use error::TreeError;
-pub use node::{Branch, CompactLeaf, ComputedNode, EmptyLeaf, Hasher, Leaf, Node};
-pub use tree::{verify_merkle_proof, walk_up, CompactMSSMT, EmptyTree, TreeSize, MSSMT};
+#[cfg(feature = "async")]
+pub use db::{AsyncDb, AsyncMemoryDb, BlockingDb};
+pub use db::{
+    CachedDb, Db, GcReport, MemoryDb, NamespaceDb, NodeKind, SharedDb, StoredNodes, ThreadSafe,
+    VersionedDb, WritePolicy,
+};
+#[cfg(feature = "file-db")]
+pub use db::{FileDb, FileDbError};
+pub use error::{DecodeError, TreeError};
+#[cfg(feature = "pedersen")]
+pub use node::Pedersen;
+#[cfg(feature = "poseidon")]
+pub use node::Poseidon;
+pub use node::{
+    Branch, CompactLeaf, ComputedNode, DomainSeparated, EmptyLeaf, Hasher, Leaf, MultiSum, Node,
+    Sum,
+};
+#[cfg(feature = "u256")]
+pub use primitive_types::U256;
+pub use proof::{
+    verify_compressed_merkle_proof, verify_non_inclusion_proof, CompressedProof, NonInclusionProof,
+    Proof,
+};
+pub use tree::{
+    verify_merkle_proof, walk_up, AuditIssue, AuditReport, CompactMSSMT, Corruption, EmptyTree,
+    MSSMT,
+};
+#[cfg(feature = "async")]
+pub use tree::{AsyncCompactMSSMT, AsyncMSSMT};
 
 #[cfg(test)]
 mod tests;
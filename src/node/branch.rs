@@ -3,56 +3,76 @@ use std::{fmt::Display, marker::PhantomData};
 
 use super::Node;
 use super::{Hasher, Sum};
+use crate::TreeError;
 
 /// A branch is a node that has exactly 2 children. Those children can either be
 /// any type of [`Node`].
 /// Those nodes hold the sum of all their descendants.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Branch<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> {
-    left: Arc<Node<HASH_SIZE, H>>,
-    right: Arc<Node<HASH_SIZE, H>>,
-    sum: Sum,
+pub struct Branch<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum = u64> {
+    left: Arc<Node<HASH_SIZE, H, S>>,
+    right: Arc<Node<HASH_SIZE, H, S>>,
+    sum: S,
     node_hash: [u8; HASH_SIZE],
     _phantom: PhantomData<H>,
 }
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Branch<HASH_SIZE, H> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Branch<HASH_SIZE, H, S> {
     /// Creates a new [`Branch`]. This function performs a hash and an addition.
-    pub fn new(left: Node<HASH_SIZE, H>, right: Node<HASH_SIZE, H>) -> Self {
-        let sum = left.sum() + right.sum();
-        let node_hash = H::hash(
-            [
-                left.hash().as_slice(),
-                right.hash().as_slice(),
-                sum.to_be_bytes().as_slice(),
-            ]
-            .concat()
-            .as_slice(),
-        );
-
-        Self {
-            sum,
-            left: Arc::new(left),
-            right: Arc::new(right),
-            node_hash,
-            _phantom: PhantomData,
-        }
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sum of the children overflows. Use [`Branch::try_new`] for untrusted
+    /// nodes.
+    pub fn new(left: Node<HASH_SIZE, H, S>, right: Node<HASH_SIZE, H, S>) -> Self {
+        Self::new_with_arc_children(Arc::new(left), Arc::new(right))
     }
 
     /// Creates a new [`Branch`] with the provided children.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sum of the children overflows. Use
+    /// [`Branch::try_new_with_arc_children`] for untrusted nodes.
     pub fn new_with_arc_children(
-        left: Arc<Node<HASH_SIZE, H>>,
-        right: Arc<Node<HASH_SIZE, H>>,
+        left: Arc<Node<HASH_SIZE, H, S>>,
+        right: Arc<Node<HASH_SIZE, H, S>>,
     ) -> Self {
-        let sum = left.sum() + right.sum();
-        let node_hash = H::hash(
-            [
-                left.hash().as_slice(),
-                right.hash().as_slice(),
-                sum.to_be_bytes().as_slice(),
-            ]
-            .concat()
-            .as_slice(),
-        );
+        let sum = left
+            .sum()
+            .checked_add(right.sum())
+            .expect("The sum of the branch children overflows");
+        Self::with_sum(left, right, sum)
+    }
+
+    /// Creates a new [`Branch`] or returns [`TreeError::SumOverflow`] if the sum of the
+    /// children overflows.
+    pub fn try_new<DbError>(
+        left: Node<HASH_SIZE, H, S>,
+        right: Node<HASH_SIZE, H, S>,
+    ) -> Result<Self, TreeError<DbError>> {
+        Self::try_new_with_arc_children(Arc::new(left), Arc::new(right))
+    }
+
+    /// Creates a new [`Branch`] with the provided children or returns
+    /// [`TreeError::SumOverflow`] if the sum of the children overflows.
+    pub fn try_new_with_arc_children<DbError>(
+        left: Arc<Node<HASH_SIZE, H, S>>,
+        right: Arc<Node<HASH_SIZE, H, S>>,
+    ) -> Result<Self, TreeError<DbError>> {
+        let sum = left
+            .sum()
+            .checked_add(right.sum())
+            .ok_or(TreeError::SumOverflow)?;
+        Ok(Self::with_sum(left, right, sum))
+    }
+
+    /// Hashes the children with their `sum`.
+    fn with_sum(
+        left: Arc<Node<HASH_SIZE, H, S>>,
+        right: Arc<Node<HASH_SIZE, H, S>>,
+        sum: S,
+    ) -> Self {
+        let node_hash = H::hash_branch(&left.hash(), &right.hash(), sum.to_be_bytes().as_ref());
 
         Self {
             sum,
@@ -67,10 +87,10 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Branch<HASH_SIZE, H>
     ///
     /// The node hash won't be recomputed so if the provided hash is incorrect the whole tree will be incorrect
     pub unsafe fn new_with_hash(
-        left: Node<HASH_SIZE, H>,
-        right: Node<HASH_SIZE, H>,
+        left: Node<HASH_SIZE, H, S>,
+        right: Node<HASH_SIZE, H, S>,
         node_hash: [u8; HASH_SIZE],
-        sum: Sum,
+        sum: S,
     ) -> Self {
         Self {
             sum,
@@ -93,26 +113,28 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Branch<HASH_SIZE, H>
     }
 
     /// Returns the sum of the node.
-    pub fn sum(&self) -> Sum {
+    pub fn sum(&self) -> S {
         self.sum
     }
 
     /// Returns the left and right children of this branch.
-    pub fn children(&self) -> (&Node<HASH_SIZE, H>, &Node<HASH_SIZE, H>) {
+    pub fn children(&self) -> (&Node<HASH_SIZE, H, S>, &Node<HASH_SIZE, H, S>) {
         (&self.left, &self.right)
     }
 
     /// Returns the left children of this branch.
-    pub fn left(&self) -> &Node<HASH_SIZE, H> {
+    pub fn left(&self) -> &Node<HASH_SIZE, H, S> {
         &self.left
     }
 
     /// Returns the right children of this branch.
-    pub fn right(&self) -> &Node<HASH_SIZE, H> {
+    pub fn right(&self) -> &Node<HASH_SIZE, H, S> {
         &self.right
     }
 }
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Display for Branch<HASH_SIZE, H> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Display
+    for Branch<HASH_SIZE, H, S>
+{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
@@ -130,7 +152,7 @@ mod test {
     use std::sync::Arc;
 
     use super::Branch;
-    use crate::{node::Node, Leaf};
+    use crate::{node::Node, Leaf, TreeError};
     use hex_literal::hex;
     use sha2::Sha256;
 
@@ -222,4 +244,27 @@ mod test {
             hex!("c3171e69d789087eea2e9a1f0a7cb0068421e5af3727455ea5ec24ef764184a6")
         );
     }
+
+    #[test]
+    fn test_branch_try_new_sum_overflow() {
+        let left = Node::Leaf(Leaf::<32, Sha256>::new(vec![1, 2, 3], u64::MAX));
+        let right = Node::Leaf(Leaf::<32, Sha256>::new(vec![4, 5, 6], 1));
+        assert_eq!(
+            Branch::try_new::<()>(left.clone(), right.clone()).unwrap_err(),
+            TreeError::SumOverflow
+        );
+        assert_eq!(
+            Branch::try_new_with_arc_children::<()>(Arc::new(left), Arc::new(right)).unwrap_err(),
+            TreeError::SumOverflow
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_branch_new_sum_overflow() {
+        Branch::new(
+            Node::Leaf(Leaf::<32, Sha256>::new(vec![1, 2, 3], u64::MAX)),
+            Node::Leaf(Leaf::<32, Sha256>::new(vec![4, 5, 6], 1)),
+        );
+    }
 }
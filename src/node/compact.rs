@@ -5,27 +5,29 @@ use crate::EmptyTree;
 use super::leaf::Leaf;
 use super::Hasher;
 use super::Node;
+use super::Sum;
 use crate::tree::bit_index;
 
 /// A compact leaf is a leaf doesn't require all the empty parts of the path to be inserted.
 /// When required we can extract all the branches on that path.
 /// The node hash is the hash of the node at the top of the path.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct CompactLeaf<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> {
+pub struct CompactLeaf<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum = u64> {
     node_hash: [u8; HASH_SIZE],
-    leaf: Leaf<HASH_SIZE, H>,
+    leaf: Leaf<HASH_SIZE, H, S>,
     key: [u8; HASH_SIZE],
 }
 
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> CompactLeaf<HASH_SIZE, H> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> CompactLeaf<HASH_SIZE, H, S> {
     /// Creates a new compact leaf.
-    pub fn new(height: usize, key: [u8; HASH_SIZE], leaf: Leaf<HASH_SIZE, H>) -> Self {
+    pub fn new(height: usize, key: [u8; HASH_SIZE], leaf: Leaf<HASH_SIZE, H, S>) -> Self {
         // Walk up the path from the leaf to the top of the path
         let mut current = Node::Leaf(leaf.clone());
-        let empty_tree = EmptyTree::<HASH_SIZE, H>::empty_tree();
+        let empty_tree = EmptyTree::<HASH_SIZE, H, S>::empty_tree();
 
         // Start from the last height of the tree (the leaf) and walk up to the `height` required.
-        // This height is the last bit that is common with another leaf.
+        // This height is the last bit that is common with another leaf. The siblings on the
+        // path are all empty so the sums of these branches can't overflow.
         for i in (height..HASH_SIZE * 8).rev() {
             // Construct all the branches on the path to the height.
             if bit_index(i, &key) == 0 {
@@ -46,7 +48,7 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> CompactLeaf<HASH_SIZE
     /// The node hash won't be recomputed so if the provided hash is incorrect the whole tree will be incorrect
     pub unsafe fn new_with_hash(
         node_hash: [u8; HASH_SIZE],
-        leaf: Leaf<HASH_SIZE, H>,
+        leaf: Leaf<HASH_SIZE, H, S>,
         key: [u8; HASH_SIZE],
     ) -> Self {
         Self {
@@ -60,7 +62,7 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> CompactLeaf<HASH_SIZE
         self.node_hash
     }
     /// Returns the leaf of the compact leaf.
-    pub fn leaf(&self) -> &Leaf<HASH_SIZE, H> {
+    pub fn leaf(&self) -> &Leaf<HASH_SIZE, H, S> {
         &self.leaf
     }
     /// Returns the key of the compact leaf.
@@ -68,15 +70,16 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> CompactLeaf<HASH_SIZE
         &self.key
     }
     /// Returns the sum of the leaf.
-    pub fn sum(&self) -> u64 {
+    pub fn sum(&self) -> S {
         self.leaf.sum()
     }
     /// Extracts the branches on the path to the leaf.
-    pub fn extract(&self, height: usize) -> Node<HASH_SIZE, H> {
+    pub fn extract(&self, height: usize) -> Node<HASH_SIZE, H, S> {
         let mut current = Node::Leaf(self.leaf.clone());
-        let empty_tree = EmptyTree::<HASH_SIZE, H>::empty_tree();
+        let empty_tree = EmptyTree::<HASH_SIZE, H, S>::empty_tree();
 
-        // Walk up and recreate the missing branches
+        // Walk up and recreate the missing branches. Like in `new`, the siblings are empty so
+        // the sums can't overflow.
         for j in (height + 2..=(HASH_SIZE * 8)).rev() {
             let (left, right) = if bit_index(j - 1, &self.key) == 0 {
                 (current, empty_tree[j].clone())
@@ -90,7 +93,9 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> CompactLeaf<HASH_SIZE
         current
     }
 }
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Display for CompactLeaf<HASH_SIZE, H> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Display
+    for CompactLeaf<HASH_SIZE, H, S>
+{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
@@ -134,6 +139,16 @@ mod test {
         assert_eq!(extracted.sum(), 1);
     }
 
+    #[test]
+    fn test_compact_leaf_max_sum() {
+        // The siblings on the path are empty, so a leaf holding the largest sum fits.
+        let leaf = Leaf::<32, Sha256>::new(vec![1, 2, 3], u64::MAX);
+        let compact_leaf = CompactLeaf::new(3, [1; 32], leaf);
+        let extracted = compact_leaf.extract(2);
+        assert_eq!(extracted.sum(), u64::MAX);
+        assert_eq!(extracted.hash(), compact_leaf.hash());
+    }
+
     #[test]
     fn test_compact_leaf_new_with_hash() {
         let leaf = Leaf::<32, Sha256>::new(vec![1, 2, 3], 1);
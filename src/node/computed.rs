@@ -5,12 +5,12 @@ use super::Sum;
 /// A computed node. Useful for traversing the tree without reconstructing branches
 /// which contains their children and are expensive to reconstruct.
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct ComputedNode<const HASH_SIZE: usize> {
+pub struct ComputedNode<const HASH_SIZE: usize, S: Sum = u64> {
     node_hash: [u8; HASH_SIZE],
-    sum: Sum,
+    sum: S,
 }
-impl<const HASH_SIZE: usize> ComputedNode<HASH_SIZE> {
-    pub fn new(node_hash: [u8; HASH_SIZE], sum: Sum) -> Self {
+impl<const HASH_SIZE: usize, S: Sum> ComputedNode<HASH_SIZE, S> {
+    pub fn new(node_hash: [u8; HASH_SIZE], sum: S) -> Self {
         Self { node_hash, sum }
     }
     /// Returns the hash of the node.
@@ -18,12 +18,12 @@ impl<const HASH_SIZE: usize> ComputedNode<HASH_SIZE> {
         self.node_hash
     }
     /// Returns the sum of the node.
-    pub fn sum(&self) -> Sum {
+    pub fn sum(&self) -> S {
         self.sum
     }
 }
 
-impl<const HASH_SIZE: usize> Display for ComputedNode<HASH_SIZE> {
+impl<const HASH_SIZE: usize, S: Sum> Display for ComputedNode<HASH_SIZE, S> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
@@ -40,7 +40,7 @@ mod test {
     use hex_literal::hex;
     #[test]
     fn test_computed_node_new() {
-        let computed_node = ComputedNode::new(
+        let computed_node = ComputedNode::<32>::new(
             hex!("0000000000000000000000000000000000000000000000000000000000000000"),
             1,
         );
@@ -53,7 +53,7 @@ mod test {
 
     #[test]
     fn test_computed_node_display() {
-        let computed_node = ComputedNode::new(
+        let computed_node = ComputedNode::<32>::new(
             hex!("0000000000000000000000000000000000000000000000000000000000000000"),
             1,
         );
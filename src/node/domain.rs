@@ -0,0 +1,126 @@
+use std::marker::PhantomData;
+
+use super::Hasher;
+
+/// Tag of the leaf preimages.
+const LEAF_TAG: u8 = 0;
+/// Tag of the branch preimages.
+const BRANCH_TAG: u8 = 1;
+/// Tag of the empty leaf preimages.
+const EMPTY_LEAF_TAG: u8 = 2;
+
+/// Domain-separated hashing of the nodes with `H`.
+///
+/// By default a leaf hashes `value || sum` and a branch `left || right || sum`, as in
+/// taproot-assets, so a leaf with a value of twice the hash size has the preimage of a
+/// branch. This hasher prefixes the preimages with a tag of the kind of node:
+/// - `0x00 || value || sum` for a leaf
+/// - `0x01 || left || right || sum` for a branch
+/// - `0x02 || sum` for an empty leaf
+///
+/// The scheme is selected per tree by its hasher, e.g. `MSSMT<32, DomainSeparated<Sha256>, _>`,
+/// and the proofs of the tree only verify with the same hasher.
+#[derive(Debug, Clone, Default)]
+pub struct DomainSeparated<H>(PhantomData<H>);
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE>> Hasher<HASH_SIZE> for DomainSeparated<H> {
+    fn hash(data: &[u8]) -> [u8; HASH_SIZE] {
+        H::hash(data)
+    }
+
+    fn hash_parts(parts: &[&[u8]]) -> [u8; HASH_SIZE] {
+        H::hash_parts(parts)
+    }
+
+    fn hash_branch(left: &[u8; HASH_SIZE], right: &[u8; HASH_SIZE], sum: &[u8]) -> [u8; HASH_SIZE] {
+        H::hash_parts(&[&[BRANCH_TAG], left, right, sum])
+    }
+
+    fn hash_leaf(value: &[u8], sum: &[u8]) -> [u8; HASH_SIZE] {
+        H::hash_parts(&[&[LEAF_TAG], value, sum])
+    }
+
+    fn hash_empty_leaf(sum: &[u8]) -> [u8; HASH_SIZE] {
+        H::hash_parts(&[&[EMPTY_LEAF_TAG], sum])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DomainSeparated;
+    use crate::{
+        verify_merkle_proof, Branch, EmptyTree, Hasher, Leaf, MemoryDb, Node, Proof, TreeError,
+        MSSMT,
+    };
+    use sha2::{Digest, Sha256};
+
+    type Tagged = DomainSeparated<Sha256>;
+
+    #[test]
+    fn test_domain_tags() {
+        let sum = 3u64.to_be_bytes();
+        assert_eq!(
+            Tagged::hash_leaf(&[1, 2], &sum),
+            <[u8; 32]>::from(Sha256::digest([&[0, 1, 2], sum.as_slice()].concat()))
+        );
+        assert_eq!(
+            Tagged::hash_branch(&[1; 32], &[2; 32], &sum),
+            <[u8; 32]>::from(Sha256::digest(
+                [&[1][..], &[1; 32], &[2; 32], &sum].concat()
+            ))
+        );
+        assert_eq!(
+            EmptyTree::<32, Tagged>::empty_tree()[256].hash(),
+            <[u8; 32]>::from(Sha256::digest([2, 0, 0, 0, 0, 0, 0, 0, 0]))
+        );
+    }
+
+    /// A leaf holding the hashes of the children of a branch hashes like the branch, unless
+    /// the hashes are domain-separated.
+    fn leaf_like_branch<H: Hasher<32> + Clone>() -> (Leaf<32, H>, Branch<32, H>) {
+        let left = Leaf::<32, H>::new(vec![1], 1);
+        let right = Leaf::<32, H>::new(vec![2], 2);
+        let value = [left.hash(), right.hash()].concat();
+        let branch = Branch::new(Node::Leaf(left), Node::Leaf(right));
+        (Leaf::new(value, 3), branch)
+    }
+
+    #[test]
+    fn test_leaf_branch_collision() {
+        let (leaf, branch) = leaf_like_branch::<Sha256>();
+        assert_eq!(leaf.hash(), branch.hash());
+        let (leaf, branch) = leaf_like_branch::<Tagged>();
+        assert_ne!(leaf.hash(), branch.hash());
+    }
+
+    #[test]
+    fn test_proofs_respect_the_scheme() {
+        let mut tree = MSSMT::<32, Tagged, ()>::new(Box::new(MemoryDb::new()));
+        let leaf = Leaf::<32, Tagged>::new(vec![1; 32], 1);
+        tree.insert([1; 32], leaf).unwrap();
+        tree.insert([2; 32], Leaf::new(vec![2; 32], 2)).unwrap();
+        let root = tree.root().unwrap().hash();
+        let proof = tree.merkle_proof([1; 32]).unwrap();
+        assert_eq!(
+            verify_merkle_proof::<32, Tagged, (), _>(
+                [1; 32],
+                Leaf::new(vec![1; 32], 1),
+                proof.clone(),
+                root
+            ),
+            Ok(())
+        );
+
+        // The same proof read with the untagged scheme doesn't lead to the root.
+        let proof = Proof::<32, Sha256>::from_bytes(&proof.to_bytes()).unwrap();
+        assert_eq!(
+            verify_merkle_proof::<32, Sha256, (), _>(
+                [1; 32],
+                Leaf::new(vec![1; 32], 1),
+                proof,
+                root
+            ),
+            Err(TreeError::InvalidMerkleProof)
+        );
+    }
+}
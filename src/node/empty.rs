@@ -5,22 +5,24 @@ use super::{Hasher, Sum};
 
 /// Represents an empty leaf in the tree. Those leaves have no `value` and hold `0` as sum value.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct EmptyLeaf<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> {
+pub struct EmptyLeaf<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum = u64> {
     node_hash: [u8; HASH_SIZE],
-    _phantom: PhantomData<H>,
+    _phantom: PhantomData<(H, S)>,
 }
 
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Default for EmptyLeaf<HASH_SIZE, H> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Default
+    for EmptyLeaf<HASH_SIZE, H, S>
+{
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> EmptyLeaf<HASH_SIZE, H> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> EmptyLeaf<HASH_SIZE, H, S> {
     /// Creates a new [`EmptyLeaf`]. This function performs a hash.
     pub fn new() -> Self {
         Self {
-            node_hash: H::hash([0; 8].as_slice()),
+            node_hash: H::hash_empty_leaf(S::default().to_be_bytes().as_ref()),
             _phantom: PhantomData,
         }
     }
@@ -31,12 +33,14 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> EmptyLeaf<HASH_SIZE,
     }
 
     /// Returns the sum of the node.
-    pub fn sum(&self) -> Sum {
-        0
+    pub fn sum(&self) -> S {
+        S::default()
     }
 }
 
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Display for EmptyLeaf<HASH_SIZE, H> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Display
+    for EmptyLeaf<HASH_SIZE, H, S>
+{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
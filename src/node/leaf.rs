@@ -3,13 +3,13 @@ use std::{fmt::Display, marker::PhantomData};
 use super::{EmptyLeaf, Hasher, Sum};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Leaf<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> {
-    NonEmpty(NonEmptyLeaf<HASH_SIZE, H>),
-    Empty(EmptyLeaf<HASH_SIZE, H>),
+pub enum Leaf<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum = u64> {
+    NonEmpty(NonEmptyLeaf<HASH_SIZE, H, S>),
+    Empty(EmptyLeaf<HASH_SIZE, H, S>),
 }
 
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Leaf<HASH_SIZE, H> {
-    pub fn new(value: Vec<u8>, sum: Sum) -> Self {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Leaf<HASH_SIZE, H, S> {
+    pub fn new(value: Vec<u8>, sum: S) -> Self {
         if value.is_empty() {
             Self::Empty(EmptyLeaf::new())
         } else {
@@ -23,7 +23,7 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Leaf<HASH_SIZE, H> {
     ///
     /// The provided hash must be correctly computed from the value and sum.
     /// If an incorrect hash is provided, the tree's integrity will be compromised.
-    pub unsafe fn new_with_hash(value: Vec<u8>, sum: Sum, node_hash: [u8; HASH_SIZE]) -> Self {
+    pub unsafe fn new_with_hash(value: Vec<u8>, sum: S, node_hash: [u8; HASH_SIZE]) -> Self {
         Self::NonEmpty(NonEmptyLeaf::new_with_hash(value, sum, node_hash))
     }
 
@@ -36,7 +36,7 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Leaf<HASH_SIZE, H> {
     }
 
     /// Returns the sum of the node.
-    pub fn sum(&self) -> Sum {
+    pub fn sum(&self) -> S {
         match self {
             Self::NonEmpty(leaf) => leaf.sum(),
             Self::Empty(leaf) => leaf.sum(),
@@ -50,9 +50,16 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Leaf<HASH_SIZE, H> {
             Self::Empty(_) => &[],
         }
     }
+
+    /// Returns `true` if this is an empty leaf.
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Self::Empty(_))
+    }
 }
 
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Display for Leaf<HASH_SIZE, H> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Display
+    for Leaf<HASH_SIZE, H, S>
+{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::NonEmpty(leaf) => write!(f, "{}", leaf),
@@ -66,21 +73,17 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Display for Leaf<HASH
 /// Each leaf contains a `value`
 /// represented as bytes and a `sum` which is an integer.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct NonEmptyLeaf<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> {
+pub struct NonEmptyLeaf<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum = u64> {
     value: Vec<u8>,
-    sum: Sum,
+    sum: S,
     node_hash: [u8; HASH_SIZE],
     _phantom: PhantomData<H>,
 }
 
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> NonEmptyLeaf<HASH_SIZE, H> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> NonEmptyLeaf<HASH_SIZE, H, S> {
     /// Creates a new [`Leaf`]. This function performs a hash.
-    pub fn new(value: Vec<u8>, sum: Sum) -> Self {
-        let node_hash = H::hash(
-            [value.as_slice(), sum.to_be_bytes().as_slice()]
-                .concat()
-                .as_slice(),
-        );
+    pub fn new(value: Vec<u8>, sum: S) -> Self {
+        let node_hash = H::hash_leaf(&value, sum.to_be_bytes().as_ref());
         Self {
             value,
             sum,
@@ -95,7 +98,7 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> NonEmptyLeaf<HASH_SIZ
     ///
     /// The provided hash must be correctly computed from the value and sum.
     /// If an incorrect hash is provided, the tree's integrity will be compromised.
-    pub unsafe fn new_with_hash(value: Vec<u8>, sum: Sum, node_hash: [u8; HASH_SIZE]) -> Self {
+    pub unsafe fn new_with_hash(value: Vec<u8>, sum: S, node_hash: [u8; HASH_SIZE]) -> Self {
         Self {
             value,
             sum,
@@ -110,7 +113,7 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> NonEmptyLeaf<HASH_SIZ
     }
 
     /// Returns the sum of the node.
-    pub fn sum(&self) -> Sum {
+    pub fn sum(&self) -> S {
         self.sum
     }
 
@@ -119,7 +122,9 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> NonEmptyLeaf<HASH_SIZ
         &self.value
     }
 }
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Display for NonEmptyLeaf<HASH_SIZE, H> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Display
+    for NonEmptyLeaf<HASH_SIZE, H, S>
+{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
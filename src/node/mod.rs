@@ -1,18 +1,41 @@
 mod branch;
 mod compact;
 mod computed;
+mod domain;
 mod empty;
 mod leaf;
+#[cfg(any(feature = "poseidon", feature = "pedersen"))]
+mod starknet;
+mod sum;
 
-use sha2::{Digest, Sha256};
+use sha2::{Digest, Sha256, Sha512_256};
 use std::fmt::Debug;
 use std::fmt::Display;
 
 pub use branch::Branch;
 pub use compact::CompactLeaf;
 pub use computed::ComputedNode;
+pub use domain::DomainSeparated;
 pub use empty::EmptyLeaf;
 pub use leaf::Leaf;
+#[cfg(feature = "pedersen")]
+pub use starknet::Pedersen;
+#[cfg(feature = "poseidon")]
+pub use starknet::Poseidon;
+pub use sum::{MultiSum, Sum};
+
+/// Size of the buffer the parts of a hash input are concatenated in without allocating, see
+/// [`Hasher::hash_parts`].
+const STACK_BUFFER_SIZE: usize = 256;
+
+/// Updates a new `D` with each of `parts` and finalizes it.
+fn digest_parts<D: Digest>(parts: &[&[u8]]) -> sha2::digest::Output<D> {
+    let mut hasher = D::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize()
+}
 
 impl Hasher<32> for Sha256 {
     fn hash(data: &[u8]) -> [u8; 32] {
@@ -20,16 +43,96 @@ impl Hasher<32> for Sha256 {
         hasher.update(data);
         hasher.finalize().into()
     }
+
+    fn hash_parts(parts: &[&[u8]]) -> [u8; 32] {
+        digest_parts::<Sha256>(parts).into()
+    }
+}
+
+impl Hasher<32> for Sha512_256 {
+    fn hash(data: &[u8]) -> [u8; 32] {
+        Sha512_256::digest(data).into()
+    }
+
+    fn hash_parts(parts: &[&[u8]]) -> [u8; 32] {
+        digest_parts::<Sha512_256>(parts).into()
+    }
+}
+
+/// Keccak-256 as used by Ethereum, not the padding of the standardized SHA3-256.
+#[cfg(feature = "keccak")]
+impl Hasher<32> for sha3::Keccak256 {
+    fn hash(data: &[u8]) -> [u8; 32] {
+        sha3::Keccak256::digest(data).into()
+    }
+
+    fn hash_parts(parts: &[&[u8]]) -> [u8; 32] {
+        digest_parts::<sha3::Keccak256>(parts).into()
+    }
 }
 
-pub type Sum = u64;
+#[cfg(feature = "blake3")]
+impl Hasher<32> for blake3::Hasher {
+    fn hash(data: &[u8]) -> [u8; 32] {
+        blake3::hash(data).into()
+    }
+
+    fn hash_parts(parts: &[&[u8]]) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().into()
+    }
+}
 
 /// Simple hash trait required to hash the nodes in the tree
 ///
+/// The nodes are hashed with [`Hasher::hash_branch`], [`Hasher::hash_leaf`] and
+/// [`Hasher::hash_empty_leaf`], which feed their arguments piece by piece to
+/// [`Hasher::hash_parts`] by default. Hashers that don't work on bytes, like the Starknet
+/// ones, override them to encode the nodes their own way, and [`DomainSeparated`] to tag
+/// them.
+///
 /// # Type Parameters
 /// * `HASH_SIZE` - The size of the hash digest in bytes
 pub trait Hasher<const HASH_SIZE: usize>: 'static {
     fn hash(data: &[u8]) -> [u8; HASH_SIZE];
+
+    /// Hashes the concatenation of `parts`. Incremental hashers update their state with each
+    /// part and finalize it, which is what the bundled ones do.
+    ///
+    /// The default implementation is for one-shot hashers: it concatenates the parts, on the
+    /// stack unless they are longer than a few branches, and calls [`Hasher::hash`].
+    fn hash_parts(parts: &[&[u8]]) -> [u8; HASH_SIZE] {
+        let len = parts.iter().map(|part| part.len()).sum();
+        if len > STACK_BUFFER_SIZE {
+            return Self::hash(&parts.concat());
+        }
+        let mut buffer = [0; STACK_BUFFER_SIZE];
+        let mut offset = 0;
+        for part in parts {
+            buffer[offset..offset + part.len()].copy_from_slice(part);
+            offset += part.len();
+        }
+        Self::hash(&buffer[..len])
+    }
+
+    /// Hashes a branch from the hashes of its children and the big-endian encoding of its sum.
+    fn hash_branch(left: &[u8; HASH_SIZE], right: &[u8; HASH_SIZE], sum: &[u8]) -> [u8; HASH_SIZE] {
+        Self::hash_parts(&[left, right, sum])
+    }
+
+    /// Hashes a leaf from its value and the big-endian encoding of its sum.
+    fn hash_leaf(value: &[u8], sum: &[u8]) -> [u8; HASH_SIZE] {
+        Self::hash_parts(&[value, sum])
+    }
+
+    /// Hashes an empty leaf from the big-endian encoding of its zero sum, like a leaf with an
+    /// empty value by default.
+    fn hash_empty_leaf(sum: &[u8]) -> [u8; HASH_SIZE] {
+        Self::hash_leaf(&[], sum)
+    }
 }
 
 /// All possible nodes in the tree.
@@ -37,18 +140,19 @@ pub trait Hasher<const HASH_SIZE: usize>: 'static {
 /// # Type Parameters
 /// * `HASH_SIZE` - The size of the hash digest in bytes
 /// * `H` - The hasher implementation used for this node
+/// * `S` - The type of the sums, `u64` by default
 #[derive(Clone, PartialEq, Eq)]
-pub enum Node<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> {
+pub enum Node<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum = u64> {
     /// A leaf node containing a value and sum
-    Leaf(Leaf<HASH_SIZE, H>),
+    Leaf(Leaf<HASH_SIZE, H, S>),
     /// A branch node with two children
-    Branch(Branch<HASH_SIZE, H>),
+    Branch(Branch<HASH_SIZE, H, S>),
     /// A compact leaf node containing a value and sum
-    Compact(CompactLeaf<HASH_SIZE, H>),
+    Compact(CompactLeaf<HASH_SIZE, H, S>),
     /// A computed node
-    Computed(ComputedNode<HASH_SIZE>),
+    Computed(ComputedNode<HASH_SIZE, S>),
 }
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Debug for Node<HASH_SIZE, H> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Debug for Node<HASH_SIZE, H, S> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Leaf(leaf) => {
@@ -81,7 +185,9 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Debug for Node<HASH_S
         }
     }
 }
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Display for Node<HASH_SIZE, H> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Display
+    for Node<HASH_SIZE, H, S>
+{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(&match self {
             Self::Leaf(leaf) => format!("{}", leaf),
@@ -92,18 +198,20 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Display for Node<HASH
     }
 }
 
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Node<HASH_SIZE, H> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Node<HASH_SIZE, H, S> {
     /// Creates a [`Node::Branch`] from 2 [`Node`]
-    pub fn new_branch(left: Node<HASH_SIZE, H>, right: Node<HASH_SIZE, H>) -> Self {
-        Self::Branch(Branch::<HASH_SIZE, H>::new(left, right))
+    pub fn new_branch(left: Node<HASH_SIZE, H, S>, right: Node<HASH_SIZE, H, S>) -> Self {
+        Self::Branch(Branch::<HASH_SIZE, H, S>::new(left, right))
     }
     /// Creates a [`Node::Leaf`] from a `value` and a `sum`
-    pub fn new_leaf(value: Vec<u8>, sum: Sum) -> Self {
-        Self::Leaf(Leaf::<HASH_SIZE, H>::new(value, sum))
+    pub fn new_leaf(value: Vec<u8>, sum: S) -> Self {
+        Self::Leaf(Leaf::<HASH_SIZE, H, S>::new(value, sum))
     }
     /// Creates a [`Node::Leaf(Leaf::Empty(EmptyLeaf))`]
     pub fn new_empty_leaf() -> Self {
-        Self::Leaf(Leaf::<HASH_SIZE, H>::Empty(EmptyLeaf::<HASH_SIZE, H>::new()))
+        Self::Leaf(Leaf::<HASH_SIZE, H, S>::Empty(
+            EmptyLeaf::<HASH_SIZE, H, S>::new(),
+        ))
     }
 
     /// Returns the hash of the node. NO HASHING IS DONE HERE.
@@ -117,7 +225,7 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> Node<HASH_SIZE, H> {
     }
 
     /// Returns the sum of a [`Node`]. NO OPERATION IS DONE HERE.
-    pub fn sum(&self) -> Sum {
+    pub fn sum(&self) -> S {
         match self {
             Self::Leaf(leaf) => leaf.sum(),
             Self::Branch(branch) => branch.sum(),
@@ -133,7 +241,7 @@ mod test {
 
     use super::Node;
     use hex_literal::hex;
-    use sha2::Sha256;
+    use sha2::{Digest, Sha256};
 
     #[test]
     fn test_computed_node() {
@@ -146,6 +254,103 @@ mod test {
         assert_eq!(computed_node.sum(), computed.sum());
     }
 
+    /// Digests of the empty input from the reference implementations.
+    #[test]
+    fn test_bundled_hashers() {
+        use super::Hasher;
+
+        assert_eq!(
+            <Sha256 as Hasher<32>>::hash(&[]),
+            hex!("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+        );
+        assert_eq!(
+            <sha2::Sha512_256 as Hasher<32>>::hash(&[]),
+            hex!("c672b8d1ef56ed28ab87c3622c5114069bdd3ad7b8f9737498d0c01ecef0967a")
+        );
+        #[cfg(feature = "keccak")]
+        assert_eq!(
+            <sha3::Keccak256 as Hasher<32>>::hash(&[]),
+            hex!("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470")
+        );
+        #[cfg(feature = "blake3")]
+        assert_eq!(
+            <blake3::Hasher as Hasher<32>>::hash(&[]),
+            hex!("af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262")
+        );
+    }
+
+    /// Leaf and branch hashes from the reference implementations, of `"hello" || 1` for the
+    /// leaf and `[1; 32] || [2; 32] || 3` for the branch, the sums being big-endian `u64`s.
+    #[test]
+    fn test_bundled_node_hashes() {
+        use super::Hasher;
+
+        fn node_hashes<H: Hasher<32>>() -> ([u8; 32], [u8; 32]) {
+            (
+                H::hash_leaf(b"hello", &1u64.to_be_bytes()),
+                H::hash_branch(&[1; 32], &[2; 32], &3u64.to_be_bytes()),
+            )
+        }
+
+        assert_eq!(
+            node_hashes::<sha2::Sha512_256>(),
+            (
+                hex!("6ad812bf7c6ef0d26db7c6a8a820350405e013d9863fea9865a85c43f0d15fc3"),
+                hex!("607d26e71c2449a632e32f517d5cc0b6c7a89bb03e4c49a1a69a684bda2db4bd")
+            )
+        );
+        #[cfg(feature = "keccak")]
+        assert_eq!(
+            node_hashes::<sha3::Keccak256>(),
+            (
+                hex!("b6cbfa4c9b36c19d8e43a57a8d5a1bcc6422f789a60e75f20df6db536413aa5c"),
+                hex!("cf1b38ed4b5b54b659495c06a7cff22f3ff3df4da49cdeb8934276f79b3d84f0")
+            )
+        );
+        #[cfg(feature = "blake3")]
+        assert_eq!(
+            node_hashes::<blake3::Hasher>(),
+            (
+                hex!("96754dc572185666ea3e358b0b5e56f00339e25b93c49941eb9ab2de0dd70427"),
+                hex!("b886bc93d96d178aef9bbcd8a02ab96c25c20a4073a055fd696b315276ef2892")
+            )
+        );
+    }
+
+    /// Checks that hashing parts is hashing their concatenation, on the stack or not.
+    fn test_hash_parts<H: super::Hasher<32>>() {
+        for len in [0, 31, 300] {
+            let value = vec![7; len];
+            let parts: [&[u8]; 3] = [&[1; 32], &value, &[2; 8]];
+            assert_eq!(H::hash_parts(&parts), H::hash(&parts.concat()));
+        }
+    }
+
+    #[test]
+    fn test_piecewise_hashing() {
+        /// A hasher only hashing whole inputs.
+        #[derive(Clone)]
+        struct OneShot;
+
+        impl super::Hasher<32> for OneShot {
+            fn hash(data: &[u8]) -> [u8; 32] {
+                sha2::Sha256::digest(data).into()
+            }
+        }
+
+        test_hash_parts::<OneShot>();
+        test_hash_parts::<Sha256>();
+        test_hash_parts::<sha2::Sha512_256>();
+        #[cfg(feature = "keccak")]
+        test_hash_parts::<sha3::Keccak256>();
+        #[cfg(feature = "blake3")]
+        test_hash_parts::<blake3::Hasher>();
+        assert_eq!(
+            Leaf::<32, OneShot>::new(vec![1, 2, 3], 1).hash(),
+            Leaf::<32, Sha256>::new(vec![1, 2, 3], 1).hash()
+        );
+    }
+
     #[test]
     fn test_new_leaf() {
         let leaf = Node::<32, Sha256>::new_leaf(vec![1, 2, 3], 1);
@@ -0,0 +1,238 @@
+//! Starknet hashers, to verify the roots and proofs of a tree in Cairo.
+//!
+//! The nodes are encoded into field elements (felts) and hashed with the Starknet array
+//! hashes:
+//! - a branch is `[left, right, ..sum]`
+//! - a leaf is `[..value, ..sum]`, an empty leaf having an empty value
+//!
+//! where:
+//! - `left` and `right` are the hashes of the children, read as big-endian felts
+//! - `value` is serialized like a Cairo `ByteArray`: the number of full 31-byte words, the
+//!   full words, the remaining bytes as a word and their number
+//! - `sum` is serialized like a Cairo integer: one felt up to `u128`, and otherwise its
+//!   128-bit limbs from the least significant one, so a `U256` is `[low, high]`
+//!
+//! The hashes are the big-endian encoding of the resulting felt. Bytes that aren't the
+//! encoding of a felt, which no node hashes to, are reduced modulo the field prime.
+
+use starknet_types_core::{
+    felt::Felt,
+    hash::{self, StarkHash},
+};
+
+use super::Hasher;
+
+/// Number of bytes of a full `ByteArray` word.
+const WORD_SIZE: usize = 31;
+
+/// Number of bytes of a sum limb.
+const LIMB_SIZE: usize = 16;
+
+/// Appends the Cairo `ByteArray` serialization of `bytes` to `felts`.
+fn push_bytes(felts: &mut Vec<Felt>, bytes: &[u8]) {
+    let words = bytes.chunks_exact(WORD_SIZE);
+    let pending = words.remainder();
+    felts.push(Felt::from(words.len()));
+    felts.extend(words.map(Felt::from_bytes_be_slice));
+    felts.push(Felt::from_bytes_be_slice(pending));
+    felts.push(Felt::from(pending.len()));
+}
+
+/// Appends the Cairo serialization of the big-endian `sum` to `felts`.
+fn push_sum(felts: &mut Vec<Felt>, sum: &[u8]) {
+    felts.extend(sum.rchunks(LIMB_SIZE).map(Felt::from_bytes_be_slice));
+}
+
+fn hash_bytes<T: StarkHash>(data: &[u8]) -> [u8; 32] {
+    let mut felts = Vec::new();
+    push_bytes(&mut felts, data);
+    T::hash_array(&felts).to_bytes_be()
+}
+
+fn hash_branch<T: StarkHash>(left: &[u8; 32], right: &[u8; 32], sum: &[u8]) -> [u8; 32] {
+    let mut felts = vec![Felt::from_bytes_be(left), Felt::from_bytes_be(right)];
+    push_sum(&mut felts, sum);
+    T::hash_array(&felts).to_bytes_be()
+}
+
+fn hash_leaf<T: StarkHash>(value: &[u8], sum: &[u8]) -> [u8; 32] {
+    let mut felts = Vec::new();
+    push_bytes(&mut felts, value);
+    push_sum(&mut felts, sum);
+    T::hash_array(&felts).to_bytes_be()
+}
+
+/// Starknet Poseidon hash, `poseidon_hash_span` in Cairo.
+#[cfg(feature = "poseidon")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Poseidon;
+
+#[cfg(feature = "poseidon")]
+impl Hasher<32> for Poseidon {
+    /// Hashes `data` serialized like a Cairo `ByteArray`.
+    fn hash(data: &[u8]) -> [u8; 32] {
+        hash_bytes::<hash::Poseidon>(data)
+    }
+
+    fn hash_branch(left: &[u8; 32], right: &[u8; 32], sum: &[u8]) -> [u8; 32] {
+        hash_branch::<hash::Poseidon>(left, right, sum)
+    }
+
+    fn hash_leaf(value: &[u8], sum: &[u8]) -> [u8; 32] {
+        hash_leaf::<hash::Poseidon>(value, sum)
+    }
+}
+
+/// Starknet Pedersen array hash, `compute_hash_on_elements` in Cairo: the Pedersen hashes
+/// chained from `0` over the elements, then with their number.
+#[cfg(feature = "pedersen")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pedersen;
+
+#[cfg(feature = "pedersen")]
+impl Hasher<32> for Pedersen {
+    /// Hashes `data` serialized like a Cairo `ByteArray`.
+    fn hash(data: &[u8]) -> [u8; 32] {
+        hash_bytes::<hash::Pedersen>(data)
+    }
+
+    fn hash_branch(left: &[u8; 32], right: &[u8; 32], sum: &[u8]) -> [u8; 32] {
+        hash_branch::<hash::Pedersen>(left, right, sum)
+    }
+
+    fn hash_leaf(value: &[u8], sum: &[u8]) -> [u8; 32] {
+        hash_leaf::<hash::Pedersen>(value, sum)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{push_bytes, push_sum};
+    use crate::{verify_merkle_proof, Hasher, Leaf, MemoryDb, ThreadSafe, MSSMT};
+    use starknet_types_core::felt::Felt;
+
+    fn felt(hex: &str) -> Felt {
+        Felt::from_hex(hex).unwrap()
+    }
+
+    fn felt_bytes(hex: &str) -> [u8; 32] {
+        felt(hex).to_bytes_be()
+    }
+
+    #[test]
+    fn test_felt_encoding() {
+        let mut felts = Vec::new();
+        push_bytes(&mut felts, &[]);
+        assert_eq!(felts, [Felt::ZERO, Felt::ZERO, Felt::ZERO]);
+
+        // A full word and a pending "!".
+        let mut felts = Vec::new();
+        push_bytes(&mut felts, b"Starknet is a validity rollup.!!");
+        assert_eq!(
+            felts,
+            [
+                Felt::ONE,
+                Felt::from_bytes_be_slice(b"Starknet is a validity rollup.!"),
+                felt("0x21"),
+                Felt::ONE,
+            ]
+        );
+
+        let mut felts = Vec::new();
+        push_sum(&mut felts, &258u64.to_be_bytes());
+        push_sum(&mut felts, &u128::MAX.to_be_bytes());
+        let mut u256 = [0; 32];
+        u256[15] = 1;
+        u256[31] = 2;
+        push_sum(&mut felts, &u256);
+        assert_eq!(
+            felts,
+            [
+                felt("0x102"),
+                Felt::from(u128::MAX),
+                felt("0x2"),
+                felt("0x1")
+            ]
+        );
+    }
+
+    /// Reference vectors of `poseidon_hash_many` and `compute_hash_on_elements` from
+    /// cairo-lang, a branch being hashed like the array of its children and sum.
+    #[cfg(feature = "poseidon")]
+    #[test]
+    fn test_poseidon_vectors() {
+        use super::Poseidon;
+
+        assert_eq!(
+            Poseidon::hash_branch(
+                &felt_bytes("0xaa"),
+                &felt_bytes("0xbb"),
+                &0xccu64.to_be_bytes()
+            ),
+            felt_bytes("0x2742e049f7e1613e4a014efeec0d742882a798ae0af8b8dd730358c23848775")
+        );
+        // `[0, "hello", 5, 1]` for a "hello" leaf with a sum of 1.
+        assert_eq!(
+            Poseidon::hash_leaf(b"hello", &1u64.to_be_bytes()),
+            felt_bytes("0x7445601869463e702dacc6d9cfdec903e160b0140a335de86cd1c667f4c6509")
+        );
+        assert_eq!(
+            Poseidon::hash(b"hello"),
+            felt_bytes("0x30c616199236e6ead87ae6931d750794c9702f3604ed1c1006f60f01d129aea")
+        );
+    }
+
+    #[cfg(feature = "pedersen")]
+    #[test]
+    fn test_pedersen_vectors() {
+        use super::Pedersen;
+
+        assert_eq!(
+            Pedersen::hash_branch(
+                &felt_bytes("0xaa"),
+                &felt_bytes("0xbb"),
+                &0xccu64.to_be_bytes()
+            ),
+            felt_bytes("0x10808e8929644950878c4f71326e47c6b584d9cfea2de0415daf8def0f5e89f")
+        );
+        assert_eq!(
+            Pedersen::hash_leaf(b"hello", &1u64.to_be_bytes()),
+            felt_bytes("0x1307dc855208789661e01e1fa34bd947cb18d30bcd096d3798205e896d15a7b")
+        );
+    }
+
+    /// Builds a tree and checks that its nodes and proofs are hashed into felts.
+    fn test_tree<H: Hasher<32> + Clone + ThreadSafe>() {
+        let mut tree = MSSMT::<32, H, ()>::new(Box::new(MemoryDb::new()));
+        for i in 1..=4u8 {
+            tree.insert([i; 32], Leaf::new(vec![i; 40], i as u64))
+                .unwrap();
+        }
+        let root = tree.root().unwrap();
+        assert_eq!(root.sum(), 10);
+        assert_eq!(Felt::from_bytes_be(&root.hash()).to_bytes_be(), root.hash());
+        let proof = tree.merkle_proof([2; 32]).unwrap();
+        let verify = |sum| {
+            verify_merkle_proof::<32, H, (), _>(
+                [2; 32],
+                Leaf::new(vec![2; 40], sum),
+                proof.clone(),
+                root.hash(),
+            )
+        };
+        assert_eq!(verify(2), Ok(()));
+        assert!(verify(3).is_err());
+    }
+
+    #[cfg(feature = "poseidon")]
+    #[test]
+    fn test_poseidon_tree() {
+        test_tree::<super::Poseidon>();
+    }
+
+    #[cfg(feature = "pedersen")]
+    #[test]
+    fn test_pedersen_tree() {
+        test_tree::<super::Pedersen>();
+    }
+}
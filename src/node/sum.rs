@@ -0,0 +1,203 @@
+use std::fmt::{Debug, Display};
+
+/// Integer type used for the sums of the tree.
+///
+/// The sums are committed to in the node hashes with their big-endian encoding, so the
+/// default `u64` produces the same hashes as the Go implementation.
+pub trait Sum: Copy + Debug + Display + Default + PartialEq + Eq + Send + Sync + 'static {
+    /// Size of the big-endian encoding in bytes.
+    const SIZE: usize;
+
+    /// Big-endian encoding of the sum.
+    type Bytes: AsRef<[u8]>;
+
+    /// Adds `rhs` to the sum, returning `None` on overflow.
+    fn checked_add(self, rhs: Self) -> Option<Self>;
+
+    /// Returns the big-endian encoding of the sum.
+    fn to_be_bytes(self) -> Self::Bytes;
+
+    /// Decodes a big-endian sum, returning `None` if `bytes` isn't [`Sum::SIZE`] long.
+    fn from_be_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+macro_rules! impl_sum {
+    ($($int:ty),*) => {
+        $(
+            impl Sum for $int {
+                const SIZE: usize = size_of::<$int>();
+
+                type Bytes = [u8; size_of::<$int>()];
+
+                fn checked_add(self, rhs: Self) -> Option<Self> {
+                    <$int>::checked_add(self, rhs)
+                }
+
+                fn to_be_bytes(self) -> Self::Bytes {
+                    <$int>::to_be_bytes(self)
+                }
+
+                fn from_be_bytes(bytes: &[u8]) -> Option<Self> {
+                    Some(<$int>::from_be_bytes(bytes.try_into().ok()?))
+                }
+            }
+        )*
+    };
+}
+
+impl_sum!(u64, u128);
+
+#[cfg(feature = "u256")]
+impl Sum for primitive_types::U256 {
+    const SIZE: usize = 32;
+
+    type Bytes = [u8; 32];
+
+    fn checked_add(self, rhs: Self) -> Option<Self> {
+        primitive_types::U256::checked_add(self, rhs)
+    }
+
+    fn to_be_bytes(self) -> Self::Bytes {
+        self.to_big_endian()
+    }
+
+    fn from_be_bytes(bytes: &[u8]) -> Option<Self> {
+        (bytes.len() == Self::SIZE).then(|| primitive_types::U256::from_big_endian(bytes))
+    }
+}
+
+/// One sum per asset, for trees that hold the balances of `N` assets at once.
+///
+/// Sums are added asset by asset and the node hashes commit to all of them: the encoding
+/// is the concatenation of the big-endian encoding of each sum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MultiSum<const N: usize, T: Sum = u64>([T; N]);
+
+impl<const N: usize, T: Sum> MultiSum<N, T> {
+    /// Creates a sum from the sum of each asset.
+    pub fn new(sums: [T; N]) -> Self {
+        Self(sums)
+    }
+
+    /// Returns the sum of the asset at `index`.
+    pub fn get(&self, index: usize) -> Option<T> {
+        self.0.get(index).copied()
+    }
+
+    /// Returns the sums of all the assets.
+    pub fn sums(&self) -> &[T; N] {
+        &self.0
+    }
+}
+
+impl<const N: usize, T: Sum> Default for MultiSum<N, T> {
+    fn default() -> Self {
+        Self([T::default(); N])
+    }
+}
+
+impl<const N: usize, T: Sum> From<[T; N]> for MultiSum<N, T> {
+    fn from(sums: [T; N]) -> Self {
+        Self(sums)
+    }
+}
+
+impl<const N: usize, T: Sum> Display for MultiSum<N, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[")?;
+        for (i, sum) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{sum}")?;
+        }
+        f.write_str("]")
+    }
+}
+
+impl<const N: usize, T: Sum> Sum for MultiSum<N, T> {
+    const SIZE: usize = N * T::SIZE;
+
+    type Bytes = Vec<u8>;
+
+    fn checked_add(self, rhs: Self) -> Option<Self> {
+        let mut sums = self.0;
+        for (sum, rhs) in sums.iter_mut().zip(rhs.0) {
+            *sum = sum.checked_add(rhs)?;
+        }
+        Some(Self(sums))
+    }
+
+    fn to_be_bytes(self) -> Self::Bytes {
+        let mut bytes = Vec::with_capacity(Self::SIZE);
+        for sum in self.0 {
+            bytes.extend_from_slice(sum.to_be_bytes().as_ref());
+        }
+        bytes
+    }
+
+    fn from_be_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::SIZE {
+            return None;
+        }
+        let mut sums = [T::default(); N];
+        for (sum, bytes) in sums.iter_mut().zip(bytes.chunks_exact(T::SIZE)) {
+            *sum = T::from_be_bytes(bytes)?;
+        }
+        Some(Self(sums))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MultiSum, Sum};
+
+    #[test]
+    fn test_sum_encoding() {
+        assert_eq!(Sum::to_be_bytes(258u64), [0, 0, 0, 0, 0, 0, 1, 2]);
+        assert_eq!(
+            <u64 as Sum>::from_be_bytes(&[0, 0, 0, 0, 0, 0, 1, 2]),
+            Some(258)
+        );
+        assert_eq!(<u128 as Sum>::from_be_bytes(&[1, 2]), None);
+        assert_eq!(
+            <u128 as Sum>::from_be_bytes(Sum::to_be_bytes(u128::MAX).as_ref()),
+            Some(u128::MAX)
+        );
+        assert_eq!(Sum::checked_add(u64::MAX, 1), None);
+    }
+
+    #[test]
+    fn test_multi_sum() {
+        let sum = MultiSum::new([1u64, u64::MAX, 3]);
+        assert_eq!(sum.to_string(), "[1, 18446744073709551615, 3]");
+        assert_eq!(
+            sum.checked_add(MultiSum::new([1, 0, 2])),
+            Some(MultiSum::new([2, u64::MAX, 5]))
+        );
+        // A single asset overflowing overflows the whole sum.
+        assert_eq!(sum.checked_add(MultiSum::new([0, 1, 0])), None);
+
+        let bytes = sum.to_be_bytes();
+        assert_eq!(bytes.len(), <MultiSum<3> as Sum>::SIZE);
+        assert_eq!(bytes[..8], 1u64.to_be_bytes());
+        assert_eq!(<MultiSum<3> as Sum>::from_be_bytes(&bytes), Some(sum));
+        assert_eq!(<MultiSum<3> as Sum>::from_be_bytes(&bytes[1..]), None);
+    }
+
+    #[cfg(feature = "u256")]
+    #[test]
+    fn test_u256_sum_encoding() {
+        use primitive_types::U256;
+
+        let sum = U256::from(u128::MAX) + U256::from(258);
+        let bytes = Sum::to_be_bytes(sum);
+        assert_eq!(
+            bytes[15..],
+            [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1]
+        );
+        assert_eq!(<U256 as Sum>::from_be_bytes(&bytes), Some(sum));
+        assert_eq!(<U256 as Sum>::from_be_bytes(&bytes[1..]), None);
+        assert_eq!(Sum::checked_add(U256::MAX, U256::one()), None);
+    }
+}
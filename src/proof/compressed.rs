@@ -0,0 +1,319 @@
+//! Compressed merkle proofs.
+//!
+//! Most of the siblings of a merkle proof are part of the empty tree. A compressed proof
+//! replaces them with a bit so only the non-empty siblings have to be sent over the wire.
+//! With the default `u64` sums, the encoding is the same as the one of
+//! `mssmt.CompressedProof` in taproot-assets.
+
+use std::marker::PhantomData;
+
+use crate::{
+    node::{Branch, ComputedNode, Hasher, Leaf, Node, Sum},
+    proof::Proof,
+    tree::{bit_index, EmptyTree},
+    DecodeError, TreeError,
+};
+
+/// A merkle proof where the siblings that are part of the empty tree are replaced by a bit.
+///
+/// # Type Parameters
+/// * `HASH_SIZE` - The size of the hash digest in bytes
+/// * `H` - The hasher implementation used for the tree
+/// * `S` - The type of the sums, `u64` by default
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressedProof<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum = u64> {
+    /// `true` if the sibling at this index is part of the empty tree. Starts from the leaf.
+    bits: Vec<bool>,
+    /// The non-empty siblings, starting from the leaf.
+    nodes: Vec<ComputedNode<HASH_SIZE, S>>,
+    _phantom: PhantomData<H>,
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum>
+    CompressedProof<HASH_SIZE, H, S>
+{
+    /// Number of siblings in a proof.
+    const LEVELS: usize = HASH_SIZE * 8;
+
+    /// Compresses a merkle proof as returned by `merkle_proof`.
+    pub fn compress(proof: &Proof<HASH_SIZE, H, S>) -> Self {
+        let empty_tree = EmptyTree::<HASH_SIZE, H, S>::empty_tree();
+        let mut bits = Vec::with_capacity(Self::LEVELS);
+        let mut nodes = Vec::new();
+        // A proof has a sibling per level, so the index stays within the empty tree.
+        for (i, node) in proof.nodes().iter().enumerate() {
+            // The proof starts at the leaf while the empty tree starts at the root.
+            if node.hash() == empty_tree[Self::LEVELS - i].hash() {
+                bits.push(true);
+            } else {
+                bits.push(false);
+                nodes.push(ComputedNode::new(node.hash(), node.sum()));
+            }
+        }
+        Self {
+            bits,
+            nodes,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the full merkle proof, ordered from the leaf to the root.
+    pub fn decompress(&self) -> Vec<Node<HASH_SIZE, H, S>> {
+        let empty_tree = EmptyTree::<HASH_SIZE, H, S>::empty_tree();
+        let mut nodes = self.nodes.iter();
+        self.bits
+            .iter()
+            .enumerate()
+            .map(|(i, &empty)| {
+                if empty {
+                    empty_tree[Self::LEVELS - i].clone()
+                } else {
+                    // The number of nodes always matches the number of unset bits.
+                    Node::Computed(nodes.next().cloned().expect("missing proof node"))
+                }
+            })
+            .collect()
+    }
+
+    /// Returns `true` for each sibling that is part of the empty tree, starting from the leaf.
+    pub fn bits(&self) -> &[bool] {
+        &self.bits
+    }
+
+    /// Returns the non-empty siblings, starting from the leaf.
+    pub fn nodes(&self) -> &[ComputedNode<HASH_SIZE, S>] {
+        &self.nodes
+    }
+
+    /// Encodes the proof. The number of nodes is written as a big endian `u16`, followed by
+    /// the hash and big endian sum of each node, followed by the bits packed in
+    /// `HASH_SIZE` bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes =
+            Vec::with_capacity(2 + self.nodes.len() * (HASH_SIZE + S::SIZE) + Self::LEVELS / 8);
+        bytes.extend_from_slice(&(self.nodes.len() as u16).to_be_bytes());
+        for node in &self.nodes {
+            bytes.extend_from_slice(&node.hash());
+            bytes.extend_from_slice(node.sum().to_be_bytes().as_ref());
+        }
+        let mut packed = vec![0u8; Self::LEVELS / 8];
+        for (i, _) in self.bits.iter().enumerate().filter(|(_, &bit)| bit) {
+            packed[i / 8] |= 1 << (i % 8);
+        }
+        bytes.extend_from_slice(&packed);
+        bytes
+    }
+
+    /// Decodes a proof encoded with [`CompressedProof::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (num_nodes, mut rest) = split(bytes, 2)?;
+        let num_nodes = u16::from_be_bytes([num_nodes[0], num_nodes[1]]) as usize;
+        if num_nodes > Self::LEVELS {
+            return Err(DecodeError::InvalidNodeCount);
+        }
+
+        let mut nodes = Vec::with_capacity(num_nodes);
+        for _ in 0..num_nodes {
+            let (hash, tail) = split(rest, HASH_SIZE)?;
+            let (sum, tail) = split(tail, S::SIZE)?;
+            nodes.push(ComputedNode::new(
+                hash.try_into().expect("slice has HASH_SIZE bytes"),
+                S::from_be_bytes(sum).expect("slice has the size of a sum"),
+            ));
+            rest = tail;
+        }
+
+        let (packed, rest) = split(rest, Self::LEVELS / 8)?;
+        if !rest.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+        let bits: Vec<bool> = (0..Self::LEVELS)
+            .map(|i| (packed[i / 8] >> (i % 8)) & 1 == 1)
+            .collect();
+        if bits.iter().filter(|&&empty| !empty).count() != nodes.len() {
+            return Err(DecodeError::InvalidNodeCount);
+        }
+
+        Ok(Self {
+            bits,
+            nodes,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+/// Splits `n` bytes off the front of `bytes`.
+fn split(bytes: &[u8], n: usize) -> Result<(&[u8], &[u8]), DecodeError> {
+    if bytes.len() < n {
+        return Err(DecodeError::UnexpectedEof);
+    }
+    Ok(bytes.split_at(n))
+}
+
+/// Verify a compressed merkle proof for a given key without decompressing it.
+///
+/// # Arguments
+///
+/// * `key` - The key of the node to verify the proof for
+/// * `leaf` - The leaf node to verify the proof for
+/// * `proof` - The compressed proof to verify
+/// * `root_hash` - The expected root of the tree
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the proof is valid, otherwise returns an error.
+pub fn verify_compressed_merkle_proof<
+    const HASH_SIZE: usize,
+    H: Hasher<HASH_SIZE> + Clone,
+    DbError,
+    S: Sum,
+>(
+    key: [u8; HASH_SIZE],
+    leaf: Leaf<HASH_SIZE, H, S>,
+    proof: &CompressedProof<HASH_SIZE, H, S>,
+    root_hash: [u8; HASH_SIZE],
+) -> Result<(), TreeError<DbError>> {
+    let levels = CompressedProof::<HASH_SIZE, H, S>::LEVELS;
+    if proof.bits.len() != levels {
+        return Err(TreeError::InvalidMerkleProof);
+    }
+    let empty_tree = EmptyTree::<HASH_SIZE, H, S>::empty_tree();
+    let mut nodes = proof.nodes.iter();
+    let mut current = Node::Leaf(leaf);
+    for (i, &empty) in proof.bits.iter().enumerate() {
+        let sibling = if empty {
+            empty_tree[levels - i].clone()
+        } else {
+            Node::Computed(nodes.next().ok_or(TreeError::InvalidMerkleProof)?.clone())
+        };
+        // Order the children based on the path
+        let height = levels - 1 - i;
+        let parent = if bit_index(height, &key) == 0 {
+            Branch::try_new(current, sibling)?
+        } else {
+            Branch::try_new(sibling, current)?
+        };
+        // Only the hash and the sum are needed to keep walking up.
+        current = Node::Computed(ComputedNode::new(parent.hash(), parent.sum()));
+    }
+    if current.hash() == root_hash {
+        Ok(())
+    } else {
+        Err(TreeError::InvalidMerkleProof)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{verify_compressed_merkle_proof, CompressedProof};
+    use crate::{CompactMSSMT, DecodeError, Leaf, MemoryDb, TreeError, MSSMT};
+    use sha2::Sha256;
+
+    #[test]
+    fn test_compress_empty_siblings() {
+        let mut mssmt = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        mssmt.insert([1; 32], Leaf::new(vec![1; 32], 1)).unwrap();
+        let proof = mssmt.merkle_proof([1; 32]).unwrap();
+        let compressed = proof.compress();
+        assert!(compressed.bits().iter().all(|&empty| empty));
+        assert!(compressed.nodes().is_empty());
+        // No nodes and all the bits set.
+        let mut expected = vec![0, 0];
+        expected.extend_from_slice(&[0xff; 32]);
+        assert_eq!(compressed.to_bytes(), expected);
+    }
+
+    #[test]
+    fn test_compressed_proof_encoding() {
+        let mut mssmt = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        let leaf1 = Leaf::new(vec![1; 32], 1);
+        let leaf2 = Leaf::new(vec![2; 32], 2);
+        // Those keys only differ on the first bit so the only non-empty sibling of
+        // `[0; 32]` is right below the root.
+        let mut key2 = [0; 32];
+        key2[0] = 1;
+        mssmt.insert([0; 32], leaf1).unwrap();
+        mssmt.insert(key2, leaf2.clone()).unwrap();
+
+        let proof = mssmt.merkle_proof([0; 32]).unwrap();
+        let compressed = proof.compress();
+        assert_eq!(compressed.nodes().len(), 1);
+        assert!(!compressed.bits()[255]);
+
+        let bytes = compressed.to_bytes();
+        assert_eq!(bytes.len(), 2 + 32 + 8 + 32);
+        assert_eq!(bytes[..2], [0, 1]);
+        assert_eq!(bytes[2..34], proof.nodes()[255].hash());
+        assert_eq!(bytes[34..42], 2u64.to_be_bytes());
+        assert_eq!(bytes[42..73], [0xff; 31]);
+        assert_eq!(bytes[73], 0x7f);
+
+        let decoded = CompressedProof::<32, Sha256>::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.bits(), compressed.bits());
+        assert_eq!(decoded.nodes(), compressed.nodes());
+        assert_eq!(
+            decoded
+                .decompress()
+                .iter()
+                .map(|node| node.hash())
+                .collect::<Vec<_>>(),
+            proof
+                .nodes()
+                .iter()
+                .map(|node| node.hash())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_compressed_proof_decode_malformed() {
+        let mut mssmt = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        mssmt.insert([1; 32], Leaf::new(vec![1; 32], 1)).unwrap();
+        mssmt.insert([2; 32], Leaf::new(vec![2; 32], 2)).unwrap();
+        let bytes = mssmt.merkle_proof([1; 32]).unwrap().compress().to_bytes();
+
+        for len in 0..bytes.len() {
+            assert_eq!(
+                CompressedProof::<32, Sha256>::from_bytes(&bytes[..len]).unwrap_err(),
+                DecodeError::UnexpectedEof
+            );
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            CompressedProof::<32, Sha256>::from_bytes(&trailing).unwrap_err(),
+            DecodeError::TrailingBytes
+        );
+        // Flag the non-empty sibling as empty.
+        let mut missing_bit = bytes.clone();
+        *missing_bit.last_mut().unwrap() = 0xff;
+        assert_eq!(
+            CompressedProof::<32, Sha256>::from_bytes(&missing_bit).unwrap_err(),
+            DecodeError::InvalidNodeCount
+        );
+    }
+
+    #[test]
+    fn test_verify_compressed_merkle_proof() {
+        let mut mssmt = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        let leaf1 = Leaf::new(vec![1; 32], 1);
+        let leaf2 = Leaf::new(vec![2; 32], 2);
+        mssmt.insert([1; 32], leaf1.clone()).unwrap();
+        mssmt.insert([2; 32], leaf2.clone()).unwrap();
+        let root = mssmt.root().unwrap().hash();
+
+        let proof = mssmt.merkle_proof([1; 32]).unwrap().compress();
+        verify_compressed_merkle_proof::<32, Sha256, (), _>([1; 32], leaf1.clone(), &proof, root)
+            .unwrap();
+        assert_eq!(
+            verify_compressed_merkle_proof::<32, Sha256, (), _>([1; 32], leaf2, &proof, root)
+                .unwrap_err(),
+            TreeError::InvalidMerkleProof
+        );
+        assert_eq!(
+            verify_compressed_merkle_proof::<32, Sha256, (), _>([2; 32], leaf1, &proof, root)
+                .unwrap_err(),
+            TreeError::InvalidMerkleProof
+        );
+    }
+}
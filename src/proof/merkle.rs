@@ -0,0 +1,261 @@
+//! Merkle proofs.
+
+use std::sync::Arc;
+
+use crate::{
+    node::{Branch, Hasher, Leaf, Node, Sum},
+    tree::walk_up,
+    DecodeError, TreeError,
+};
+
+use super::CompressedProof;
+
+/// A merkle proof: the siblings of every node on the path from a leaf to the root.
+///
+/// # Type Parameters
+/// * `HASH_SIZE` - The size of the hash digest in bytes
+/// * `H` - The hasher implementation used for the tree
+/// * `S` - The type of the sums, `u64` by default
+#[derive(Debug, Clone)]
+pub struct Proof<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum = u64> {
+    /// Siblings ordered from the leaf to the root.
+    nodes: Vec<Node<HASH_SIZE, H, S>>,
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Proof<HASH_SIZE, H, S> {
+    /// Version of the binary encoding produced by [`Proof::to_bytes`].
+    pub const VERSION: u8 = 1;
+
+    /// Creates a proof from the siblings of a leaf, ordered from the leaf to the root.
+    ///
+    /// Returns [`TreeError::InvalidMerkleProof`] if there isn't one sibling per level of the
+    /// tree, i.e. `HASH_SIZE * 8` of them.
+    pub fn new<DbError>(nodes: Vec<Node<HASH_SIZE, H, S>>) -> Result<Self, TreeError<DbError>> {
+        if nodes.len() != HASH_SIZE * 8 {
+            return Err(TreeError::InvalidMerkleProof);
+        }
+        Ok(Self { nodes })
+    }
+
+    /// Returns the siblings, ordered from the leaf to the root.
+    pub fn nodes(&self) -> &[Node<HASH_SIZE, H, S>] {
+        &self.nodes
+    }
+
+    /// Consumes the proof and returns its siblings, ordered from the leaf to the root.
+    pub fn into_nodes(self) -> Vec<Node<HASH_SIZE, H, S>> {
+        self.nodes
+    }
+
+    /// Computes the root of the tree from `leaf` at `key` and the proof.
+    ///
+    /// The proof is valid if the hash of the returned root is the expected one. The sum of
+    /// the root is then the total committed to by the tree, e.g. the per-asset totals of a
+    /// tree of [`MultiSum`](crate::MultiSum).
+    pub fn root<DbError>(
+        &self,
+        key: [u8; HASH_SIZE],
+        leaf: Leaf<HASH_SIZE, H, S>,
+    ) -> Result<Branch<HASH_SIZE, H, S>, TreeError<DbError>> {
+        walk_up(
+            key,
+            leaf,
+            self.nodes.iter().cloned().map(Arc::new).collect(),
+            |_, _, _, _| {},
+        )
+    }
+
+    /// Compresses the proof by removing the siblings that are part of the empty tree.
+    pub fn compress(&self) -> CompressedProof<HASH_SIZE, H, S> {
+        CompressedProof::compress(self)
+    }
+
+    /// Encodes the proof. The encoding starts with a version byte followed by the
+    /// encoding of the [`CompressedProof`]. Decoded siblings are [`Node::Computed`] nodes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![Self::VERSION];
+        bytes.extend_from_slice(&self.compress().to_bytes());
+        bytes
+    }
+
+    /// Decodes a proof encoded with [`Proof::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (&version, rest) = bytes.split_first().ok_or(DecodeError::UnexpectedEof)?;
+        if version != Self::VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        Ok(CompressedProof::from_bytes(rest)?.into())
+    }
+}
+
+/// Checks the number of siblings like [`Proof::new`].
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum>
+    TryFrom<Vec<Node<HASH_SIZE, H, S>>> for Proof<HASH_SIZE, H, S>
+{
+    type Error = TreeError<()>;
+
+    fn try_from(nodes: Vec<Node<HASH_SIZE, H, S>>) -> Result<Self, Self::Error> {
+        Self::new(nodes)
+    }
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum>
+    From<CompressedProof<HASH_SIZE, H, S>> for Proof<HASH_SIZE, H, S>
+{
+    fn from(proof: CompressedProof<HASH_SIZE, H, S>) -> Self {
+        // A compressed proof has a bit per level, so it decompresses to a valid proof.
+        Self {
+            nodes: proof.decompress(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use std::fmt;
+    use std::marker::PhantomData;
+
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Proof;
+    use crate::node::{Hasher, Sum};
+
+    /// Human readable formats get the binary encoding as a hex string, the others as bytes.
+    impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Serialize
+        for Proof<HASH_SIZE, H, S>
+    {
+        fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+            if serializer.is_human_readable() {
+                serializer.serialize_str(&hex::encode(self.to_bytes()))
+            } else {
+                serializer.serialize_bytes(&self.to_bytes())
+            }
+        }
+    }
+
+    impl<'de, const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Deserialize<'de>
+        for Proof<HASH_SIZE, H, S>
+    {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct ProofVisitor<const HASH_SIZE: usize, H, S>(PhantomData<(H, S)>);
+
+            impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> de::Visitor<'_>
+                for ProofVisitor<HASH_SIZE, H, S>
+            {
+                type Value = Proof<HASH_SIZE, H, S>;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    f.write_str("an encoded merkle proof")
+                }
+
+                fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                    Proof::from_bytes(v).map_err(E::custom)
+                }
+
+                fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                    let bytes = hex::decode(v).map_err(E::custom)?;
+                    self.visit_bytes(&bytes)
+                }
+            }
+
+            if deserializer.is_human_readable() {
+                deserializer.deserialize_str(ProofVisitor(PhantomData))
+            } else {
+                deserializer.deserialize_bytes(ProofVisitor(PhantomData))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Proof;
+    use crate::{
+        verify_merkle_proof, CompactMSSMT, DecodeError, EmptyTree, Leaf, MemoryDb, TreeError,
+    };
+    use sha2::Sha256;
+
+    fn proof_and_root() -> (Proof<32, Sha256>, [u8; 32]) {
+        let mut mssmt = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        mssmt.insert([1; 32], Leaf::new(vec![1; 32], 1)).unwrap();
+        mssmt.insert([2; 32], Leaf::new(vec![2; 32], 2)).unwrap();
+        (
+            mssmt.merkle_proof([1; 32]).unwrap(),
+            mssmt.root().unwrap().hash(),
+        )
+    }
+
+    #[test]
+    fn test_proof_round_trip() {
+        let (proof, root) = proof_and_root();
+        let bytes = proof.to_bytes();
+        assert_eq!(bytes[0], Proof::<32, Sha256>::VERSION);
+        assert_eq!(bytes[1..], proof.compress().to_bytes());
+
+        let decoded = Proof::<32, Sha256>::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            decoded.nodes().iter().map(|n| n.hash()).collect::<Vec<_>>(),
+            proof.nodes().iter().map(|n| n.hash()).collect::<Vec<_>>()
+        );
+        verify_merkle_proof::<32, Sha256, (), _>([1; 32], Leaf::new(vec![1; 32], 1), decoded, root)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_proof_length() {
+        let (proof, _) = proof_and_root();
+        let empty_leaf = EmptyTree::<32, Sha256>::empty_tree()[256].clone();
+        for len in [0, 255, 257] {
+            let nodes = vec![empty_leaf.clone(); len];
+            assert_eq!(
+                Proof::<32, Sha256>::new::<()>(nodes.clone()).unwrap_err(),
+                TreeError::InvalidMerkleProof
+            );
+            assert_eq!(
+                Proof::<32, Sha256>::try_from(nodes).unwrap_err(),
+                TreeError::InvalidMerkleProof
+            );
+        }
+        let decoded = Proof::<32, Sha256>::new::<()>(proof.clone().into_nodes()).unwrap();
+        assert_eq!(decoded.to_bytes(), proof.to_bytes());
+        let converted: Proof<32, Sha256> = proof.clone().into_nodes().try_into().unwrap();
+        assert_eq!(converted.to_bytes(), proof.to_bytes());
+    }
+
+    #[test]
+    fn test_proof_decode_malformed() {
+        let (proof, _) = proof_and_root();
+        let bytes = proof.to_bytes();
+        for len in 0..bytes.len() {
+            assert_eq!(
+                Proof::<32, Sha256>::from_bytes(&bytes[..len]).unwrap_err(),
+                DecodeError::UnexpectedEof
+            );
+        }
+        let mut unknown_version = bytes.clone();
+        unknown_version[0] = 2;
+        assert_eq!(
+            Proof::<32, Sha256>::from_bytes(&unknown_version).unwrap_err(),
+            DecodeError::UnsupportedVersion(2)
+        );
+        let mut too_many_nodes = bytes.clone();
+        too_many_nodes[1..3].copy_from_slice(&u16::MAX.to_be_bytes());
+        assert_eq!(
+            Proof::<32, Sha256>::from_bytes(&too_many_nodes).unwrap_err(),
+            DecodeError::InvalidNodeCount
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_proof_serde() {
+        let (proof, _) = proof_and_root();
+        let json = serde_json::to_string(&proof).unwrap();
+        assert_eq!(json, format!("\"{}\"", hex::encode(proof.to_bytes())));
+        let decoded: Proof<32, Sha256> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.to_bytes(), proof.to_bytes());
+
+        assert!(serde_json::from_str::<Proof<32, Sha256>>("\"01\"").is_err());
+        assert!(serde_json::from_str::<Proof<32, Sha256>>("\"zz\"").is_err());
+    }
+}
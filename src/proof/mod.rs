@@ -0,0 +1,9 @@
+//! Merkle proof types for the Merkle Sum Sparse Merkle Tree
+
+mod compressed;
+mod merkle;
+mod non_inclusion;
+
+pub use compressed::{verify_compressed_merkle_proof, CompressedProof};
+pub use merkle::Proof;
+pub use non_inclusion::{verify_non_inclusion_proof, NonInclusionProof};
@@ -0,0 +1,174 @@
+//! Non-inclusion proofs.
+//!
+//! A key is not in the tree if the leaf at that key is the empty leaf. A non-inclusion
+//! proof is the merkle proof of the empty leaf at that key.
+
+use crate::{
+    node::{EmptyLeaf, Hasher, Leaf, Sum},
+    TreeError,
+};
+
+use super::{CompressedProof, Proof};
+
+/// Proof that a key is not in the tree.
+///
+/// # Type Parameters
+/// * `HASH_SIZE` - The size of the hash digest in bytes
+/// * `H` - The hasher implementation used for the tree
+/// * `S` - The type of the sums, `u64` by default
+#[derive(Debug, Clone)]
+pub struct NonInclusionProof<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum = u64> {
+    /// Merkle proof of the empty leaf.
+    proof: Proof<HASH_SIZE, H, S>,
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum>
+    NonInclusionProof<HASH_SIZE, H, S>
+{
+    /// Creates a non-inclusion proof from the merkle proof of the empty leaf.
+    pub fn new(proof: Proof<HASH_SIZE, H, S>) -> Self {
+        Self { proof }
+    }
+
+    /// Returns the merkle proof of the empty leaf.
+    pub fn proof(&self) -> &Proof<HASH_SIZE, H, S> {
+        &self.proof
+    }
+
+    /// Compresses the proof to send it over the wire.
+    pub fn compress(&self) -> CompressedProof<HASH_SIZE, H, S> {
+        self.proof.compress()
+    }
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum>
+    From<CompressedProof<HASH_SIZE, H, S>> for NonInclusionProof<HASH_SIZE, H, S>
+{
+    fn from(proof: CompressedProof<HASH_SIZE, H, S>) -> Self {
+        Self::new(proof.into())
+    }
+}
+
+/// Verify that a key is not in the tree.
+///
+/// # Arguments
+///
+/// * `key` - The key that should be absent from the tree
+/// * `proof` - The non-inclusion proof for the key
+/// * `root_hash` - The expected root of the tree
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the empty leaf is at `key` in the tree, otherwise returns an error.
+pub fn verify_non_inclusion_proof<
+    const HASH_SIZE: usize,
+    H: Hasher<HASH_SIZE> + Clone,
+    DbError,
+    S: Sum,
+>(
+    key: [u8; HASH_SIZE],
+    proof: &NonInclusionProof<HASH_SIZE, H, S>,
+    root_hash: [u8; HASH_SIZE],
+) -> Result<(), TreeError<DbError>> {
+    // Compute the root from the empty leaf and the proof
+    let got_root = proof.proof.root(key, Leaf::Empty(EmptyLeaf::new()))?;
+    if got_root.hash() == root_hash {
+        Ok(())
+    } else {
+        Err(TreeError::InvalidMerkleProof)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{verify_non_inclusion_proof, NonInclusionProof};
+    use crate::{CompactMSSMT, Leaf, MemoryDb, TreeError, MSSMT};
+    use hex_literal::hex;
+    use sha2::Sha256;
+
+    #[test]
+    fn test_non_inclusion_proof() {
+        let mut mssmt = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        let mut compact_mssmt = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        for (key, leaf) in [
+            ([1; 32], Leaf::new(vec![1; 32], 1)),
+            ([2; 32], Leaf::new(vec![2; 32], 2)),
+        ] {
+            mssmt.insert(key, leaf.clone()).unwrap();
+            compact_mssmt.insert(key, leaf).unwrap();
+        }
+        let root = mssmt.root().unwrap().hash();
+
+        let proof = mssmt.non_inclusion_proof([3; 32]).unwrap();
+        verify_non_inclusion_proof::<32, Sha256, (), _>([3; 32], &proof, root).unwrap();
+        let proof = compact_mssmt.non_inclusion_proof([3; 32]).unwrap();
+        verify_non_inclusion_proof::<32, Sha256, (), _>([3; 32], &proof, root).unwrap();
+
+        // Present keys have no non-inclusion proof.
+        assert_eq!(
+            mssmt.non_inclusion_proof([1; 32]).unwrap_err(),
+            TreeError::ExpectedEmptyLeaf
+        );
+        assert_eq!(
+            compact_mssmt.non_inclusion_proof([1; 32]).unwrap_err(),
+            TreeError::ExpectedEmptyLeaf
+        );
+    }
+
+    #[test]
+    fn test_non_inclusion_proof_compact_leaf_on_path() {
+        // Both keys share the first 254 bits so the path of `key2` ends on the compact
+        // leaf of `key1` far above the last level.
+        let key1 = hex!("0000000000000000000000000000000000000000000000000000000000000000");
+        let key2 = hex!("0000000000000000000000000000000000000000000000000000000000000040");
+        let mut mssmt = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        let mut compact_mssmt = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        mssmt.insert(key1, Leaf::new(vec![1; 32], 1)).unwrap();
+        compact_mssmt
+            .insert(key1, Leaf::new(vec![1; 32], 1))
+            .unwrap();
+        let root = compact_mssmt.root().unwrap().hash();
+
+        let proof = compact_mssmt.non_inclusion_proof(key2).unwrap();
+        verify_non_inclusion_proof::<32, Sha256, (), _>(key2, &proof, root).unwrap();
+        let expected = mssmt.non_inclusion_proof(key2).unwrap();
+        assert_eq!(
+            proof
+                .proof()
+                .nodes()
+                .iter()
+                .map(|n| n.hash())
+                .collect::<Vec<_>>(),
+            expected
+                .proof()
+                .nodes()
+                .iter()
+                .map(|n| n.hash())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_non_inclusion_proof_rejects_present_key() {
+        let mut mssmt = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        mssmt.insert([1; 32], Leaf::new(vec![1; 32], 1)).unwrap();
+        mssmt.insert([2; 32], Leaf::new(vec![2; 32], 2)).unwrap();
+        let root = mssmt.root().unwrap().hash();
+
+        // The inclusion proof of a present key doesn't prove its absence.
+        let proof = NonInclusionProof::new(mssmt.merkle_proof([1; 32]).unwrap());
+        assert_eq!(
+            verify_non_inclusion_proof::<32, Sha256, (), _>([1; 32], &proof, root).unwrap_err(),
+            TreeError::InvalidMerkleProof
+        );
+        // Neither does a valid proof for another key.
+        let proof = mssmt.non_inclusion_proof([3; 32]).unwrap();
+        assert_eq!(
+            verify_non_inclusion_proof::<32, Sha256, (), _>([1; 32], &proof, root).unwrap_err(),
+            TreeError::InvalidMerkleProof
+        );
+        // Round trip through the compressed encoding.
+        let proof = NonInclusionProof::from(proof.compress());
+        verify_non_inclusion_proof::<32, Sha256, (), _>([3; 32], &proof, root).unwrap();
+    }
+}
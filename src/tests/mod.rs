@@ -1 +1,24 @@
+use crate::node::{Hasher, Leaf};
+
 mod tree;
+
+/// Leaves of the hasher independent tests, the last one having a random key and value.
+pub(crate) fn test_leaves<H: Hasher<32> + Clone>() -> [([u8; 32], Leaf<32, H>); 4] {
+    let leaf4 = Leaf::new(
+        vec![
+            2, 140, 120, 40, 192, 9, 98, 114, 244, 120, 64, 72, 171, 79, 80, 112, 181, 15, 155, 49,
+            210, 19, 22, 216, 74, 168, 143, 149, 16, 184, 63, 25, 192,
+        ],
+        4,
+    );
+    let key4 = [
+        177_u8, 231, 231, 200, 71, 83, 63, 150, 221, 247, 213, 231, 188, 27, 190, 148, 112, 218,
+        129, 131, 93, 195, 197, 44, 143, 203, 191, 17, 154, 100, 103, 100,
+    ];
+    [
+        ([1; 32], Leaf::new([1; 32].to_vec(), 1)),
+        ([2; 32], Leaf::new([2; 32].to_vec(), 2)),
+        ([3; 32], Leaf::new([3; 32].to_vec(), 3)),
+        (key4, leaf4),
+    ]
+}
@@ -1,13 +1,22 @@
 //! Tests for the Merkle Sum Sparse Merkle Tree implementation
 
+use std::{
+    any::Any,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
 use hex_literal::hex;
-use sha2::Sha256;
+use sha2::{Digest, Sha256, Sha512};
 
+use super::test_leaves;
 use crate::{
     node::{Branch, CompactLeaf, Hasher, Leaf, Node},
-    tree::CompactMSSMT,
-    tree::MSSMT,
-    Db, EmptyTree, MemoryDb, ThreadSafe,
+    tree::{bit_index, CompactMSSMT, MSSMT},
+    verify_compressed_merkle_proof, verify_merkle_proof, verify_non_inclusion_proof, Db, EmptyTree,
+    MemoryDb, MultiSum, NonInclusionProof, Proof, Sum, ThreadSafe, TreeError,
 };
 
 #[test]
@@ -19,107 +28,155 @@ fn test_empty_tree() {
     );
 }
 
-#[test]
-fn test_leaves_insertion() {
-    let leaf1 = Leaf::new([1; 32].to_vec(), 1);
-    let leaf2 = Leaf::new([2; 32].to_vec(), 2);
-    let leaf3 = Leaf::new([3; 32].to_vec(), 3);
+/// Root of the tree holding `leaves`, built from the empty tree without the trees and the
+/// databases.
+fn expected_root<H: Hasher<32> + Clone>(leaves: &[([u8; 32], Leaf<32, H>)]) -> [u8; 32] {
+    fn subtree<H: Hasher<32> + Clone>(
+        height: usize,
+        leaves: &[&([u8; 32], Leaf<32, H>)],
+        empty_tree: &[Node<32, H>],
+    ) -> Node<32, H> {
+        match leaves {
+            [] => empty_tree[height].clone(),
+            [(_, leaf)] if height == 256 => Node::Leaf(leaf.clone()),
+            _ => {
+                let (left, right): (Vec<_>, Vec<_>) = leaves
+                    .iter()
+                    .partition(|(key, _)| bit_index(height, key) == 0);
+                Node::Branch(Branch::new(
+                    subtree(height + 1, &left, empty_tree),
+                    subtree(height + 1, &right, empty_tree),
+                ))
+            }
+        }
+    }
+    let leaves: Vec<_> = leaves.iter().collect();
+    subtree(0, &leaves, &EmptyTree::<32, H>::empty_tree()).hash()
+}
 
-    let leaf4 = Leaf::new(
-        vec![
-            2, 140, 120, 40, 192, 9, 98, 114, 244, 120, 64, 72, 171, 79, 80, 112, 181, 15, 155, 49,
-            210, 19, 22, 216, 74, 168, 143, 149, 16, 184, 63, 25, 192,
-        ],
-        4,
-    );
-    let key4 = [
-        177_u8, 231, 231, 200, 71, 83, 63, 150, 221, 247, 213, 231, 188, 27, 190, 148, 112, 218,
-        129, 131, 93, 195, 197, 44, 143, 203, 191, 17, 154, 100, 103, 100,
-    ];
+/// Roots of the SHA-256 trees of the hasher independent tests.
+#[test]
+fn test_sha256_roots() {
+    let leaves = test_leaves::<Sha256>();
     assert_eq!(
-        leaf4.hash(),
+        leaves[3].1.hash(),
         [
             57, 69, 34, 179, 59, 126, 69, 176, 23, 250, 43, 62, 92, 40, 140, 134, 218, 152, 51,
             247, 13, 206, 24, 141, 226, 105, 72, 134, 21, 60, 103, 103
         ]
     );
-
-    let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::default()));
-    let mut compact_tree = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::default()));
-
-    tree.insert([1; 32], leaf1.clone()).unwrap();
-    compact_tree.insert([1; 32], leaf1.clone()).unwrap();
     assert_eq!(
-        tree.root().unwrap().hash(),
-        hex!("b46e250d98aa9917abdd1012f72c03ab9a59f6de5253d963a99b7d69c2eca3da")
+        expected_root(&leaves[..0]),
+        hex!("b1e8e8f2dc3b266452988cfe169aa73be25405eeead02ab5dd6b3c6fd0ca8d67")
     );
     assert_eq!(
-        compact_tree.root().unwrap().hash(),
+        expected_root(&leaves[..1]),
         hex!("b46e250d98aa9917abdd1012f72c03ab9a59f6de5253d963a99b7d69c2eca3da")
     );
-
-    tree.insert([2; 32], leaf2.clone()).unwrap();
-    compact_tree.insert([2; 32], leaf2.clone()).unwrap();
-    assert_eq!(
-        tree.root().unwrap().hash(),
-        hex!("dc5ab9a0f0b56e215b550b2946cdc72aae2b013aa4790ee4d809a9b43cf2d9aa")
-    );
     assert_eq!(
-        compact_tree.root().unwrap().hash(),
+        expected_root(&leaves[..2]),
         hex!("dc5ab9a0f0b56e215b550b2946cdc72aae2b013aa4790ee4d809a9b43cf2d9aa")
     );
-
-    tree.insert([3; 32], leaf3.clone()).unwrap();
-    compact_tree.insert([3; 32], leaf3.clone()).unwrap();
     assert_eq!(
-        tree.root().unwrap().hash(),
+        expected_root(&leaves[..3]),
         hex!("37cb0517efdaaeb2c2c32fac206d8f14070864a1fd69d5368127dba161569ca2")
     );
-    assert_eq!(
-        compact_tree.root().unwrap().hash(),
-        hex!("37cb0517efdaaeb2c2c32fac206d8f14070864a1fd69d5368127dba161569ca2")
-    );
-    tree.insert(key4, leaf4.clone()).unwrap();
-    compact_tree.insert(key4, leaf4.clone()).unwrap();
+}
 
-    assert_eq!(
-        tree.root().unwrap().hash(),
-        compact_tree.root().unwrap().hash()
-    );
+fn test_leaves_insertion<H: Hasher<32> + Clone + ThreadSafe>() {
+    let leaves = test_leaves::<H>();
+    let mut tree = MSSMT::<32, H, ()>::new(Box::new(MemoryDb::default()));
+    let mut compact_tree = CompactMSSMT::<32, H, ()>::new(Box::new(MemoryDb::default()));
+    for (i, (key, leaf)) in leaves.iter().cloned().enumerate() {
+        tree.insert(key, leaf.clone()).unwrap();
+        compact_tree.insert(key, leaf).unwrap();
+        let root = expected_root(&leaves[..=i]);
+        assert_eq!(tree.root().unwrap().hash(), root);
+        assert_eq!(compact_tree.root().unwrap().hash(), root);
+    }
 }
 
-#[test]
-fn test_history_independant() {
-    let leaf1 = Leaf::new([1; 32].to_vec(), 1);
-    let leaf2 = Leaf::new([2; 32].to_vec(), 2);
-    let leaf3 = Leaf::new([3; 32].to_vec(), 3);
+fn test_history_independant<H: Hasher<32> + Clone + ThreadSafe>() {
+    let [leaf1, leaf2, leaf3, _] = test_leaves::<H>();
 
-    let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::default()));
-    let mut compact_tree = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::default()));
-    tree.insert([1; 32], leaf1.clone()).unwrap();
-    tree.insert([3; 32], leaf3.clone()).unwrap();
-    tree.insert([2; 32], leaf2.clone()).unwrap();
-    compact_tree.insert([3; 32], leaf3.clone()).unwrap();
-    compact_tree.insert([2; 32], leaf2.clone()).unwrap();
-    compact_tree.insert([1; 32], leaf1.clone()).unwrap();
-    assert_eq!(
-        tree.root().unwrap().hash(),
-        hex!("37cb0517efdaaeb2c2c32fac206d8f14070864a1fd69d5368127dba161569ca2")
-    );
+    let mut tree = MSSMT::<32, H, ()>::new(Box::new(MemoryDb::default()));
+    let mut compact_tree = CompactMSSMT::<32, H, ()>::new(Box::new(MemoryDb::default()));
+    for (key, leaf) in [leaf1.clone(), leaf3.clone(), leaf2.clone()] {
+        tree.insert(key, leaf).unwrap();
+    }
+    for (key, leaf) in [leaf3.clone(), leaf2.clone(), leaf1.clone()] {
+        compact_tree.insert(key, leaf).unwrap();
+    }
+    let root = expected_root(&[leaf1, leaf2, leaf3]);
+    assert_eq!(tree.root().unwrap().hash(), root);
+    assert_eq!(compact_tree.root().unwrap().hash(), root);
+}
+
+fn test_leaves_deletion<H: Hasher<32> + Clone + ThreadSafe>() {
+    let leaves = test_leaves::<H>();
+    let (key3, leaf3) = leaves[2].clone();
+
+    let mut tree = MSSMT::<32, H, ()>::new(Box::new(MemoryDb::default()));
+    let mut compact_tree = CompactMSSMT::<32, H, ()>::new(Box::new(MemoryDb::default()));
+    for (key, leaf) in leaves[..3].iter().cloned() {
+        tree.insert(key, leaf.clone()).unwrap();
+        compact_tree.insert(key, leaf).unwrap();
+    }
+
+    assert_eq!(tree.delete(key3).unwrap().unwrap().hash(), leaf3.hash());
     assert_eq!(
-        compact_tree.root().unwrap().hash(),
-        hex!("37cb0517efdaaeb2c2c32fac206d8f14070864a1fd69d5368127dba161569ca2")
+        compact_tree.delete(key3).unwrap().unwrap().hash(),
+        leaf3.hash()
     );
+    let root = expected_root(&leaves[..2]);
+    assert_eq!(tree.root().unwrap().hash(), root);
+    assert_eq!(compact_tree.root().unwrap().hash(), root);
+
+    // Deleting a missing key doesn't change the tree.
+    assert!(tree.delete(key3).unwrap().is_none());
+    assert!(compact_tree.delete(key3).unwrap().is_none());
+
+    tree.delete([1; 32]).unwrap();
+    tree.delete([2; 32]).unwrap();
+    compact_tree.delete([2; 32]).unwrap();
+    compact_tree.delete([1; 32]).unwrap();
+    let root = expected_root::<H>(&[]);
+    assert_eq!(tree.root().unwrap().hash(), root);
+    assert_eq!(compact_tree.root().unwrap().hash(), root);
 }
 
-#[test]
-fn test_insertion() {
+fn test_insert_batch<H: Hasher<32> + Clone + ThreadSafe>() {
+    let leaves = test_leaves::<H>();
+    let [leaf1, leaf2, leaf3, _] = leaves.clone();
+    let batch = vec![
+        ([3; 32], Leaf::new([4; 32].to_vec(), 4)),
+        leaf2,
+        ([4; 32], Leaf::new([4; 32].to_vec(), 4)),
+        leaf1,
+        // The last leaf of a key wins and empty leaves delete their key.
+        leaf3,
+        ([4; 32], Leaf::new(vec![], 0)),
+    ];
+
+    let mut tree = MSSMT::<32, H, ()>::new(Box::new(MemoryDb::default()));
+    let mut compact_tree = CompactMSSMT::<32, H, ()>::new(Box::new(MemoryDb::default()));
+    tree.insert_batch(batch.clone()).unwrap();
+    compact_tree.insert_batch(batch).unwrap();
+    let root = expected_root(&leaves[..3]);
+    assert_eq!(tree.root().unwrap().hash(), root);
+    assert_eq!(compact_tree.root().unwrap().hash(), root);
+
+    // Empty batches don't change the tree.
+    tree.insert_batch(vec![]).unwrap();
+    compact_tree.insert_batch(vec![]).unwrap();
+    assert_eq!(tree.root().unwrap().hash(), root);
+    assert_eq!(compact_tree.root().unwrap().hash(), root);
+}
+
+fn test_insertion<H: Hasher<32> + Clone + ThreadSafe>() {
     // tests that inserting leaves, branches and compacted leaves
     // in an orderly manner results in the expected tree structure in the database.
-    fn test_children<
-        const HASH_SIZE: usize,
-        H: Hasher<HASH_SIZE> + Clone + Default + ThreadSafe,
-    >(
+    fn test_children<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone + ThreadSafe>(
         leaves: Vec<Leaf<HASH_SIZE, H>>,
         check_branches: Vec<Vec<Branch<HASH_SIZE, H>>>,
         leaf_level: usize,
@@ -141,10 +198,10 @@ fn test_insertion() {
             }
         }
     }
-    let empty_tree = EmptyTree::<32, Sha256>::empty_tree();
+    let empty_tree = EmptyTree::<32, H>::empty_tree();
     let l1 = Leaf::new([1; 32].to_vec(), 1);
     let l2 = Leaf::new([2; 32].to_vec(), 2);
-    let l3 = Leaf::<32, Sha256>::new([3; 32].to_vec(), 3);
+    let l3 = Leaf::<32, H>::new([3; 32].to_vec(), 3);
     let l4 = Leaf::new([4; 32].to_vec(), 4);
     let branch_l1_l2 = Branch::new(Node::Leaf(l1.clone()), Node::Leaf(l2.clone()));
     let branch_l3_l4 = Branch::new(Node::Leaf(l3.clone()), Node::Leaf(l4.clone()));
@@ -310,6 +367,71 @@ fn test_insertion() {
     }
 }
 
+/// Checks the inclusion and non-inclusion proofs of both trees, compressed and encoded.
+fn test_proofs<H: Hasher<32> + Clone + ThreadSafe>() {
+    let leaves = test_leaves::<H>();
+    let mut tree = MSSMT::<32, H, ()>::new(Box::new(MemoryDb::default()));
+    let mut compact_tree = CompactMSSMT::<32, H, ()>::new(Box::new(MemoryDb::default()));
+    tree.insert_batch(leaves.to_vec()).unwrap();
+    compact_tree.insert_batch(leaves.to_vec()).unwrap();
+    let root = expected_root(&leaves);
+
+    /// Hashes of the siblings of `proof`.
+    fn hashes<H: Hasher<32> + Clone>(proof: &Proof<32, H>) -> Vec<[u8; 32]> {
+        proof.nodes().iter().map(|node| node.hash()).collect()
+    }
+
+    for (key, leaf) in leaves.iter().cloned() {
+        let proof = tree.merkle_proof(key).unwrap();
+        assert_eq!(
+            hashes(&compact_tree.merkle_proof(key).unwrap()),
+            hashes(&proof)
+        );
+        verify_compressed_merkle_proof::<32, H, (), _>(key, leaf.clone(), &proof.compress(), root)
+            .unwrap();
+        let decoded = Proof::from_bytes(&proof.to_bytes()).unwrap();
+        verify_merkle_proof::<32, H, (), _>(key, leaf.clone(), decoded, root).unwrap();
+
+        // Neither another leaf nor another key lead to the root.
+        let other_leaf = Leaf::new(vec![0; 32], leaf.sum());
+        assert_eq!(
+            verify_merkle_proof::<32, H, (), _>(key, other_leaf, proof.clone(), root),
+            Err(TreeError::InvalidMerkleProof)
+        );
+        assert_eq!(
+            verify_merkle_proof::<32, H, (), _>([0; 32], leaf, proof, root),
+            Err(TreeError::InvalidMerkleProof)
+        );
+
+        // Present keys have no non-inclusion proof.
+        assert!(matches!(
+            tree.non_inclusion_proof(key),
+            Err(TreeError::ExpectedEmptyLeaf)
+        ));
+        assert!(matches!(
+            compact_tree.non_inclusion_proof(key),
+            Err(TreeError::ExpectedEmptyLeaf)
+        ));
+    }
+
+    // The path of a key only differing from a present one on its last bit ends on the
+    // compact leaf of the present key.
+    let mut sibling_key = [1; 32];
+    sibling_key[31] ^= 1;
+    for key in [[0; 32], [5; 32], sibling_key] {
+        let proof = tree.non_inclusion_proof(key).unwrap();
+        let compact_proof = compact_tree.non_inclusion_proof(key).unwrap();
+        assert_eq!(hashes(compact_proof.proof()), hashes(proof.proof()));
+        verify_non_inclusion_proof::<32, H, (), _>(key, &proof, root).unwrap();
+        let proof = NonInclusionProof::from(proof.compress());
+        verify_non_inclusion_proof::<32, H, (), _>(key, &proof, root).unwrap();
+        assert_eq!(
+            verify_non_inclusion_proof::<32, H, (), _>([1; 32], &proof, root),
+            Err(TreeError::InvalidMerkleProof)
+        );
+    }
+}
+
 #[test]
 fn test_empty_tree_root() {
     let db = MemoryDb::<32, Sha256>::default();
@@ -317,3 +439,544 @@ fn test_empty_tree_root() {
     let root = tree.root().unwrap();
     assert_eq!(root.hash(), tree.db().empty_tree()[0].hash());
 }
+
+/// SHA-256 truncated to 20 bytes.
+#[derive(Clone)]
+struct Sha256Truncated;
+
+impl Hasher<20> for Sha256Truncated {
+    fn hash(data: &[u8]) -> [u8; 20] {
+        Sha256::digest(data)[..20].try_into().unwrap()
+    }
+}
+
+impl Hasher<64> for Sha512 {
+    fn hash(data: &[u8]) -> [u8; 64] {
+        Sha512::digest(data).into()
+    }
+}
+
+/// Builds, updates and proves against both trees with `HASH_SIZE`-byte keys and hashes.
+fn test_hash_size<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone + ThreadSafe>() {
+    let empty_tree = EmptyTree::<HASH_SIZE, H>::empty_tree();
+    assert_eq!(empty_tree.len(), HASH_SIZE * 8 + 1);
+    assert!(matches!(empty_tree[HASH_SIZE * 8], Node::Leaf(_)));
+    let mut tree = MSSMT::<HASH_SIZE, H, ()>::new(Box::new(MemoryDb::default()));
+    let mut compact_tree = CompactMSSMT::<HASH_SIZE, H, ()>::new(Box::new(MemoryDb::default()));
+    assert_eq!(tree.root().unwrap().hash(), empty_tree[0].hash());
+    assert_eq!(compact_tree.root().unwrap().hash(), empty_tree[0].hash());
+
+    let leaves: Vec<_> = (1..=4u8)
+        .map(|i| {
+            (
+                [i; HASH_SIZE],
+                Leaf::<HASH_SIZE, H>::new(vec![i; 8], i as u64),
+            )
+        })
+        .collect();
+    for (key, leaf) in leaves.clone() {
+        tree.insert(key, leaf.clone()).unwrap();
+        compact_tree.insert(key, leaf).unwrap();
+    }
+    tree.delete([4; HASH_SIZE]).unwrap();
+    compact_tree.delete([4; HASH_SIZE]).unwrap();
+    let root = tree.root().unwrap();
+    assert_eq!(root.sum(), 6);
+    assert_eq!(root.hash(), compact_tree.root().unwrap().hash());
+    assert!(tree.verify_integrity().is_ok());
+    assert!(compact_tree.verify_integrity().is_ok());
+
+    for (key, leaf) in leaves.into_iter().take(3) {
+        let proof = compact_tree.merkle_proof(key).unwrap();
+        verify_compressed_merkle_proof::<HASH_SIZE, H, (), _>(
+            key,
+            leaf.clone(),
+            &proof.compress(),
+            root.hash(),
+        )
+        .unwrap();
+        let proof = Proof::from_bytes(&tree.merkle_proof(key).unwrap().to_bytes()).unwrap();
+        verify_merkle_proof::<HASH_SIZE, H, (), _>(key, leaf, proof, root.hash()).unwrap();
+    }
+    for key in [[4; HASH_SIZE], [5; HASH_SIZE]] {
+        let proof = tree.non_inclusion_proof(key).unwrap();
+        verify_non_inclusion_proof::<HASH_SIZE, H, (), _>(key, &proof, root.hash()).unwrap();
+        let proof = compact_tree.non_inclusion_proof(key).unwrap();
+        verify_non_inclusion_proof::<HASH_SIZE, H, (), _>(key, &proof, root.hash()).unwrap();
+    }
+}
+
+#[test]
+fn test_20_byte_hashes() {
+    test_hash_size::<20, Sha256Truncated>();
+}
+
+#[test]
+fn test_64_byte_hashes() {
+    test_hash_size::<64, Sha512>();
+}
+
+/// Checks that the root only depends on the leaves, whatever the order and the kind of the
+/// updates, and that deleting leaves brings the previous roots back.
+fn test_hasher<H: Hasher<32> + Clone + ThreadSafe>() {
+    test_hash_size::<32, H>();
+
+    let leaves: Vec<_> = (1..=3u8)
+        .map(|i| ([i; 32], Leaf::<32, H>::new(vec![i; 32], i as u64)))
+        .collect();
+    let empty_root = EmptyTree::<32, H>::empty_tree()[0].hash();
+    let mut roots = vec![empty_root];
+    let mut tree = MSSMT::<32, H, ()>::new(Box::new(MemoryDb::default()));
+    for (key, leaf) in leaves.clone() {
+        tree.insert(key, leaf).unwrap();
+        roots.push(tree.root().unwrap().hash());
+    }
+
+    let mut compact_tree = CompactMSSMT::<32, H, ()>::new(Box::new(MemoryDb::default()));
+    for (key, leaf) in leaves.iter().rev().cloned() {
+        compact_tree.insert(key, leaf).unwrap();
+    }
+    assert_eq!(compact_tree.root().unwrap().hash(), roots[3]);
+    let mut batch_tree = MSSMT::<32, H, ()>::new(Box::new(MemoryDb::default()));
+    batch_tree.insert_batch(leaves.clone()).unwrap();
+    assert_eq!(batch_tree.root().unwrap().hash(), roots[3]);
+
+    for (i, (key, leaf)) in leaves.into_iter().enumerate().rev() {
+        assert_eq!(tree.delete(key).unwrap().unwrap().hash(), leaf.hash());
+        assert_eq!(
+            compact_tree.delete(key).unwrap().unwrap().hash(),
+            leaf.hash()
+        );
+        assert_eq!(tree.root().unwrap().hash(), roots[i]);
+        assert_eq!(compact_tree.root().unwrap().hash(), roots[i]);
+    }
+    assert_eq!(tree.gc(false).unwrap().total(), 0);
+}
+
+/// Runs the hasher independent tests over each bundled hasher, in a module per hasher.
+macro_rules! hasher_tests {
+    ($($name:ident: $hasher:ty $(, $feature:literal)?;)*) => {
+        $(
+            $(#[cfg(feature = $feature)])?
+            mod $name {
+                hasher_tests!(@tests $hasher:
+                    test_hasher,
+                    test_leaves_insertion,
+                    test_history_independant,
+                    test_leaves_deletion,
+                    test_insert_batch,
+                    test_insertion,
+                    test_proofs
+                );
+            }
+        )*
+    };
+    (@tests $hasher:ty: $($test:ident),*) => {
+        $(
+            #[test]
+            fn $test() {
+                super::$test::<$hasher>();
+            }
+        )*
+    };
+}
+
+hasher_tests! {
+    sha256: sha2::Sha256;
+    sha512_256: sha2::Sha512_256;
+    domain_separated: crate::DomainSeparated<sha2::Sha256>;
+    keccak256: sha3::Keccak256, "keccak";
+    blake3: blake3::Hasher, "blake3";
+    poseidon: crate::Poseidon, "poseidon";
+    pedersen: crate::Pedersen, "pedersen";
+}
+
+/// Inserts two leaves whose sums don't fit in a `u64` in both trees and checks the proofs.
+fn test_wide_sums<S: Sum>(sum: S, total: S) {
+    let leaves = [
+        ([1; 32], Leaf::<32, Sha256, S>::new(vec![1; 32], sum)),
+        ([2; 32], Leaf::<32, Sha256, S>::new(vec![2; 32], sum)),
+    ];
+    let mut tree = MSSMT::<32, Sha256, (), S>::new(Box::new(MemoryDb::default()));
+    let mut compact_tree = CompactMSSMT::<32, Sha256, (), S>::new(Box::new(MemoryDb::default()));
+    for (key, leaf) in leaves.clone() {
+        tree.insert(key, leaf.clone()).unwrap();
+        compact_tree.insert(key, leaf).unwrap();
+    }
+    let root = tree.root().unwrap();
+    assert_eq!(root.sum(), total);
+    assert_eq!(root.hash(), compact_tree.root().unwrap().hash());
+    for (key, leaf) in leaves {
+        let proof = compact_tree.merkle_proof(key).unwrap();
+        verify_merkle_proof::<32, Sha256, (), S>(key, leaf.clone(), proof, root.hash()).unwrap();
+        let proof = Proof::from_bytes(&tree.merkle_proof(key).unwrap().to_bytes()).unwrap();
+        verify_merkle_proof::<32, Sha256, (), S>(key, leaf, proof, root.hash()).unwrap();
+    }
+}
+
+#[test]
+fn test_insert_batch_replaces_sums() {
+    let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::default()));
+    tree.insert([1; 32], Leaf::new(vec![1; 32], u64::MAX))
+        .unwrap();
+    // The new leaf replaces the sum of the old one.
+    tree.insert_batch([([1; 32], Leaf::new(vec![1; 32], 5))])
+        .unwrap();
+    assert_eq!(tree.root().unwrap().sum(), 5);
+
+    let root = tree.root().unwrap().hash();
+    assert_eq!(
+        tree.insert_batch([
+            ([2; 32], Leaf::new(vec![2; 32], 1)),
+            ([3; 32], Leaf::new(vec![3; 32], u64::MAX)),
+        ]),
+        Err(TreeError::SumOverflow)
+    );
+    assert_eq!(tree.root().unwrap().hash(), root);
+    assert!(tree.verify_integrity().is_ok());
+}
+
+#[test]
+fn test_compact_insert_batch_replaces_sums() {
+    let mut tree = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::default()));
+    tree.insert([1; 32], Leaf::new(vec![1; 32], u64::MAX))
+        .unwrap();
+    tree.insert([1; 32], Leaf::new(vec![1; 32], 5)).unwrap();
+    tree.insert_batch([([1; 32], Leaf::new(vec![1; 32], u64::MAX))])
+        .unwrap();
+    // Deleting the large leaf makes room for the other one, as it would one by one.
+    tree.insert_batch([
+        ([1; 32], Leaf::new(vec![], 0)),
+        ([2; 32], Leaf::new(vec![2; 32], 1)),
+    ])
+    .unwrap();
+    assert_eq!(tree.root().unwrap().sum(), 1);
+
+    let root = tree.root().unwrap().hash();
+    assert_eq!(
+        tree.insert_batch([([3; 32], Leaf::new(vec![3; 32], u64::MAX))]),
+        Err(TreeError::SumOverflow)
+    );
+    assert_eq!(
+        tree.insert([3; 32], Leaf::new(vec![3; 32], u64::MAX)),
+        Err(TreeError::SumOverflow)
+    );
+    assert_eq!(tree.root().unwrap().hash(), root);
+    assert!(tree.verify_integrity().is_ok());
+}
+
+#[test]
+fn test_u128_sums() {
+    test_wide_sums(u64::MAX as u128, 2 * u64::MAX as u128);
+
+    let mut tree = MSSMT::<32, Sha256, (), u128>::new(Box::new(MemoryDb::default()));
+    tree.insert([1; 32], Leaf::new(vec![1; 32], u128::MAX))
+        .unwrap();
+    assert_eq!(
+        tree.insert([2; 32], Leaf::new(vec![2; 32], 1)),
+        Err(TreeError::SumOverflow)
+    );
+}
+
+#[cfg(feature = "u256")]
+#[test]
+fn test_u256_sums() {
+    use crate::U256;
+
+    test_wide_sums(U256::from(u128::MAX), U256::from(u128::MAX) * 2);
+}
+
+#[test]
+fn test_u64_sums_keep_root_hashes() {
+    let mut tree = MSSMT::<32, Sha256, (), u64>::new(Box::new(MemoryDb::default()));
+    for i in 1..=3u8 {
+        tree.insert([i; 32], Leaf::new([i; 32].to_vec(), i as u64))
+            .unwrap();
+    }
+    assert_eq!(
+        tree.root().unwrap().hash(),
+        hex!("37cb0517efdaaeb2c2c32fac206d8f14070864a1fd69d5368127dba161569ca2")
+    );
+}
+
+#[test]
+fn test_multi_asset_sums() {
+    let leaves = [
+        ([1; 32], MultiSum::new([1, 0, 5])),
+        ([2; 32], MultiSum::new([2, 7, 0])),
+        ([3; 32], MultiSum::new([3, 1, u64::MAX - 5])),
+    ]
+    .map(|(key, sum)| (key, Leaf::<32, Sha256, MultiSum<3>>::new(key.to_vec(), sum)));
+    let mut tree = MSSMT::<32, Sha256, (), MultiSum<3>>::new(Box::new(MemoryDb::default()));
+    let mut compact_tree =
+        CompactMSSMT::<32, Sha256, (), MultiSum<3>>::new(Box::new(MemoryDb::default()));
+    for (key, leaf) in leaves.clone() {
+        tree.insert(key, leaf.clone()).unwrap();
+        compact_tree.insert(key, leaf).unwrap();
+    }
+    let root = tree.root().unwrap();
+    assert_eq!(root.sum(), MultiSum::new([6, 8, u64::MAX]));
+    assert_eq!(root.hash(), compact_tree.root().unwrap().hash());
+
+    // A verifier checks the per-asset totals committed to by the root.
+    for (key, leaf) in leaves {
+        let proof = Proof::<32, Sha256, MultiSum<3>>::from_bytes(
+            &compact_tree.merkle_proof(key).unwrap().to_bytes(),
+        )
+        .unwrap();
+        let proof_root = proof.root::<()>(key, leaf).unwrap();
+        assert_eq!(proof_root.hash(), root.hash());
+        assert_eq!(proof_root.sum().sums(), &[6, 8, u64::MAX]);
+    }
+
+    // Any asset overflowing is rejected.
+    assert_eq!(
+        tree.insert([4; 32], Leaf::new(vec![4; 32], MultiSum::new([0, 0, 1]))),
+        Err(TreeError::SumOverflow)
+    );
+}
+
+/// A [`MemoryDb`] whose node writes fail once the shared budget of writes is spent, and
+/// whose commits fail without applying anything while `fail_commits` is set.
+struct FailingDb {
+    db: MemoryDb<32, Sha256>,
+    writes_left: Arc<AtomicUsize>,
+    fail_commits: Arc<AtomicBool>,
+}
+
+impl FailingDb {
+    fn write(&self) -> Result<(), TreeError<()>> {
+        self.writes_left
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                left.checked_sub(1)
+            })
+            .map(|_| ())
+            .map_err(|_| TreeError::DbError(()))
+    }
+}
+
+impl Db<32, Sha256> for FailingDb {
+    type DbError = ();
+
+    fn get_root_node(&self) -> Option<Branch<32, Sha256>> {
+        self.db.get_root_node()
+    }
+
+    fn get_children(
+        &self,
+        height: usize,
+        key: [u8; 32],
+    ) -> Result<(Node<32, Sha256>, Node<32, Sha256>), TreeError<()>> {
+        self.db.get_children(height, key)
+    }
+
+    fn insert_leaf(&mut self, leaf: Leaf<32, Sha256>) -> Result<(), TreeError<()>> {
+        self.write()?;
+        self.db.insert_leaf(leaf)
+    }
+
+    fn insert_branch(&mut self, branch: Branch<32, Sha256>) -> Result<(), TreeError<()>> {
+        self.write()?;
+        self.db.insert_branch(branch)
+    }
+
+    fn insert_compact_leaf(
+        &mut self,
+        compact_leaf: CompactLeaf<32, Sha256>,
+    ) -> Result<(), TreeError<()>> {
+        self.write()?;
+        self.db.insert_compact_leaf(compact_leaf)
+    }
+
+    fn empty_tree(&self) -> Arc<[Node<32, Sha256>]> {
+        self.db.empty_tree()
+    }
+
+    fn update_root(&mut self, root: Branch<32, Sha256>) -> Result<(), TreeError<()>> {
+        self.write()?;
+        self.db.update_root(root)
+    }
+
+    fn delete_branch(&mut self, key: &[u8; 32]) -> Result<(), TreeError<()>> {
+        self.write()?;
+        self.db.delete_branch(key)
+    }
+
+    fn delete_leaf(&mut self, key: &[u8; 32]) -> Result<(), TreeError<()>> {
+        self.write()?;
+        self.db.delete_leaf(key)
+    }
+
+    fn delete_compact_leaf(&mut self, key: &[u8; 32]) -> Result<(), TreeError<()>> {
+        self.write()?;
+        self.db.delete_compact_leaf(key)
+    }
+
+    fn begin(&mut self) -> Result<(), TreeError<()>> {
+        self.db.begin()
+    }
+
+    fn commit(&mut self) -> Result<(), TreeError<()>> {
+        if self.fail_commits.load(Ordering::SeqCst) {
+            return Err(TreeError::DbError(()));
+        }
+        self.db.commit()
+    }
+
+    fn rollback(&mut self) -> Result<(), TreeError<()>> {
+        self.db.rollback()
+    }
+
+    /// The inner [`MemoryDb`], to compare the stored nodes.
+    fn as_any(&self) -> &dyn Any {
+        &self.db
+    }
+}
+
+type StoredNodes = (
+    Option<[u8; 32]>,
+    Vec<[u8; 32]>,
+    Vec<[u8; 32]>,
+    Vec<[u8; 32]>,
+);
+
+fn stored_nodes(db: &dyn Db<32, Sha256, DbError = ()>) -> StoredNodes {
+    let db = db.as_any().downcast_ref::<MemoryDb<32, Sha256>>().unwrap();
+    let sorted = |mut keys: Vec<[u8; 32]>| {
+        keys.sort();
+        keys
+    };
+    (
+        db.get_root_node().map(|root| root.hash()),
+        sorted(db.get_branches().keys().copied().collect()),
+        sorted(db.get_leaves().keys().copied().collect()),
+        sorted(db.get_compact_leaves().keys().copied().collect()),
+    )
+}
+
+#[test]
+fn test_failed_updates_are_rolled_back() {
+    let leaves: Vec<_> = (1..=10u8)
+        .map(|i| ([i; 32], Leaf::new(vec![i; 32], i as u64)))
+        .collect();
+    let new_leaves: Vec<_> = (11..=20u8)
+        .map(|i| ([i; 32], Leaf::new(vec![i; 32], i as u64)))
+        .collect();
+    let writes_left = Arc::new(AtomicUsize::new(usize::MAX));
+    let fail_commits = Arc::new(AtomicBool::new(false));
+    let db = || {
+        Box::new(FailingDb {
+            db: MemoryDb::new(),
+            writes_left: writes_left.clone(),
+            fail_commits: fail_commits.clone(),
+        })
+    };
+    let mut tree = MSSMT::new(db());
+    let mut compact_tree = CompactMSSMT::new(db());
+    tree.insert_batch(leaves.clone()).unwrap();
+    compact_tree.insert_batch(leaves.clone()).unwrap();
+    let before = (stored_nodes(tree.db()), stored_nodes(compact_tree.db()));
+
+    // Fail after a few writes, in the middle of each update.
+    for writes in [0, 1, 3] {
+        writes_left.store(writes, Ordering::SeqCst);
+        let (key, leaf) = new_leaves[0].clone();
+        assert_eq!(tree.insert(key, leaf.clone()), Err(TreeError::DbError(())));
+        assert_eq!(compact_tree.insert(key, leaf), Err(TreeError::DbError(())));
+        writes_left.store(writes, Ordering::SeqCst);
+        assert_eq!(
+            tree.insert_batch(new_leaves.clone()),
+            Err(TreeError::DbError(()))
+        );
+        assert_eq!(
+            compact_tree.insert_batch(new_leaves.clone()),
+            Err(TreeError::DbError(()))
+        );
+        writes_left.store(writes, Ordering::SeqCst);
+        assert_eq!(tree.delete([1; 32]).unwrap_err(), TreeError::DbError(()));
+        assert_eq!(
+            compact_tree.delete([1; 32]).unwrap_err(),
+            TreeError::DbError(())
+        );
+        assert_eq!(
+            (stored_nodes(tree.db()), stored_nodes(compact_tree.db())),
+            before
+        );
+    }
+    writes_left.store(0, Ordering::SeqCst);
+    assert!(CompactMSSMT::from_sorted_leaves(db(), leaves.clone()).is_err());
+
+    // The updates of a failed commit are rolled back.
+    writes_left.store(usize::MAX, Ordering::SeqCst);
+    fail_commits.store(true, Ordering::SeqCst);
+    let (key, leaf) = new_leaves[0].clone();
+    assert_eq!(tree.insert(key, leaf.clone()), Err(TreeError::DbError(())));
+    assert_eq!(compact_tree.insert(key, leaf), Err(TreeError::DbError(())));
+    assert_eq!(
+        (stored_nodes(tree.db()), stored_nodes(compact_tree.db())),
+        before
+    );
+    fail_commits.store(false, Ordering::SeqCst);
+
+    // The trees are still usable once the writes succeed again.
+    writes_left.store(usize::MAX, Ordering::SeqCst);
+    tree.insert_batch(new_leaves.clone()).unwrap();
+    compact_tree.insert_batch(new_leaves.clone()).unwrap();
+    let mut expected = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::default()));
+    expected
+        .insert_batch(leaves.into_iter().chain(new_leaves))
+        .unwrap();
+    assert_eq!(tree.root().unwrap().hash(), expected.root().unwrap().hash());
+    assert_eq!(
+        compact_tree.root().unwrap().hash(),
+        expected.root().unwrap().hash()
+    );
+}
+
+#[test]
+fn test_identical_subtrees_survive_deletes() {
+    // The keys only differ on the first bit of their path, so the same leaf at both keys
+    // makes the two children of the root identical and stored once.
+    let (key_a, mut key_b) = ([0; 32], [0; 32]);
+    key_b[0] = 1;
+    let leaf = Leaf::<32, Sha256>::new(vec![1; 32], 1);
+    let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::default()));
+    let mut compact_tree = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::default()));
+    for key in [key_a, key_b] {
+        tree.insert(key, leaf.clone()).unwrap();
+        compact_tree.insert(key, leaf.clone()).unwrap();
+    }
+    let root = tree.root().unwrap();
+    assert_eq!(root.left().hash(), root.right().hash());
+
+    tree.delete(key_a).unwrap();
+    compact_tree.delete(key_a).unwrap();
+    let mut expected = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::default()));
+    expected.insert(key_b, leaf.clone()).unwrap();
+    let root = expected.root().unwrap().hash();
+    for (tree_root, proof, got) in [
+        (
+            tree.root().unwrap().hash(),
+            tree.merkle_proof(key_b).unwrap(),
+            tree.get(key_b).unwrap(),
+        ),
+        (
+            compact_tree.root().unwrap().hash(),
+            compact_tree.merkle_proof(key_b).unwrap(),
+            compact_tree.get(key_b).unwrap(),
+        ),
+    ] {
+        assert_eq!(tree_root, root);
+        assert_eq!(got.unwrap().hash(), leaf.hash());
+        verify_merkle_proof::<32, Sha256, (), _>(key_b, leaf.clone(), proof, root).unwrap();
+    }
+    assert_eq!(stored_nodes(tree.db()), stored_nodes(expected.db()));
+
+    // The last references go with the last leaf.
+    tree.delete(key_b).unwrap();
+    compact_tree.delete(key_b).unwrap();
+    for db in [tree.db(), compact_tree.db()] {
+        let (_, branches, leaves, compact_leaves) = stored_nodes(db);
+        assert!(branches.is_empty() && leaves.is_empty() && compact_leaves.is_empty());
+    }
+}
@@ -0,0 +1,667 @@
+//! Trees stored in an [`AsyncDb`].
+//!
+//! The operations run the synchronous trees on a [`Staging`] database holding the nodes read
+//! from the [`AsyncDb`] so far. When the tree reads a node that isn't staged yet, it's fetched
+//! and the operation starts over, so the algorithms are the ones of [`MSSMT`] and
+//! [`CompactMSSMT`]. The nodes on the path to the key are fetched beforehand, which covers
+//! the reads of most operations. The updates are staged as well and applied to the
+//! [`AsyncDb`] in one transaction once the operation succeeded.
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    db::{AsyncDb, Db, Write},
+    node::{Branch, CompactLeaf, Hasher, Leaf, Node, Sum},
+    proof::Proof,
+    ThreadSafe, TreeError,
+};
+
+use super::{bit_index, CompactMSSMT, MSSMT};
+
+const POISONED: &str = "The staging lock is poisoned";
+
+/// Error of the [`Staging`] database: the node isn't staged yet.
+#[derive(Debug)]
+struct Missing;
+
+/// Nodes read and updates made by an operation.
+#[allow(clippy::type_complexity)]
+struct Staged<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> {
+    root: Option<Branch<HASH_SIZE, H, S>>,
+    /// Children of the branches read from the [`AsyncDb`] by height and hash.
+    children: HashMap<(usize, [u8; HASH_SIZE]), (Node<HASH_SIZE, H, S>, Node<HASH_SIZE, H, S>)>,
+    /// Branch that was read without being staged.
+    missing: Option<(usize, [u8; HASH_SIZE])>,
+    writes: Vec<Write<HASH_SIZE, H, S>>,
+}
+
+/// Synchronous database serving the staged nodes and recording the updates.
+struct Staging<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> {
+    empty_tree: Arc<[Node<HASH_SIZE, H, S>]>,
+    staged: Mutex<Option<Staged<HASH_SIZE, H, S>>>,
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> Staging<HASH_SIZE, H, S> {
+    fn record(&mut self, write: Write<HASH_SIZE, H, S>) -> Result<(), TreeError<Missing>> {
+        self.staged().writes.push(write);
+        Ok(())
+    }
+
+    fn staged(&mut self) -> &mut Staged<HASH_SIZE, H, S> {
+        self.staged
+            .get_mut()
+            .expect(POISONED)
+            .as_mut()
+            .expect("The staged nodes are only taken after the operation")
+    }
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone + ThreadSafe, S: Sum> Db<HASH_SIZE, H, S>
+    for Staging<HASH_SIZE, H, S>
+{
+    type DbError = Missing;
+
+    fn get_root_node(&self) -> Option<Branch<HASH_SIZE, H, S>> {
+        let staged = self.staged.lock().expect(POISONED);
+        staged.as_ref().and_then(|staged| staged.root.clone())
+    }
+
+    fn get_children(
+        &self,
+        height: usize,
+        key: [u8; HASH_SIZE],
+    ) -> Result<(Node<HASH_SIZE, H, S>, Node<HASH_SIZE, H, S>), TreeError<Self::DbError>> {
+        if key == self.empty_tree[height].hash() {
+            let child = self.empty_tree[height + 1].clone();
+            return Ok((child.clone(), child));
+        }
+        let mut staged = self.staged.lock().expect(POISONED);
+        let staged = staged
+            .as_mut()
+            .expect("The staged nodes are only taken after the operation");
+        if let Some(children) = staged.children.get(&(height, key)) {
+            return Ok(children.clone());
+        }
+        staged.missing = Some((height, key));
+        Err(TreeError::DbError(Missing))
+    }
+
+    fn insert_leaf(&mut self, leaf: Leaf<HASH_SIZE, H, S>) -> Result<(), TreeError<Self::DbError>> {
+        self.record(Write::Leaf(leaf))
+    }
+
+    fn insert_branch(
+        &mut self,
+        branch: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.record(Write::Branch(branch))
+    }
+
+    fn insert_compact_leaf(
+        &mut self,
+        compact_leaf: CompactLeaf<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.record(Write::CompactLeaf(compact_leaf))
+    }
+
+    fn empty_tree(&self) -> Arc<[Node<HASH_SIZE, H, S>]> {
+        self.empty_tree.clone()
+    }
+
+    fn update_root(
+        &mut self,
+        root: Branch<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.staged().root = Some(root.clone());
+        self.record(Write::Root(root))
+    }
+
+    fn delete_branch(&mut self, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<Self::DbError>> {
+        self.record(Write::DeleteBranch(*key))
+    }
+
+    fn delete_leaf(&mut self, key: &[u8; HASH_SIZE]) -> Result<(), TreeError<Self::DbError>> {
+        self.record(Write::DeleteLeaf(*key))
+    }
+
+    fn delete_compact_leaf(
+        &mut self,
+        key: &[u8; HASH_SIZE],
+    ) -> Result<(), TreeError<Self::DbError>> {
+        self.record(Write::DeleteCompactLeaf(*key))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Synchronous tree running the operations of an asynchronous one.
+trait SyncTree<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> {
+    fn new(db: Box<dyn Db<HASH_SIZE, H, S, DbError = Missing>>) -> Self;
+
+    fn db(&self) -> &dyn Db<HASH_SIZE, H, S, DbError = Missing>;
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> SyncTree<HASH_SIZE, H, S>
+    for MSSMT<HASH_SIZE, H, Missing, S>
+{
+    fn new(db: Box<dyn Db<HASH_SIZE, H, S, DbError = Missing>>) -> Self {
+        MSSMT::new(db)
+    }
+
+    fn db(&self) -> &dyn Db<HASH_SIZE, H, S, DbError = Missing> {
+        MSSMT::db(self)
+    }
+}
+
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> SyncTree<HASH_SIZE, H, S>
+    for CompactMSSMT<HASH_SIZE, H, Missing, S>
+{
+    fn new(db: Box<dyn Db<HASH_SIZE, H, S, DbError = Missing>>) -> Self {
+        CompactMSSMT::new(db)
+    }
+
+    fn db(&self) -> &dyn Db<HASH_SIZE, H, S, DbError = Missing> {
+        CompactMSSMT::db(self)
+    }
+}
+
+/// Runs `op` on a synchronous tree reading its nodes from `db` and returns its result with
+/// the updates it made.
+async fn stage<
+    const HASH_SIZE: usize,
+    H: Hasher<HASH_SIZE> + Clone + ThreadSafe + 'static,
+    S: Sum,
+    DbError,
+    Tree: SyncTree<HASH_SIZE, H, S>,
+    T,
+>(
+    db: &dyn AsyncDb<HASH_SIZE, H, S, DbError = DbError>,
+    key: [u8; HASH_SIZE],
+    op: impl Fn(&mut Tree) -> Result<T, TreeError<Missing>>,
+) -> Result<(T, Vec<Write<HASH_SIZE, H, S>>), TreeError<DbError>> {
+    let empty_tree = db.empty_tree();
+    let root = db.get_root_node().await?;
+    let mut staged = Staged {
+        root: root.clone(),
+        children: HashMap::new(),
+        missing: None,
+        writes: Vec::new(),
+    };
+
+    // Fetch the branches on the path to the key.
+    if let Some(root) = &root {
+        let mut hash = root.hash();
+        for height in 0..HASH_SIZE * 8 {
+            if hash == empty_tree[height].hash() {
+                break;
+            }
+            let (left, right) = db.get_children(height, hash).await?;
+            let next = if bit_index(height, &key) == 0 {
+                &left
+            } else {
+                &right
+            };
+            let next = match next {
+                Node::Branch(branch) => Some(branch.hash()),
+                _ => None,
+            };
+            staged.children.insert((height, hash), (left, right));
+            let Some(next) = next else {
+                break;
+            };
+            hash = next;
+        }
+    }
+
+    loop {
+        let (result, mut done) = {
+            let mut tree = Tree::new(Box::new(Staging {
+                empty_tree: empty_tree.clone(),
+                staged: Mutex::new(Some(staged)),
+            }));
+            let result = op(&mut tree);
+            let staging = tree
+                .db()
+                .as_any()
+                .downcast_ref::<Staging<HASH_SIZE, H, S>>()
+                .expect("The tree runs on the staging database");
+            let done = staging.staged.lock().expect(POISONED).take();
+            (result, done.expect("The staged nodes are taken once"))
+        };
+        match result {
+            Err(TreeError::DbError(Missing)) => {
+                let (height, hash) = done.missing.take().expect("A missing node was read");
+                let children = db.get_children(height, hash).await?;
+                done.children.insert((height, hash), children);
+                done.root = root.clone();
+                done.writes.clear();
+                staged = done;
+            }
+            result => {
+                return result.map(|value| (value, done.writes)).map_err(|e| {
+                    e.map_db_error(|Missing| unreachable!("Missing nodes are fetched"))
+                })
+            }
+        }
+    }
+}
+
+/// Applies the updates of an operation to `db` in a transaction.
+async fn apply<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum, DbError>(
+    db: &mut dyn AsyncDb<HASH_SIZE, H, S, DbError = DbError>,
+    writes: Vec<Write<HASH_SIZE, H, S>>,
+) -> Result<(), TreeError<DbError>> {
+    db.begin().await?;
+    for write in writes {
+        let result = match write {
+            Write::Leaf(leaf) => db.insert_leaf(leaf).await,
+            Write::Branch(branch) => db.insert_branch(branch).await,
+            Write::CompactLeaf(compact_leaf) => db.insert_compact_leaf(compact_leaf).await,
+            Write::DeleteLeaf(key) => db.delete_leaf(&key).await,
+            Write::DeleteBranch(key) => db.delete_branch(&key).await,
+            Write::DeleteCompactLeaf(key) => db.delete_compact_leaf(&key).await,
+            Write::Root(root) => db.update_root(root).await,
+            Write::NamespaceRoot(..) => unreachable!("The trees only update their root"),
+        };
+        if let Err(e) = result {
+            db.rollback().await?;
+            return Err(e);
+        }
+    }
+    if let Err(e) = db.commit().await {
+        db.rollback().await?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Root of the tree stored in `db`.
+async fn root<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum, DbError>(
+    db: &dyn AsyncDb<HASH_SIZE, H, S, DbError = DbError>,
+) -> Result<Branch<HASH_SIZE, H, S>, TreeError<DbError>> {
+    match db.get_root_node().await? {
+        Some(branch) => Ok(branch),
+        None => {
+            let Node::Branch(branch) = db.empty_tree().as_ref()[0].clone() else {
+                return Err(TreeError::ExpectedBranch);
+            };
+            Ok(branch)
+        }
+    }
+}
+
+/// Merkle sum sparse merkle tree stored in an [`AsyncDb`], see [`MSSMT`].
+pub struct AsyncMSSMT<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError, S: Sum = u64> {
+    db: Box<dyn AsyncDb<HASH_SIZE, H, S, DbError = DbError>>,
+}
+
+impl<
+        const HASH_SIZE: usize,
+        H: Hasher<HASH_SIZE> + Clone + ThreadSafe + 'static,
+        DbError,
+        S: Sum,
+    > AsyncMSSMT<HASH_SIZE, H, DbError, S>
+{
+    /// Creates a tree stored in `db`.
+    pub fn new(db: Box<dyn AsyncDb<HASH_SIZE, H, S, DbError = DbError>>) -> Self {
+        Self { db }
+    }
+
+    pub fn db(&self) -> &dyn AsyncDb<HASH_SIZE, H, S, DbError = DbError> {
+        self.db.as_ref()
+    }
+
+    /// Root node of the tree.
+    pub async fn root(&self) -> Result<Branch<HASH_SIZE, H, S>, TreeError<DbError>> {
+        root(self.db.as_ref()).await
+    }
+
+    /// Get the leaf stored at `key`, or `None` if there is no leaf at `key`.
+    pub async fn get(
+        &self,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Option<Leaf<HASH_SIZE, H, S>>, TreeError<DbError>> {
+        let (leaf, _) = stage(self.db.as_ref(), key, |tree: &mut MSSMT<_, _, _, _>| {
+            tree.get(key)
+        })
+        .await?;
+        Ok(leaf)
+    }
+
+    /// Insert a leaf in the tree, see [`MSSMT::insert`].
+    pub async fn insert(
+        &mut self,
+        key: [u8; HASH_SIZE],
+        leaf: Leaf<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<DbError>> {
+        let ((), writes) = stage(self.db.as_ref(), key, |tree: &mut MSSMT<_, _, _, _>| {
+            tree.insert(key, leaf.clone())
+        })
+        .await?;
+        apply(self.db.as_mut(), writes).await
+    }
+
+    /// Delete the leaf stored at `key`, see [`MSSMT::delete`].
+    pub async fn delete(
+        &mut self,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Option<Leaf<HASH_SIZE, H, S>>, TreeError<DbError>> {
+        let (leaf, writes) = stage(self.db.as_ref(), key, |tree: &mut MSSMT<_, _, _, _>| {
+            tree.delete(key)
+        })
+        .await?;
+        apply(self.db.as_mut(), writes).await?;
+        Ok(leaf)
+    }
+
+    /// Merkle proof of the leaf stored at `key`.
+    pub async fn merkle_proof(
+        &self,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Proof<HASH_SIZE, H, S>, TreeError<DbError>> {
+        let (proof, _) = stage(self.db.as_ref(), key, |tree: &mut MSSMT<_, _, _, _>| {
+            tree.merkle_proof(key)
+        })
+        .await?;
+        Ok(proof)
+    }
+}
+
+/// Compact merkle sum sparse merkle tree stored in an [`AsyncDb`], see [`CompactMSSMT`].
+pub struct AsyncCompactMSSMT<
+    const HASH_SIZE: usize,
+    H: Hasher<HASH_SIZE> + Clone,
+    DbError,
+    S: Sum = u64,
+> {
+    db: Box<dyn AsyncDb<HASH_SIZE, H, S, DbError = DbError>>,
+}
+
+impl<
+        const HASH_SIZE: usize,
+        H: Hasher<HASH_SIZE> + Clone + ThreadSafe + 'static,
+        DbError,
+        S: Sum,
+    > AsyncCompactMSSMT<HASH_SIZE, H, DbError, S>
+{
+    /// Creates a tree stored in `db`.
+    pub fn new(db: Box<dyn AsyncDb<HASH_SIZE, H, S, DbError = DbError>>) -> Self {
+        Self { db }
+    }
+
+    pub fn db(&self) -> &dyn AsyncDb<HASH_SIZE, H, S, DbError = DbError> {
+        self.db.as_ref()
+    }
+
+    /// Root node of the tree.
+    pub async fn root(&self) -> Result<Branch<HASH_SIZE, H, S>, TreeError<DbError>> {
+        root(self.db.as_ref()).await
+    }
+
+    /// Get the leaf stored at `key`, or `None` if there is no leaf at `key`.
+    pub async fn get(
+        &self,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Option<Leaf<HASH_SIZE, H, S>>, TreeError<DbError>> {
+        let (leaf, _) = stage(
+            self.db.as_ref(),
+            key,
+            |tree: &mut CompactMSSMT<_, _, _, _>| tree.get(key),
+        )
+        .await?;
+        Ok(leaf)
+    }
+
+    /// Insert a leaf in the tree, see [`CompactMSSMT::insert`].
+    pub async fn insert(
+        &mut self,
+        key: [u8; HASH_SIZE],
+        leaf: Leaf<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<DbError>> {
+        let ((), writes) = stage(
+            self.db.as_ref(),
+            key,
+            |tree: &mut CompactMSSMT<_, _, _, _>| tree.insert(key, leaf.clone()),
+        )
+        .await?;
+        apply(self.db.as_mut(), writes).await
+    }
+
+    /// Delete the leaf stored at `key`, see [`CompactMSSMT::delete`].
+    pub async fn delete(
+        &mut self,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Option<Leaf<HASH_SIZE, H, S>>, TreeError<DbError>> {
+        let (leaf, writes) = stage(
+            self.db.as_ref(),
+            key,
+            |tree: &mut CompactMSSMT<_, _, _, _>| tree.delete(key),
+        )
+        .await?;
+        apply(self.db.as_mut(), writes).await?;
+        Ok(leaf)
+    }
+
+    /// Merkle proof of the leaf stored at `key`.
+    pub async fn merkle_proof(
+        &self,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Proof<HASH_SIZE, H, S>, TreeError<DbError>> {
+        let (proof, _) = stage(
+            self.db.as_ref(),
+            key,
+            |tree: &mut CompactMSSMT<_, _, _, _>| tree.merkle_proof(key),
+        )
+        .await?;
+        Ok(proof)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{any::Any, sync::Arc};
+
+    use async_trait::async_trait;
+    use pollster::block_on;
+    use sha2::Sha256;
+
+    use super::{AsyncCompactMSSMT, AsyncMSSMT};
+    use crate::{
+        tests::test_leaves, tree::verify_merkle_proof, AsyncDb, AsyncMemoryDb, Branch, CompactLeaf,
+        CompactMSSMT, Leaf, MemoryDb, Node, TreeError, MSSMT,
+    };
+
+    #[test]
+    fn test_async_mssmt() {
+        block_on(async {
+            let mut tree = AsyncMSSMT::<32, Sha256, ()>::new(Box::new(AsyncMemoryDb::default()));
+            let mut expected = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+            assert_eq!(
+                tree.root().await.unwrap().hash(),
+                expected.root().unwrap().hash()
+            );
+            let leaves = test_leaves::<Sha256>();
+            for (key, leaf) in leaves.clone() {
+                tree.insert(key, leaf.clone()).await.unwrap();
+                expected.insert(key, leaf).unwrap();
+            }
+            assert_eq!(tree.delete([3; 32]).await.unwrap().unwrap().sum(), 3);
+            expected.delete([3; 32]).unwrap();
+            let key = leaves[3].0;
+            tree.insert(key, Leaf::new(vec![40; 32], 40)).await.unwrap();
+            expected.insert(key, Leaf::new(vec![40; 32], 40)).unwrap();
+
+            let root = tree.root().await.unwrap();
+            assert_eq!(root.hash(), expected.root().unwrap().hash());
+            assert_eq!(root.sum(), expected.root().unwrap().sum());
+            assert!(tree.get([3; 32]).await.unwrap().is_none());
+            let leaf = tree.get(key).await.unwrap().unwrap();
+            assert_eq!(leaf.sum(), 40);
+            let proof = tree.merkle_proof(key).await.unwrap();
+            verify_merkle_proof::<32, Sha256, (), _>(key, leaf, proof, root.hash()).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_async_compact_mssmt() {
+        block_on(async {
+            let mut tree =
+                AsyncCompactMSSMT::<32, Sha256, ()>::new(Box::new(AsyncMemoryDb::default()));
+            let mut expected = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+            let leaves = test_leaves::<Sha256>();
+            for (key, leaf) in leaves.clone() {
+                tree.insert(key, leaf.clone()).await.unwrap();
+                expected.insert(key, leaf).unwrap();
+            }
+            for key in [[1; 32], [3; 32]] {
+                assert!(tree.delete(key).await.unwrap().is_some());
+                expected.delete(key).unwrap();
+            }
+            assert!(tree.delete([30; 32]).await.unwrap().is_none());
+
+            let root = tree.root().await.unwrap();
+            assert_eq!(root.hash(), expected.root().unwrap().hash());
+            for (key, leaf) in leaves {
+                assert_eq!(
+                    tree.get(key).await.unwrap().map(|leaf| leaf.sum()),
+                    expected.get(key).unwrap().map(|leaf| leaf.sum())
+                );
+                if expected.contains(key).unwrap() {
+                    let proof = tree.merkle_proof(key).await.unwrap();
+                    verify_merkle_proof::<32, Sha256, (), _>(key, leaf, proof, root.hash())
+                        .unwrap();
+                }
+            }
+        });
+    }
+
+    /// Async database failing the branch inserts once `writes_left` is exhausted.
+    struct FailingDb {
+        db: AsyncMemoryDb<32, Sha256>,
+        writes_left: usize,
+    }
+
+    #[cfg_attr(feature = "multi-thread", async_trait)]
+    #[cfg_attr(not(feature = "multi-thread"), async_trait(?Send))]
+    impl AsyncDb<32, Sha256> for FailingDb {
+        type DbError = ();
+
+        async fn get_root_node(&self) -> Result<Option<Branch<32, Sha256>>, TreeError<()>> {
+            self.db.get_root_node().await
+        }
+
+        async fn get_children(
+            &self,
+            height: usize,
+            key: [u8; 32],
+        ) -> Result<(Node<32, Sha256>, Node<32, Sha256>), TreeError<()>> {
+            self.db.get_children(height, key).await
+        }
+
+        async fn insert_leaf(&mut self, leaf: Leaf<32, Sha256>) -> Result<(), TreeError<()>> {
+            self.db.insert_leaf(leaf).await
+        }
+
+        async fn insert_branch(&mut self, branch: Branch<32, Sha256>) -> Result<(), TreeError<()>> {
+            self.writes_left = self
+                .writes_left
+                .checked_sub(1)
+                .ok_or(TreeError::DbError(()))?;
+            self.db.insert_branch(branch).await
+        }
+
+        async fn insert_compact_leaf(
+            &mut self,
+            compact_leaf: CompactLeaf<32, Sha256>,
+        ) -> Result<(), TreeError<()>> {
+            self.db.insert_compact_leaf(compact_leaf).await
+        }
+
+        fn empty_tree(&self) -> Arc<[Node<32, Sha256>]> {
+            self.db.empty_tree()
+        }
+
+        async fn update_root(&mut self, root: Branch<32, Sha256>) -> Result<(), TreeError<()>> {
+            self.db.update_root(root).await
+        }
+
+        async fn delete_branch(&mut self, key: &[u8; 32]) -> Result<(), TreeError<()>> {
+            self.db.delete_branch(key).await
+        }
+
+        async fn delete_leaf(&mut self, key: &[u8; 32]) -> Result<(), TreeError<()>> {
+            self.db.delete_leaf(key).await
+        }
+
+        async fn delete_compact_leaf(&mut self, key: &[u8; 32]) -> Result<(), TreeError<()>> {
+            self.db.delete_compact_leaf(key).await
+        }
+
+        async fn begin(&mut self) -> Result<(), TreeError<()>> {
+            self.db.begin().await
+        }
+
+        async fn commit(&mut self) -> Result<(), TreeError<()>> {
+            self.db.commit().await
+        }
+
+        async fn rollback(&mut self) -> Result<(), TreeError<()>> {
+            self.db.rollback().await
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn test_async_failed_insert_is_rolled_back() {
+        block_on(async {
+            // The second insert fails in the middle of its branch inserts.
+            let mut tree = AsyncMSSMT::<32, Sha256, ()>::new(Box::new(FailingDb {
+                db: AsyncMemoryDb::default(),
+                writes_left: 300,
+            }));
+            tree.insert([1; 32], Leaf::new(vec![1; 32], 1))
+                .await
+                .unwrap();
+            let root = tree.root().await.unwrap().hash();
+            let stored = |tree: &AsyncMSSMT<32, Sha256, ()>| {
+                let db = tree.db().as_any().downcast_ref::<FailingDb>().unwrap();
+                let db = db.db.inner();
+                (db.get_branches().len(), db.get_leaves().len())
+            };
+            let before = stored(&tree);
+            assert_eq!(
+                tree.insert([2; 32], Leaf::new(vec![2; 32], 2))
+                    .await
+                    .unwrap_err(),
+                TreeError::DbError(())
+            );
+            assert_eq!(tree.root().await.unwrap().hash(), root);
+            assert_eq!(stored(&tree), before);
+            assert!(tree.get([2; 32]).await.unwrap().is_none());
+            assert_eq!(
+                tree.insert([2; 32], Leaf::new(vec![2; 32], u64::MAX))
+                    .await
+                    .unwrap_err(),
+                TreeError::SumOverflow
+            );
+        });
+    }
+
+    #[cfg(feature = "multi-thread")]
+    #[test]
+    fn test_async_futures_are_send() {
+        fn assert_send<T: Send>(_: T) {}
+        let mut tree = AsyncMSSMT::<32, Sha256, ()>::new(Box::new(AsyncMemoryDb::default()));
+        assert_send(tree.get([1; 32]));
+        assert_send(tree.insert([1; 32], Leaf::new(vec![1; 32], 1)));
+    }
+}
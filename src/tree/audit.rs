@@ -0,0 +1,361 @@
+//! Integrity audit of the nodes stored for a tree.
+
+use crate::{
+    node::{Branch, CompactLeaf, Hasher, Leaf, Node, Sum},
+    Db, NodeKind, TreeError,
+};
+
+/// Corruption of a stored node found by an audit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Corruption<const HASH_SIZE: usize, DbError> {
+    /// The node is referenced by its parent but isn't stored
+    MissingNode,
+    /// The hash of the node doesn't match its content: the children of a branch, the value
+    /// and sum of a leaf, or the height, key and leaf of a compact leaf
+    HashMismatch {
+        /// Hash computed from the content of the node
+        computed: [u8; HASH_SIZE],
+    },
+    /// The sum of the branch isn't the sum of its children, or it overflows
+    SumMismatch,
+    /// A node of this kind can't be at this height of the tree
+    UnexpectedNode,
+    /// The node can't be read from the database
+    DbError(TreeError<DbError>),
+}
+
+/// A corrupted node and where it is in the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditIssue<const HASH_SIZE: usize, DbError> {
+    /// Height of the node in the tree, 0 being the root
+    pub height: usize,
+    /// Hash the node is referenced by
+    pub hash: [u8; HASH_SIZE],
+    /// What is wrong with the node
+    pub corruption: Corruption<HASH_SIZE, DbError>,
+}
+
+/// Result of the audit of a tree, see [`MSSMT::audit`](crate::MSSMT::audit).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditReport<const HASH_SIZE: usize, DbError> {
+    /// Number of nodes checked, the empty subtrees aside
+    pub nodes: usize,
+    /// Corrupted nodes, in the order they were found
+    pub issues: Vec<AuditIssue<HASH_SIZE, DbError>>,
+}
+
+impl<const HASH_SIZE: usize, DbError> AuditReport<HASH_SIZE, DbError> {
+    /// Returns `true` if no corruption was found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Checks every node reachable from `root`, down to the leaves at `max_height`.
+///
+/// Each branch is checked against the children stored under the hashes it references, so
+/// the whole tree matches `root` if no issue is found. Empty subtrees are skipped.
+pub(crate) fn audit<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError, S: Sum>(
+    db: &dyn Db<HASH_SIZE, H, S, DbError = DbError>,
+    root: Branch<HASH_SIZE, H, S>,
+    max_height: usize,
+) -> AuditReport<HASH_SIZE, DbError> {
+    let empty_tree = db.empty_tree();
+    let mut report = AuditReport {
+        nodes: 0,
+        issues: Vec::new(),
+    };
+    let mut issue = |height, hash, corruption| {
+        report.issues.push(AuditIssue {
+            height,
+            hash,
+            corruption,
+        })
+    };
+    // A node is missing if it isn't an empty subtree and isn't stored, under any kind for a
+    // node only known by hash.
+    let is_missing = |height: usize, node: &Node<HASH_SIZE, H, S>| {
+        let hash = node.hash();
+        if hash == empty_tree[height].hash() {
+            return Ok(false);
+        }
+        let kinds = match NodeKind::of(node) {
+            Some(kind) => vec![kind],
+            None => NodeKind::ALL.to_vec(),
+        };
+        for kind in kinds {
+            if db.contains(kind, &hash)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    };
+    let mut stack = vec![(0, Node::Branch(root))];
+    let mut nodes = 0;
+    while let Some((height, node)) = stack.pop() {
+        let hash = node.hash();
+        if hash == empty_tree[height].hash() {
+            continue;
+        }
+        nodes += 1;
+        let computed = match &node {
+            Node::Branch(branch) if height < max_height => {
+                let (left, right) = match db.get_children(height, hash) {
+                    Ok(children) => children,
+                    Err(TreeError::NodeNotFound) => {
+                        // Either the branch or some of its children aren't stored. The ones
+                        // that are stored are still checked if the branch holds them.
+                        let mut found = false;
+                        match is_missing(height, &node) {
+                            Ok(false) => {
+                                for child in [branch.right(), branch.left()] {
+                                    match is_missing(height + 1, child) {
+                                        Ok(false) if matches!(child, Node::Computed(_)) => {}
+                                        Ok(false) => stack.push((height + 1, child.clone())),
+                                        Ok(true) => {
+                                            issue(
+                                                height + 1,
+                                                child.hash(),
+                                                Corruption::MissingNode,
+                                            );
+                                            found = true;
+                                        }
+                                        Err(e) => {
+                                            issue(height + 1, child.hash(), Corruption::DbError(e));
+                                            found = true;
+                                        }
+                                    }
+                                }
+                            }
+                            Ok(true) => {}
+                            Err(e) => {
+                                issue(height, hash, Corruption::DbError(e));
+                                found = true;
+                            }
+                        }
+                        if !found {
+                            issue(height, hash, Corruption::MissingNode);
+                        }
+                        continue;
+                    }
+                    Err(e) => {
+                        issue(height, hash, Corruption::DbError(e));
+                        continue;
+                    }
+                };
+                // A child that isn't stored may be read as an empty subtree or from the branch
+                // that references it, the branch can't be checked against it.
+                let mut missing = false;
+                for (child, expected) in [(&right, branch.right()), (&left, branch.left())] {
+                    if child.hash() != expected.hash() {
+                        issue(height + 1, expected.hash(), Corruption::MissingNode);
+                        missing = true;
+                        continue;
+                    }
+                    match is_missing(height + 1, child) {
+                        Ok(false) => stack.push((height + 1, child.clone())),
+                        Ok(true) => {
+                            issue(height + 1, child.hash(), Corruption::MissingNode);
+                            missing = true;
+                        }
+                        Err(e) => {
+                            issue(height + 1, child.hash(), Corruption::DbError(e));
+                            missing = true;
+                        }
+                    }
+                }
+                if missing {
+                    continue;
+                }
+                match Branch::try_new::<DbError>(left, right) {
+                    Ok(computed) if computed.sum() == branch.sum() => computed.hash(),
+                    Ok(computed) => {
+                        issue(height, hash, Corruption::SumMismatch);
+                        computed.hash()
+                    }
+                    Err(_) => {
+                        issue(height, hash, Corruption::SumMismatch);
+                        continue;
+                    }
+                }
+            }
+            Node::Leaf(leaf) if height == max_height => {
+                Leaf::<HASH_SIZE, H, S>::new(leaf.value().to_vec(), leaf.sum()).hash()
+            }
+            Node::Compact(compact_leaf) if height > 0 => {
+                let leaf = compact_leaf.leaf();
+                // The leaf of a compact leaf is stored on its own too.
+                match db.contains(NodeKind::Leaf, &leaf.hash()) {
+                    Ok(true) => {}
+                    Ok(false) => issue(max_height, leaf.hash(), Corruption::MissingNode),
+                    Err(e) => issue(max_height, leaf.hash(), Corruption::DbError(e)),
+                }
+                let leaf = Leaf::<HASH_SIZE, H, S>::new(leaf.value().to_vec(), leaf.sum());
+                CompactLeaf::new(height, *compact_leaf.key(), leaf).hash()
+            }
+            _ => {
+                issue(height, hash, Corruption::UnexpectedNode);
+                continue;
+            }
+        };
+        if computed != hash {
+            issue(height, hash, Corruption::HashMismatch { computed });
+        }
+    }
+    report.nodes = nodes;
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AuditIssue, Corruption};
+    use crate::{
+        tests::test_leaves, Branch, CompactLeaf, CompactMSSMT, Db, Leaf, MemoryDb, Node, MSSMT,
+    };
+    use sha2::Sha256;
+
+    /// Copy of the database of `tree`.
+    fn memory_db(tree: &dyn Db<32, Sha256, DbError = ()>) -> MemoryDb<32, Sha256> {
+        tree.as_any()
+            .downcast_ref::<MemoryDb<32, Sha256>>()
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn test_audit_intact_trees() {
+        let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        let report = tree.audit();
+        assert!(report.is_ok());
+        assert_eq!(report.nodes, 0);
+        tree.insert_batch(test_leaves::<Sha256>()).unwrap();
+        let report = tree.audit();
+        assert!(report.is_ok());
+        assert!(report.nodes > 256);
+        assert_eq!(tree.verify_integrity(), Ok(()));
+
+        let mut tree = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        tree.insert_batch(test_leaves::<Sha256>()).unwrap();
+        tree.delete([3; 32]).unwrap();
+        assert!(tree.audit().is_ok());
+        assert_eq!(tree.verify_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_audit_mssmt_corruption() {
+        let mut tree = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        tree.insert_batch(test_leaves::<Sha256>()).unwrap();
+        let mut db = memory_db(tree.db());
+        // A leaf is lost and another one is altered.
+        let lost = test_leaves::<Sha256>()[0].1.hash();
+        db.delete_leaf(&lost).unwrap();
+        let altered = test_leaves::<Sha256>()[1].1.hash();
+        db.delete_leaf(&altered).unwrap();
+        let altered_leaf = unsafe { Leaf::new_with_hash(vec![0; 32], 2, altered) };
+        db.insert_leaf(altered_leaf).unwrap();
+        // The root claims a sum its children don't have.
+        let root = tree.root().unwrap();
+        let (left, right) = root.children();
+        let root = unsafe { Branch::new_with_hash(left.clone(), right.clone(), root.hash(), 1) };
+        db.update_root(root.clone()).unwrap();
+
+        let tree = MSSMT::<32, Sha256, ()>::new(Box::new(db));
+        let report = tree.verify_integrity().unwrap_err();
+        assert_eq!(report.issues.len(), 3);
+        assert_eq!(
+            report.issues[0],
+            AuditIssue {
+                height: 0,
+                hash: root.hash(),
+                corruption: Corruption::SumMismatch,
+            }
+        );
+        assert!(report.issues.contains(&AuditIssue {
+            height: 256,
+            hash: lost,
+            corruption: Corruption::MissingNode,
+        }));
+        assert!(report.issues.contains(&AuditIssue {
+            height: 256,
+            hash: altered,
+            corruption: Corruption::HashMismatch {
+                computed: Leaf::<32, Sha256>::new(vec![0; 32], 2).hash(),
+            },
+        }));
+    }
+
+    #[test]
+    fn test_audit_compact_mssmt_corruption() {
+        let mut tree = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        tree.insert_batch(test_leaves::<Sha256>()).unwrap();
+        let mut db = memory_db(tree.db());
+        // A compact leaf moved to another key, alone on the left of the root as its key is
+        // the only even one.
+        let (hash, compact_leaf) = db
+            .get_compact_leaves()
+            .iter()
+            .find(|(_, compact_leaf)| compact_leaf.key() == &[2; 32])
+            .map(|(hash, compact_leaf)| (*hash, compact_leaf.clone()))
+            .unwrap();
+        db.delete_compact_leaf(&hash).unwrap();
+        let moved =
+            unsafe { CompactLeaf::new_with_hash(hash, compact_leaf.leaf().clone(), [0; 32]) };
+        db.insert_compact_leaf(moved).unwrap();
+        // A leaf stored in place of the branch on the right of the root.
+        let root = tree.root().unwrap();
+        let Node::Branch(branch) = root.right().clone() else {
+            panic!("The odd keys share the right of the root");
+        };
+        db.delete_branch(&branch.hash()).unwrap();
+        let leaf = unsafe { Leaf::new_with_hash(vec![0; 32], branch.sum(), branch.hash()) };
+        db.insert_leaf(leaf).unwrap();
+
+        let tree = CompactMSSMT::<32, Sha256, ()>::new(Box::new(db));
+        let report = tree.audit();
+        assert_eq!(report.issues.len(), 2);
+        assert!(matches!(
+            report.issues[0],
+            AuditIssue {
+                hash: h,
+                corruption: Corruption::HashMismatch { .. },
+                ..
+            } if h == hash
+        ));
+        assert_eq!(report.issues[1].hash, branch.hash());
+        assert_eq!(report.issues[1].corruption, Corruption::UnexpectedNode);
+    }
+
+    #[test]
+    fn test_audit_compact_mssmt_missing_records() {
+        let mut tree = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        tree.insert_batch(test_leaves::<Sha256>()).unwrap();
+        let mut db = memory_db(tree.db());
+        // The record of the compact leaf alone on the left of the root is lost, and the leaf
+        // record of one on the right.
+        let compact_leaf = |key| {
+            db.get_compact_leaves()
+                .values()
+                .find(|compact_leaf| compact_leaf.key() == &key)
+                .unwrap()
+                .clone()
+        };
+        let lost = compact_leaf([2; 32]).hash();
+        let lost_leaf = compact_leaf([1; 32]).leaf().hash();
+        db.delete_compact_leaf(&lost).unwrap();
+        db.delete_leaf(&lost_leaf).unwrap();
+
+        let tree = CompactMSSMT::<32, Sha256, ()>::new(Box::new(db));
+        let report = tree.verify_integrity().unwrap_err();
+        assert_eq!(report.issues.len(), 2);
+        assert!(report.issues.contains(&AuditIssue {
+            height: 1,
+            hash: lost,
+            corruption: Corruption::MissingNode,
+        }));
+        assert!(report.issues.contains(&AuditIssue {
+            height: 256,
+            hash: lost_leaf,
+            corruption: Corruption::MissingNode,
+        }));
+    }
+}
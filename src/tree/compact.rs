@@ -5,14 +5,18 @@
 //! This significantly reduces the storage requirements while maintaining the same cryptographic properties.
 
 use std::marker::PhantomData;
-use typenum::Unsigned;
 
 use crate::{
-    node::{Branch, CompactLeaf, Hasher, Leaf, Node},
-    Db, TreeError, TreeSize,
+    node::{Branch, CompactLeaf, Hasher, Leaf, Node, Sum},
+    proof::{NonInclusionProof, Proof},
+    Db, GcReport, TreeError,
 };
 
-use super::regular::bit_index;
+use super::{
+    audit::{audit, AuditIssue, AuditReport, Corruption},
+    regular::bit_index,
+    sort_batch,
+};
 
 /// A compact Merkle Sum Sparse Merkle Tree implementation.
 ///
@@ -23,38 +27,120 @@ use super::regular::bit_index;
 ///
 /// * `HASH_SIZE`: The size of the hash output in bytes
 /// * `H`: The hash function implementation that implements the [`Hasher`] trait
-pub struct CompactMSSMT<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError> {
+/// * `DbError`: The error type of the database backend
+/// * `S`: The type of the sums, `u64` by default
+pub struct CompactMSSMT<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError, S: Sum = u64>
+{
     /// The database backend for storing tree nodes
-    db: Box<dyn Db<HASH_SIZE, H, DbError = DbError>>,
+    db: Box<dyn Db<HASH_SIZE, H, S, DbError = DbError>>,
     /// PhantomData for the hash function type
     _phantom: PhantomData<H>,
 }
 
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>
-    CompactMSSMT<HASH_SIZE, H, DbError>
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError, S: Sum>
+    CompactMSSMT<HASH_SIZE, H, DbError, S>
 {
     /// Creates a new empty compact MS-SMT with the given database backend.
-    pub fn new(db: Box<dyn Db<HASH_SIZE, H, DbError = DbError>>) -> Self {
+    pub fn new(db: Box<dyn Db<HASH_SIZE, H, S, DbError = DbError>>) -> Self {
         Self {
             db,
             _phantom: PhantomData,
         }
     }
 
+    /// Builds a compact MS-SMT holding exactly the given leaves.
+    ///
+    /// The tree is built bottom-up by splitting the keys on each bit of their path, without
+    /// reading `db`, which must not hold a tree yet. Subtrees with a single leaf become a
+    /// [`CompactLeaf`] and empty ones are left to the
+    /// [`EmptyTree`](crate::tree::EmptyTree).
+    ///
+    /// The leaves are expected in path order, i.e. sorted by key with the bits of each byte
+    /// read from the least significant one, and without duplicate keys. Any other input is
+    /// sorted first, the last leaf of a key winning like with repeated inserts.
+    ///
+    /// # Returns
+    ///
+    /// Returns [`TreeError::DbNotEmpty`] if `db` has a root, as its nodes would be left
+    /// unreachable, or an error if the sum of all the leaves overflows
+    pub fn from_sorted_leaves(
+        db: Box<dyn Db<HASH_SIZE, H, S, DbError = DbError>>,
+        leaves: impl IntoIterator<Item = ([u8; HASH_SIZE], Leaf<HASH_SIZE, H, S>)>,
+    ) -> Result<Self, TreeError<DbError>> {
+        if db.get_root_node().is_some() {
+            return Err(TreeError::DbNotEmpty);
+        }
+        let mut tree = Self::new(db);
+        let mut leaves: Vec<_> = leaves.into_iter().collect();
+        let is_sorted = leaves
+            .windows(2)
+            .all(|pair| pair[0].0.map(u8::reverse_bits) < pair[1].0.map(u8::reverse_bits));
+        if !is_sorted {
+            leaves = sort_batch(leaves);
+        }
+        // Empty leaves don't hold anything.
+        leaves.retain(|(_, leaf)| !leaf.is_empty());
+
+        leaves
+            .iter()
+            .try_fold(S::default(), |sum, (_, leaf)| sum.checked_add(leaf.sum()))
+            .ok_or(TreeError::SumOverflow)?;
+
+        tree.atomic(|tree| {
+            let Node::Branch(root) = tree.build_at(0, &leaves)? else {
+                unreachable!("The root node is never folded into a compact leaf.");
+            };
+            tree.db.update_root(root)
+        })?;
+        Ok(tree)
+    }
+
+    /// Builds the subtree at `height` holding the sorted non-empty `leaves` and stores it,
+    /// except if it's a [`CompactLeaf`] which is stored by its parent.
+    fn build_at(
+        &mut self,
+        height: usize,
+        leaves: &[([u8; HASH_SIZE], Leaf<HASH_SIZE, H, S>)],
+    ) -> Result<Node<HASH_SIZE, H, S>, TreeError<DbError>> {
+        match leaves {
+            [] => return Ok(self.db.empty_tree()[height].clone()),
+            // The root always stays a branch.
+            [(key, leaf)] if height > 0 => {
+                self.db.insert_leaf(leaf.clone())?;
+                return Ok(Node::Compact(CompactLeaf::new(height, *key, leaf.clone())));
+            }
+            _ => {}
+        }
+        let split = leaves.partition_point(|(key, _)| bit_index(height, key) == 0);
+        let left = self.build_at(height + 1, &leaves[..split])?;
+        let right = self.build_at(height + 1, &leaves[split..])?;
+        for child in [&left, &right] {
+            if let Node::Compact(compact) = child {
+                self.db.insert_compact_leaf(compact.clone())?;
+            }
+        }
+        let branch = Branch::try_new(left, right)?;
+        // Only insert this new branch if not a default one
+        if branch.hash() != self.db.empty_tree()[height].hash() {
+            self.db.insert_branch(branch.clone())?;
+        }
+        Ok(Node::Branch(branch))
+    }
+
     /// Returns the maximum number of levels in the tree (HASH_SIZE * 8)
     pub fn max_levels() -> usize {
-        TreeSize::USIZE - 1
+        HASH_SIZE * 8
     }
 
     /// Returns a reference to the underlying database.
-    pub fn db(&self) -> &dyn Db<HASH_SIZE, H, DbError = DbError> {
+    pub fn db(&self) -> &dyn Db<HASH_SIZE, H, S, DbError = DbError> {
         self.db.as_ref()
     }
 
     /// Returns the root node of the tree.
     ///
     /// If the tree is empty, returns the default empty root node.
-    pub fn root(&self) -> Result<Branch<HASH_SIZE, H>, TreeError<DbError>> {
+    pub fn root(&self) -> Result<Branch<HASH_SIZE, H, S>, TreeError<DbError>> {
         if let Some(branch) = self.db.get_root_node() {
             Ok(branch)
         } else {
@@ -82,13 +168,34 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>
     pub fn walk_down(
         &self,
         path: &[u8; HASH_SIZE],
-        mut for_each: impl FnMut(usize, &Node<HASH_SIZE, H>, &Node<HASH_SIZE, H>, &Node<HASH_SIZE, H>),
-    ) -> Result<Leaf<HASH_SIZE, H>, TreeError<DbError>> {
+        for_each: impl FnMut(
+            usize,
+            &Node<HASH_SIZE, H, S>,
+            &Node<HASH_SIZE, H, S>,
+            &Node<HASH_SIZE, H, S>,
+        ),
+    ) -> Result<Leaf<HASH_SIZE, H, S>, TreeError<DbError>> {
+        self.walk_down_from(self.root()?, path, for_each)
+    }
+
+    /// Walks down the tree from `root` following the given path, see
+    /// [`CompactMSSMT::walk_down`].
+    fn walk_down_from(
+        &self,
+        root: Branch<HASH_SIZE, H, S>,
+        path: &[u8; HASH_SIZE],
+        mut for_each: impl FnMut(
+            usize,
+            &Node<HASH_SIZE, H, S>,
+            &Node<HASH_SIZE, H, S>,
+            &Node<HASH_SIZE, H, S>,
+        ),
+    ) -> Result<Leaf<HASH_SIZE, H, S>, TreeError<DbError>> {
         // Start from the root node
-        let mut current = Node::Branch(self.db.get_root_node().ok_or(TreeError::NodeNotFound)?);
+        let mut current = Node::Branch(root);
         for i in 0..Self::max_levels() {
             // Get the children of the current node
-            let (left, right) = self.db.get_children(i, current.hash())?;
+            let (left, right) = self.get_children(i, current.hash(), path)?;
             // Order the children based on the path
             let (mut next, mut sibling) = Self::step_order(i, path, left, right);
             match next {
@@ -153,6 +260,46 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>
         Ok(leaf)
     }
 
+    /// Returns the leaf stored at the given key, or `None` if there is no leaf at `key`.
+    ///
+    /// The lookup stops as soon as it reaches an empty subtree or a compact leaf.
+    pub fn get(
+        &self,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Option<Leaf<HASH_SIZE, H, S>>, TreeError<DbError>> {
+        self.get_from(self.root()?, key)
+    }
+
+    /// Returns the leaf stored at the given key in the tree of root `root`.
+    fn get_from(
+        &self,
+        root: Branch<HASH_SIZE, H, S>,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Option<Leaf<HASH_SIZE, H, S>>, TreeError<DbError>> {
+        let empty_tree = self.db.empty_tree();
+        let mut current = root.hash();
+        for i in 0..Self::max_levels() {
+            let (left, right) = self.get_children(i, current, &key)?;
+            let (next, _) = Self::step_order(i, &key, left, right);
+            match Self::last_level_compact(i + 1, &key, next) {
+                next if next.hash() == empty_tree[i + 1].hash() => return Ok(None),
+                // A compact leaf holds the only leaf of its subtree.
+                Node::Compact(compact) if *compact.key() == key => {
+                    return Ok(Some(compact.leaf().clone()))
+                }
+                Node::Compact(_) => return Ok(None),
+                node @ (Node::Branch(_) | Node::Computed(_)) => current = node.hash(),
+                Node::Leaf(_) => return Err(TreeError::ExpectedBranch),
+            }
+        }
+        Err(TreeError::ExpectedLeaf)
+    }
+
+    /// Returns `true` if a leaf is stored at the given key.
+    pub fn contains(&self, key: [u8; HASH_SIZE]) -> Result<bool, TreeError<DbError>> {
+        Ok(self.get(key)?.is_some())
+    }
+
     /// Creates a common subtree from two leaves that share a partial path.
     ///
     /// # Arguments
@@ -170,10 +317,10 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>
         &mut self,
         height: usize,
         key1: [u8; HASH_SIZE],
-        leaf1: Leaf<HASH_SIZE, H>,
+        leaf1: Leaf<HASH_SIZE, H, S>,
         key2: [u8; HASH_SIZE],
-        leaf2: Leaf<HASH_SIZE, H>,
-    ) -> Result<Branch<HASH_SIZE, H>, TreeError<DbError>> {
+        leaf2: Leaf<HASH_SIZE, H, S>,
+    ) -> Result<Branch<HASH_SIZE, H, S>, TreeError<DbError>> {
         // Find the common prefix first
         let mut i = 0;
         // As long as the key bits are the same we can continue
@@ -193,7 +340,7 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>
         self.db.insert_compact_leaf(node1.clone())?;
         self.db.insert_compact_leaf(node2.clone())?;
         let (left, right) = Self::step_order(i, &key1, Node::Compact(node1), Node::Compact(node2));
-        let mut parent = Branch::new(left, right);
+        let mut parent = Branch::try_new(left, right)?;
         self.db.insert_branch(parent.clone())?;
 
         // From here we'll walk up to the current level and create branches
@@ -205,7 +352,7 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>
                 Node::Branch(parent),
                 self.db.empty_tree()[i + 1].clone(),
             );
-            parent = Branch::new(left, right);
+            parent = Branch::try_new(left, right)?;
             self.db.insert_branch(parent.clone())?;
         }
 
@@ -223,10 +370,10 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>
         key: &[u8; HASH_SIZE],
         height: usize,
         root_hash: &[u8; HASH_SIZE],
-        leaf: Leaf<HASH_SIZE, H>,
-    ) -> Result<Branch<HASH_SIZE, H>, TreeError<DbError>> {
+        leaf: Leaf<HASH_SIZE, H, S>,
+    ) -> Result<Branch<HASH_SIZE, H, S>, TreeError<DbError>> {
         // Get the children of the current node
-        let (left, right) = self.db.get_children(height, *root_hash)?;
+        let (left, right) = self.get_children(height, *root_hash, key)?;
         // Order the children based on the path
         let is_left = bit_index(height, key) == 0;
         let (next, sibling) = if is_left {
@@ -236,22 +383,22 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>
         };
 
         let next_height = height + 1;
+        let next = Self::last_level_compact(next_height, key, next);
 
         let new_node = match next {
-            Node::Branch(node) => {
-                if node.hash() == self.db.empty_tree()[next_height].hash() {
-                    // This is an empty subtree, so we can just walk up
-                    // from the leaf to recreate the node key for this
-                    // subtree then replace it with a compacted leaf.
-                    let new_leaf = CompactLeaf::new(next_height, *key, leaf.clone());
-                    self.db.insert_leaf(leaf)?;
-                    self.db.insert_compact_leaf(new_leaf.clone())?;
-                    Node::Compact(new_leaf)
-                } else {
-                    // Not an empty subtree, recurse down the tree to find
-                    // the insertion point for the leaf.
-                    Node::Branch(self.insert_leaf(key, next_height, &node.hash(), leaf)?)
-                }
+            next if next.hash() == self.db.empty_tree()[next_height].hash() => {
+                // This is an empty subtree, so we can just walk up
+                // from the leaf to recreate the node key for this
+                // subtree then replace it with a compacted leaf.
+                let new_leaf = CompactLeaf::new(next_height, *key, leaf.clone());
+                self.db.insert_leaf(leaf)?;
+                self.db.insert_compact_leaf(new_leaf.clone())?;
+                Node::Compact(new_leaf)
+            }
+            Node::Branch(_) | Node::Computed(_) => {
+                // Not an empty subtree, recurse down the tree to find
+                // the insertion point for the leaf.
+                Node::Branch(self.insert_leaf(key, next_height, &next.hash(), leaf)?)
             }
             Node::Compact(node) => {
                 // First delete the old leaf.
@@ -260,7 +407,6 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>
 
                 if *key == *node.key() {
                     // Replace of an existing leaf.
-                    // TODO: change to handle delete
                     let new_leaf = CompactLeaf::new(next_height, *key, leaf.clone());
                     self.db.insert_leaf(leaf)?;
                     self.db.insert_compact_leaf(new_leaf.clone())?;
@@ -276,22 +422,7 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>
                     )?)
                 }
             }
-            Node::Computed(node) => {
-                if node.hash() == self.db.empty_tree()[next_height].hash() {
-                    // This is an empty subtree, so we can just walk up
-                    // from the leaf to recreate the node key for this
-                    // subtree then replace it with a compacted leaf.
-                    let new_leaf = CompactLeaf::new(next_height, *key, leaf.clone());
-                    self.db.insert_leaf(leaf)?;
-                    self.db.insert_compact_leaf(new_leaf.clone())?;
-                    Node::Compact(new_leaf)
-                } else {
-                    // Not an empty subtree, recurse down the tree to find
-                    // the insertion point for the leaf.
-                    Node::Branch(self.insert_leaf(key, next_height, &node.hash(), leaf)?)
-                }
-            }
-            _ => return Err(TreeError::ExpectedBranch),
+            Node::Leaf(_) => return Err(TreeError::ExpectedBranch),
         };
 
         // Delete the old root if not empty
@@ -300,9 +431,9 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>
         }
         // Create the new root
         let branch = if is_left {
-            Branch::new(new_node, sibling)
+            Branch::try_new(new_node, sibling)?
         } else {
-            Branch::new(sibling, new_node)
+            Branch::try_new(sibling, new_node)?
         };
 
         // Only insert this new branch if not a default one
@@ -322,32 +453,365 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>
     ///
     /// # Returns
     ///
-    /// Returns an error if inserting the leaf would cause the tree's sum to overflow
+    /// Returns an error if inserting the leaf would cause the tree's sum to overflow.
+    /// Inserting an empty leaf deletes the leaf stored at `key`.
     pub fn insert(
         &mut self,
         key: [u8; HASH_SIZE],
-        leaf: Leaf<HASH_SIZE, H>,
+        leaf: Leaf<HASH_SIZE, H, S>,
     ) -> Result<(), TreeError<DbError>> {
-        // Get the root node
-        let root = if let Some(branch) = self.db.get_root_node() {
-            branch
-        } else {
-            let Node::Branch(branch) = self.db.empty_tree()[0].clone() else {
-                unreachable!("Invalid empty tree. The root node should always be a branch.");
+        // Inserting an empty leaf is the same as deleting the key.
+        if leaf.is_empty() {
+            return self.delete(key).map(|_| ());
+        }
+        self.atomic(|tree| {
+            // The root is read in the transaction, as another handle to a shared tree can
+            // update it until then.
+            let root = tree.root()?;
+            let new_root = tree.insert_leaf(&key, 0, &root.hash(), leaf)?;
+            tree.db.update_root(new_root)
+        })
+    }
+
+    /// Inserts a batch of leaves in the tree.
+    ///
+    /// The keys are sorted so the branches on common prefixes are only computed and written
+    /// once. The resulting tree is the same as inserting the leaves one by one in order,
+    /// empty leaves deleting their key.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if the sum of the updated tree overflows, the tree being left unchanged
+    pub fn insert_batch(
+        &mut self,
+        leaves: impl IntoIterator<Item = ([u8; HASH_SIZE], Leaf<HASH_SIZE, H, S>)>,
+    ) -> Result<(), TreeError<DbError>> {
+        let batch = sort_batch(leaves);
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.atomic(|tree| {
+            let root = tree.root()?;
+            let Node::Branch(new_root) = tree.insert_batch_at(0, Node::Branch(root), &batch)?
+            else {
+                unreachable!("The root node is never folded into a compact leaf.");
+            };
+            tree.db.update_root(new_root)
+        })
+    }
+
+    /// Inserts the sorted `batch` in the subtree `node` at `height` and returns the new
+    /// subtree. If the new subtree is a [`CompactLeaf`] it is not stored yet as it might be
+    /// folded further up.
+    fn insert_batch_at(
+        &mut self,
+        height: usize,
+        node: Node<HASH_SIZE, H, S>,
+        batch: &[([u8; HASH_SIZE], Leaf<HASH_SIZE, H, S>)],
+    ) -> Result<Node<HASH_SIZE, H, S>, TreeError<DbError>> {
+        let empty_tree = self.db.empty_tree();
+        let with_compact;
+        let mut batch = batch;
+        let node = match node {
+            // The leaf of a compact leaf is inserted again with the batch unless it's replaced.
+            Node::Compact(compact) => {
+                self.db.delete_leaf(&compact.leaf().hash())?;
+                self.db.delete_compact_leaf(&compact.hash())?;
+                let position = batch
+                    .binary_search_by_key(&compact.key().map(u8::reverse_bits), |(key, _)| {
+                        key.map(u8::reverse_bits)
+                    });
+                if let Err(position) = position {
+                    let mut merged = batch.to_vec();
+                    merged.insert(position, (*compact.key(), compact.leaf().clone()));
+                    with_compact = merged;
+                    batch = &with_compact;
+                }
+                empty_tree[height].clone()
+            }
+            node => node,
+        };
+
+        if node.hash() == empty_tree[height].hash() {
+            let mut leaves = batch.iter().filter(|(_, leaf)| !leaf.is_empty());
+            match (leaves.next(), leaves.next()) {
+                // Only deletions in an empty subtree.
+                (None, _) => return Ok(node),
+                // A single leaf in an empty subtree is compacted. The root always stays a branch.
+                (Some((key, leaf)), None) if height > 0 => {
+                    self.db.insert_leaf(leaf.clone())?;
+                    return Ok(Node::Compact(CompactLeaf::new(height, *key, leaf.clone())));
+                }
+                _ => {}
+            }
+        }
+
+        // Split the batch between the two children.
+        let (left, right) = self.get_children(height, node.hash(), &batch[0].0)?;
+        let split = batch.partition_point(|(key, _)| bit_index(height, key) == 0);
+        let mut children = [(left, &batch[..split]), (right, &batch[split..])];
+        let mut path = batch[0].0;
+        let mut is_new = [false; 2];
+        for (bit, ((child, child_batch), is_new)) in
+            children.iter_mut().zip(is_new.iter_mut()).enumerate()
+        {
+            path[height / 8] =
+                path[height / 8] & !(1 << (height % 8)) | ((bit as u8) << (height % 8));
+            *child = Self::last_level_compact(height + 1, &path, child.clone());
+            if !child_batch.is_empty() {
+                *child = self.insert_batch_at(height + 1, child.clone(), child_batch)?;
+                *is_new = true;
+            }
+        }
+        let [(left, _), (right, _)] = children;
+        self.join_batch_children(height, node.hash(), left, right, is_new)
+    }
+
+    /// Replaces the branch `old_hash` at `height` with the new `left` and `right` children
+    /// of [`Self::insert_batch_at`]. `is_new` tells which children have been rebuilt.
+    ///
+    /// Kept out of [`Self::insert_batch_at`] to keep its recursive frames small.
+    fn join_batch_children(
+        &mut self,
+        height: usize,
+        old_hash: [u8; HASH_SIZE],
+        left: Node<HASH_SIZE, H, S>,
+        right: Node<HASH_SIZE, H, S>,
+        is_new: [bool; 2],
+    ) -> Result<Node<HASH_SIZE, H, S>, TreeError<DbError>> {
+        let empty_tree = self.db.empty_tree();
+        if old_hash != empty_tree[height].hash() {
+            self.db.delete_branch(&old_hash)?;
+        }
+
+        // If this subtree is left with a single leaf, move that leaf up to this height.
+        let empty_child = empty_tree[height + 1].hash();
+        if height > 0 {
+            let alone = match (&left, &right) {
+                (Node::Compact(compact), right) if right.hash() == empty_child => {
+                    Some((compact, is_new[0]))
+                }
+                (left, Node::Compact(compact)) if left.hash() == empty_child => {
+                    Some((compact, is_new[1]))
+                }
+                _ => None,
             };
-            branch
+            if let Some((compact, is_new)) = alone {
+                if !is_new {
+                    self.db.delete_compact_leaf(&compact.hash())?;
+                }
+                return Ok(Node::Compact(Self::lift(
+                    height,
+                    compact.clone(),
+                    &empty_tree,
+                )));
+            }
+            if left.hash() == empty_child && right.hash() == empty_child {
+                return Ok(empty_tree[height].clone());
+            }
+        }
+
+        // The new compact leaves stopped moving up so they can be stored.
+        for (child, is_new) in [(&left, is_new[0]), (&right, is_new[1])] {
+            if let (Node::Compact(compact), true) = (child, is_new) {
+                self.db.insert_compact_leaf(compact.clone())?;
+            }
+        }
+        let branch = Branch::try_new(left, right)?;
+        // Only insert this new branch if not a default one
+        if branch.hash() != empty_tree[height].hash() {
+            self.db.insert_branch(branch.clone())?;
+        }
+        Ok(Node::Branch(branch))
+    }
+
+    /// Deletes the leaf stored at the given key.
+    ///
+    /// Subtrees that are left with a single leaf are folded back into a
+    /// [`CompactLeaf`] so the tree is stored exactly as if the key had never been inserted.
+    ///
+    /// # Returns
+    ///
+    /// Returns the removed leaf or `None` if there was no leaf at `key`.
+    pub fn delete(
+        &mut self,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Option<Leaf<HASH_SIZE, H, S>>, TreeError<DbError>> {
+        self.atomic(|tree| tree.remove(key))
+    }
+
+    /// Deletes the leaf stored at `key`, see [`CompactMSSMT::delete`].
+    fn remove(
+        &mut self,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Option<Leaf<HASH_SIZE, H, S>>, TreeError<DbError>> {
+        let empty_tree = self.db.empty_tree();
+        // Walk down the tree and collect the branches on the path and their siblings
+        // until the compact leaf of the key is found.
+        let mut path = Vec::new();
+        let mut current = self.root()?.hash();
+        let removed = loop {
+            let height = path.len();
+            let (left, right) = self.get_children(height, current, &key)?;
+            let (next, sibling) = Self::step_order(height, &key, left, right);
+            path.push((current, sibling));
+            match Self::last_level_compact(height + 1, &key, next) {
+                // Nothing to delete in an empty subtree.
+                next if next.hash() == empty_tree[height + 1].hash() => return Ok(None),
+                // The path ends on another leaf so the key is not in the tree.
+                Node::Compact(node) if *node.key() != key => return Ok(None),
+                Node::Compact(node) => break node,
+                node @ (Node::Branch(_) | Node::Computed(_)) => current = node.hash(),
+                Node::Leaf(_) => return Err(TreeError::ExpectedBranch),
+            }
+        };
+        self.db.delete_leaf(&removed.leaf().hash())?;
+        self.db.delete_compact_leaf(&removed.hash())?;
+
+        // Walk back up and rebuild the branches. `pending` is a leaf that has been
+        // left alone in its subtree and moves up until it meets another node.
+        let mut new_node = empty_tree[path.len()].clone();
+        let mut pending: Option<CompactLeaf<HASH_SIZE, H, S>> = None;
+        for (height, (old_hash, sibling)) in path.into_iter().enumerate().rev() {
+            let next_height = height + 1;
+            // The old branch always holds the removed leaf so it can't be empty.
+            self.db.delete_branch(&old_hash)?;
+
+            // The root always stays a branch.
+            if height > 0 {
+                let alone = if let Some(compact) = pending.take() {
+                    (sibling.hash() == empty_tree[next_height].hash()).then_some(compact)
+                } else if new_node.hash() == empty_tree[next_height].hash() {
+                    let mut sibling_key = key;
+                    sibling_key[height / 8] ^= 1 << (height % 8);
+                    match Self::last_level_compact(next_height, &sibling_key, sibling.clone()) {
+                        Node::Compact(compact) => {
+                            self.db.delete_compact_leaf(&compact.hash())?;
+                            Some(compact)
+                        }
+                        _ => None,
+                    }
+                } else {
+                    None
+                };
+                if let Some(compact) = alone {
+                    let compact = Self::lift(height, compact, &empty_tree);
+                    new_node = Node::Compact(compact.clone());
+                    pending = Some(compact);
+                    continue;
+                }
+            }
+            // The leaf stopped moving up so it can be stored.
+            if let Node::Compact(compact) = &new_node {
+                self.db.insert_compact_leaf(compact.clone())?;
+            }
+
+            let (left, right) = Self::step_order(height, &key, new_node, sibling);
+            let branch = Branch::try_new(left, right)?;
+            // Only insert this new branch if not a default one
+            if branch.hash() != empty_tree[height].hash() {
+                self.db.insert_branch(branch.clone())?;
+            }
+            new_node = Node::Branch(branch);
+        }
+
+        let Node::Branch(new_root) = new_node else {
+            unreachable!("The root node is never folded into a compact leaf.");
+        };
+        self.db.update_root(new_root)?;
+        Ok(Some(removed.leaf().clone()))
+    }
+
+    /// Returns the children of the branch `hash` at `height` on the path to `key`.
+    ///
+    /// The hash of a compact leaf only commits to the bits of its key below it, so identical
+    /// compact leaves under different prefixes are stored once with one of their keys. The
+    /// prefix of the key is taken from the path instead.
+    #[allow(clippy::type_complexity)]
+    fn get_children(
+        &self,
+        height: usize,
+        hash: [u8; HASH_SIZE],
+        key: &[u8; HASH_SIZE],
+    ) -> Result<(Node<HASH_SIZE, H, S>, Node<HASH_SIZE, H, S>), TreeError<DbError>> {
+        let (left, right) = self.db.get_children(height, hash)?;
+        let with_prefix = |bit: u8, node| {
+            let Node::Compact(compact) = node else {
+                return node;
+            };
+            let mut compact_key = *compact.key();
+            for i in 0..=height {
+                let bit = if i == height { bit } else { bit_index(i, key) };
+                compact_key[i / 8] = compact_key[i / 8] & !(1 << (i % 8)) | (bit << (i % 8));
+            }
+            if compact_key == *compact.key() {
+                return Node::Compact(compact);
+            }
+            // SAFETY: only the bits of the key that the hash doesn't commit to changed.
+            Node::Compact(unsafe {
+                CompactLeaf::new_with_hash(compact.hash(), compact.leaf().clone(), compact_key)
+            })
         };
+        Ok((with_prefix(0, left), with_prefix(1, right)))
+    }
 
-        // First we'll check if the sum of the root and new leaf will
-        // overflow. If so, we'll return an error.
-        let sum_root = root.sum();
-        let sum_leaf = leaf.sum();
-        if sum_root.checked_add(sum_leaf).is_none() {
-            return Err(TreeError::SumOverflow);
+    /// Runs `f` in a database transaction so the tree is left unchanged if it fails.
+    fn atomic<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, TreeError<DbError>>,
+    ) -> Result<T, TreeError<DbError>> {
+        self.db.begin()?;
+        match f(self) {
+            // A failed commit may leave some of the updates, they're rolled back too.
+            Ok(value) => match self.db.commit() {
+                Ok(()) => Ok(value),
+                Err(e) => {
+                    self.db.rollback()?;
+                    Err(e)
+                }
+            },
+            Err(e) => {
+                self.db.rollback()?;
+                Err(e)
+            }
         }
+    }
+
+    /// Moves a compact leaf from `height + 1` up to `height`, when its sibling is empty.
+    ///
+    /// `empty_tree` is the one of the database, which builds it only once.
+    fn lift(
+        height: usize,
+        compact: CompactLeaf<HASH_SIZE, H, S>,
+        empty_tree: &[Node<HASH_SIZE, H, S>],
+    ) -> CompactLeaf<HASH_SIZE, H, S> {
+        let (key, leaf) = (*compact.key(), compact.leaf().clone());
+        let (left, right) = Self::step_order(
+            height,
+            &key,
+            Node::Compact(compact),
+            empty_tree[height + 1].clone(),
+        );
+        // The sibling is empty so the sum of the branch can't overflow.
+        let node_hash = Branch::new(left, right).hash();
+        // SAFETY: `node_hash` is the hash of the branch this leaf compacts at `height`.
+        unsafe { CompactLeaf::new_with_hash(node_hash, leaf, key) }
+    }
 
-        let new_root = self.insert_leaf(&key, 0, &root.hash(), leaf)?;
-        self.db.update_root(new_root)
+    /// Leaves on the last level of the tree are stored under their own hash so the
+    /// database returns them as [`Node::Leaf`]. Since the whole path is known at that
+    /// point, they can be turned back into the [`CompactLeaf`] of `key`.
+    fn last_level_compact(
+        height: usize,
+        key: &[u8; HASH_SIZE],
+        node: Node<HASH_SIZE, H, S>,
+    ) -> Node<HASH_SIZE, H, S> {
+        match node {
+            Node::Leaf(leaf) if height == Self::max_levels() && !leaf.is_empty() => {
+                Node::Compact(CompactLeaf::new(height, *key, leaf))
+            }
+            node => node,
+        }
     }
 
     /// Helper function to order nodes based on a key bit at the given height.
@@ -357,9 +821,9 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>
     fn step_order(
         height: usize,
         key: &[u8; HASH_SIZE],
-        left: Node<HASH_SIZE, H>,
-        right: Node<HASH_SIZE, H>,
-    ) -> (Node<HASH_SIZE, H>, Node<HASH_SIZE, H>) {
+        left: Node<HASH_SIZE, H, S>,
+        right: Node<HASH_SIZE, H, S>,
+    ) -> (Node<HASH_SIZE, H, S>, Node<HASH_SIZE, H, S>) {
         if bit_index(height, key) == 0 {
             (left, right)
         } else {
@@ -379,23 +843,144 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>
     pub fn merkle_proof(
         &self,
         key: [u8; HASH_SIZE],
-    ) -> Result<Vec<Node<HASH_SIZE, H>>, TreeError<DbError>> {
+    ) -> Result<Proof<HASH_SIZE, H, S>, TreeError<DbError>> {
+        self.merkle_proof_from(self.root()?, key)
+    }
+
+    /// Returns the merkle proof for the given key in the tree of root `root`.
+    fn merkle_proof_from(
+        &self,
+        root: Branch<HASH_SIZE, H, S>,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Proof<HASH_SIZE, H, S>, TreeError<DbError>> {
         let mut proof = Vec::with_capacity(Self::max_levels());
         // Walk down the tree and collect the siblings
-        self.walk_down(&key, |_, _next, sibling, _| {
+        self.walk_down_from(root, &key, |_, _next, sibling, _| {
             proof.push(sibling.clone());
         })?;
         // Reverse the proof to get the correct order
         proof.reverse();
-        Ok(proof)
+        Proof::new(proof)
+    }
+
+    /// Returns a proof that the given key is not in the tree.
+    ///
+    /// If the path to the key ends on the compact leaf of another key, the branches of that
+    /// compact leaf are extracted to reach the empty leaf at `key`.
+    ///
+    /// # Returns
+    ///
+    /// Returns [`TreeError::ExpectedEmptyLeaf`] if a leaf is stored at `key`.
+    pub fn non_inclusion_proof(
+        &self,
+        key: [u8; HASH_SIZE],
+    ) -> Result<NonInclusionProof<HASH_SIZE, H, S>, TreeError<DbError>> {
+        let mut proof = Vec::with_capacity(Self::max_levels());
+        // Walk down the tree and collect the siblings
+        let leaf = self.walk_down(&key, |_, _next, sibling, _| {
+            proof.push(sibling.clone());
+        })?;
+        if !leaf.is_empty() {
+            return Err(TreeError::ExpectedEmptyLeaf);
+        }
+        // Reverse the proof to get the correct order
+        proof.reverse();
+        Proof::new(proof).map(NonInclusionProof::new)
+    }
+
+    /// Returns the current version of the tree, if the database keeps versions like
+    /// [`VersionedDb`](crate::VersionedDb).
+    pub fn version(&self) -> Option<u64> {
+        self.db.version()
+    }
+
+    /// Returns the root node of the tree at `version`.
+    ///
+    /// # Returns
+    ///
+    /// Returns [`TreeError::VersionNotFound`] if the database doesn't retain this version.
+    pub fn root_at(&self, version: u64) -> Result<Branch<HASH_SIZE, H, S>, TreeError<DbError>> {
+        if let Some(branch) = self.db.get_root_at(version)? {
+            Ok(branch)
+        } else {
+            let Node::Branch(branch) = self.db.empty_tree().as_ref()[0].clone() else {
+                unreachable!("Invalid empty tree. The root node should always be a branch.");
+            };
+            Ok(branch)
+        }
+    }
+
+    /// Returns the leaf stored at the given key at `version`, or `None` if there was no
+    /// leaf at `key`.
+    pub fn get_at(
+        &self,
+        version: u64,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Option<Leaf<HASH_SIZE, H, S>>, TreeError<DbError>> {
+        self.get_from(self.root_at(version)?, key)
+    }
+
+    /// Returns the merkle proof for the given key at `version`, to verify against
+    /// [`CompactMSSMT::root_at`].
+    pub fn merkle_proof_at(
+        &self,
+        version: u64,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Proof<HASH_SIZE, H, S>, TreeError<DbError>> {
+        self.merkle_proof_from(self.root_at(version)?, key)
+    }
+
+    /// Forgets the versions before `version` and deletes the nodes only they use.
+    pub fn prune_versions(&mut self, version: u64) -> Result<(), TreeError<DbError>> {
+        self.atomic(|tree| tree.db.prune_versions(version))
+    }
+
+    /// Deletes the nodes of the database that no tree or retained version uses, or only
+    /// counts them with `dry_run`.
+    ///
+    /// Returns [`TreeError::GcNotSupported`] if the database can't list its nodes.
+    pub fn gc(&mut self, dry_run: bool) -> Result<GcReport, TreeError<DbError>> {
+        self.atomic(|tree| tree.db.gc(dry_run))
+    }
+
+    /// Checks every stored node of the tree: recomputes the hash and sum of the branches,
+    /// the hash of the compact leaves from their height, key and leaf, and looks for the
+    /// nodes that are referenced but not stored.
+    ///
+    /// All the corrupted nodes are reported, the tree is intact if the report
+    /// [`is_ok`](AuditReport::is_ok).
+    pub fn audit(&self) -> AuditReport<HASH_SIZE, DbError> {
+        match self.root() {
+            Ok(root) => audit(self.db.as_ref(), root, Self::max_levels()),
+            Err(e) => AuditReport {
+                nodes: 0,
+                issues: vec![AuditIssue {
+                    height: 0,
+                    hash: [0; HASH_SIZE],
+                    corruption: Corruption::DbError(e),
+                }],
+            },
+        }
+    }
+
+    /// Audits the tree and returns the report if it found a corrupted node, see
+    /// [`CompactMSSMT::audit`].
+    pub fn verify_integrity(&self) -> Result<(), AuditReport<HASH_SIZE, DbError>> {
+        let report = self.audit();
+        if report.is_ok() {
+            Ok(())
+        } else {
+            Err(report)
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::CompactMSSMT;
-    use crate::{tree::verify_merkle_proof, Leaf, MemoryDb, TreeError};
+    use crate::{tree::verify_merkle_proof, CompactLeaf, Db, GcReport, Leaf, MemoryDb, TreeError};
     use hex_literal::hex;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
     use sha2::Sha256;
 
     #[test]
@@ -436,7 +1021,7 @@ mod test {
         mssmt.insert([0; 32], leaf.clone()).unwrap();
         let proof = mssmt.merkle_proof([0; 32]).unwrap();
         let root = mssmt.root().unwrap();
-        verify_merkle_proof::<32, Sha256, ()>([0; 32], leaf, proof, root.hash()).unwrap();
+        verify_merkle_proof::<32, Sha256, (), _>([0; 32], leaf, proof, root.hash()).unwrap();
     }
 
     #[test]
@@ -449,8 +1034,294 @@ mod test {
         let proof = mssmt.merkle_proof([1; 32]).unwrap();
         let root = mssmt.root().unwrap();
         assert_eq!(
-            verify_merkle_proof::<32, Sha256, ()>([0; 32], leaf, proof, root.hash()).unwrap_err(),
+            verify_merkle_proof::<32, Sha256, (), _>([0; 32], leaf, proof, root.hash())
+                .unwrap_err(),
             TreeError::InvalidMerkleProof
         );
     }
+
+    #[allow(clippy::type_complexity)]
+    fn stored_nodes(
+        tree: &CompactMSSMT<32, Sha256, ()>,
+    ) -> (Vec<[u8; 32]>, Vec<[u8; 32]>, Vec<[u8; 32]>) {
+        let db = tree
+            .db()
+            .as_any()
+            .downcast_ref::<MemoryDb<32, Sha256>>()
+            .unwrap();
+        let sorted = |mut keys: Vec<[u8; 32]>| {
+            keys.sort();
+            keys
+        };
+        (
+            sorted(db.get_branches().keys().copied().collect()),
+            sorted(db.get_leaves().keys().copied().collect()),
+            sorted(db.get_compact_leaves().keys().copied().collect()),
+        )
+    }
+
+    #[test]
+    fn test_compact_mssmt_delete_folds_subtree() {
+        let leaf1 = Leaf::new(vec![1; 32], 1);
+        let leaf2 = Leaf::new(vec![2; 32], 2);
+        let leaf3 = Leaf::new(vec![3; 32], 3);
+        // Keys 1 and 3 share a long prefix so they are merged deep in the tree.
+        let key1 = hex!("0100000000000000000000000000000000000000000000000000000000000000");
+        let key2 = hex!("0200000000000000000000000000000000000000000000000000000000000000");
+        let key3 = hex!("0100000000000000000000000000000000000000000000000000000000000080");
+
+        let mut mssmt = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        mssmt.insert(key1, leaf1.clone()).unwrap();
+        mssmt.insert(key2, leaf2.clone()).unwrap();
+        mssmt.insert(key3, leaf3.clone()).unwrap();
+        let mut expected = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        expected.insert(key1, leaf1).unwrap();
+        expected.insert(key2, leaf2).unwrap();
+
+        assert_eq!(mssmt.delete(key3).unwrap().unwrap().hash(), leaf3.hash());
+        assert_eq!(
+            mssmt.root().unwrap().hash(),
+            expected.root().unwrap().hash()
+        );
+        assert_eq!(stored_nodes(&mssmt), stored_nodes(&expected));
+    }
+
+    #[test]
+    fn test_compact_mssmt_gc() {
+        let key = |i: u8| {
+            let mut key = [0; 32];
+            key[0] = i;
+            key[31] = i % 4;
+            key
+        };
+        // Nodes left by updates that failed outside of a transaction.
+        let mut db = MemoryDb::new();
+        let stray = Leaf::new(vec![9; 32], 9);
+        db.insert_leaf(stray.clone()).unwrap();
+        db.insert_compact_leaf(CompactLeaf::new(1, key(9), stray))
+            .unwrap();
+        let mut mssmt = CompactMSSMT::<32, Sha256, ()>::new(Box::new(db));
+        let mut expected = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        for i in 0..16 {
+            // The same leaf under several keys is stored once.
+            let leaf = Leaf::new(vec![i % 3; 32], 1);
+            mssmt.insert(key(i), leaf.clone()).unwrap();
+            if i % 2 == 0 {
+                expected.insert(key(i), leaf).unwrap();
+            }
+        }
+        for i in (1..16).step_by(2) {
+            mssmt.delete(key(i)).unwrap();
+        }
+        let report = GcReport {
+            branches: 0,
+            leaves: 1,
+            compact_leaves: 1,
+        };
+        assert_eq!(mssmt.gc(true).unwrap(), report);
+        assert_eq!(mssmt.gc(false).unwrap(), report);
+        assert_eq!(mssmt.gc(true).unwrap().total(), 0);
+        assert_eq!(expected.gc(true).unwrap().total(), 0);
+        assert_eq!(
+            mssmt.root().unwrap().hash(),
+            expected.root().unwrap().hash()
+        );
+        assert_eq!(stored_nodes(&mssmt), stored_nodes(&expected));
+        for i in (0..16).step_by(2) {
+            let leaf = mssmt.get(key(i)).unwrap().unwrap();
+            let proof = mssmt.merkle_proof(key(i)).unwrap();
+            verify_merkle_proof::<32, Sha256, (), _>(
+                key(i),
+                leaf,
+                proof,
+                mssmt.root().unwrap().hash(),
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_compact_mssmt_delete_missing_key() {
+        let mut mssmt = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        assert!(mssmt.delete([1; 32]).unwrap().is_none());
+        mssmt.insert([1; 32], Leaf::new(vec![1; 32], 1)).unwrap();
+        let before = stored_nodes(&mssmt);
+        // The path to this key ends on the compact leaf of `[1; 32]`.
+        assert!(mssmt.delete([3; 32]).unwrap().is_none());
+        assert_eq!(stored_nodes(&mssmt), before);
+    }
+
+    #[test]
+    fn test_compact_mssmt_last_level_leaves() {
+        // Those keys only differ on the last bit so their leaves are on the last level.
+        let key1 = [0; 32];
+        let key2 = hex!("0000000000000000000000000000000000000000000000000000000000000080");
+        let leaf1 = Leaf::new(vec![1; 32], 1);
+        let leaf2 = Leaf::new(vec![2; 32], 2);
+
+        let mut mssmt = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        mssmt.insert(key1, leaf1.clone()).unwrap();
+        mssmt.insert(key2, Leaf::new(vec![3; 32], 3)).unwrap();
+        // Replace the leaf on the last level.
+        mssmt.insert(key2, leaf2.clone()).unwrap();
+
+        let mut expected = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        expected.insert(key1, leaf1.clone()).unwrap();
+        expected.insert(key2, leaf2.clone()).unwrap();
+        assert_eq!(
+            mssmt.root().unwrap().hash(),
+            expected.root().unwrap().hash()
+        );
+
+        assert_eq!(mssmt.delete(key2).unwrap().unwrap().hash(), leaf2.hash());
+        let mut expected = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        expected.insert(key1, leaf1).unwrap();
+        assert_eq!(
+            mssmt.root().unwrap().hash(),
+            expected.root().unwrap().hash()
+        );
+        assert_eq!(stored_nodes(&mssmt), stored_nodes(&expected));
+    }
+
+    #[test]
+    fn test_compact_mssmt_get() {
+        let mut mssmt = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        assert!(mssmt.get([1; 32]).unwrap().is_none());
+        assert!(!mssmt.contains([1; 32]).unwrap());
+
+        let leaf = Leaf::new(vec![1; 32], 1);
+        mssmt.insert([1; 32], leaf.clone()).unwrap();
+        assert_eq!(mssmt.get([1; 32]).unwrap().unwrap().hash(), leaf.hash());
+        assert!(mssmt.contains([1; 32]).unwrap());
+        // The path to this key ends on the compact leaf of `[1; 32]`.
+        assert!(mssmt.get([3; 32]).unwrap().is_none());
+        assert!(!mssmt.contains([3; 32]).unwrap());
+        // The path to this key ends on an empty subtree.
+        assert!(mssmt.get([2; 32]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_compact_mssmt_insert_batch() {
+        // Mix keys with long common prefixes, keys on the last level and random keys, seeded
+        // so a failure can be reproduced.
+        let mut rng = StdRng::seed_from_u64(6);
+        let mut keys = vec![
+            hex!("0000000000000000000000000000000000000000000000000000000000000000"),
+            hex!("0000000000000000000000000000000000000000000000000000000000000080"),
+            hex!("0000000000000000000000000000000000000000000000000000000000000040"),
+            hex!("0100000000000000000000000000000000000000000000000000000000000000"),
+        ];
+        keys.extend((0..20).map(|_| rng.gen::<[u8; 32]>()));
+        let leaf = |i: usize| Leaf::new(vec![i as u8 + 1; 32], i as u64 + 1);
+
+        let mut mssmt = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        let mut expected = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        // Start from a tree that already has some of the leaves.
+        for (i, key) in keys.iter().enumerate().step_by(3) {
+            mssmt.insert(*key, leaf(i + 100)).unwrap();
+            expected.insert(*key, leaf(i + 100)).unwrap();
+        }
+        let batch: Vec<_> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| match i % 4 {
+                // Delete some of the leaves.
+                0 => (*key, Leaf::new(vec![], 0)),
+                _ => (*key, leaf(i)),
+            })
+            .collect();
+        mssmt.insert_batch(batch.clone()).unwrap();
+        for (key, leaf) in batch {
+            expected.insert(key, leaf).unwrap();
+        }
+        assert_eq!(
+            mssmt.root().unwrap().hash(),
+            expected.root().unwrap().hash()
+        );
+        assert_eq!(stored_nodes(&mssmt), stored_nodes(&expected));
+    }
+
+    #[test]
+    fn test_compact_mssmt_insert_batch_sum_overflow() {
+        let mut mssmt = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        assert_eq!(
+            mssmt.insert_batch(vec![
+                ([1; 32], Leaf::new(vec![1; 32], u64::MAX)),
+                ([2; 32], Leaf::new(vec![2; 32], 1)),
+            ]),
+            Err(TreeError::SumOverflow)
+        );
+    }
+
+    #[test]
+    fn test_compact_mssmt_from_sorted_leaves() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut keys = vec![
+            hex!("0000000000000000000000000000000000000000000000000000000000000000"),
+            hex!("0000000000000000000000000000000000000000000000000000000000000080"),
+            hex!("0000000000000000000000000000000000000000000000000000000000000040"),
+        ];
+        keys.extend((0..20).map(|_| rng.gen::<[u8; 32]>()));
+        let mut leaves: Vec<_> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| (*key, Leaf::new(vec![i as u8; 32], i as u64)))
+            .collect();
+
+        let mut expected = CompactMSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::new()));
+        for (key, leaf) in leaves.clone() {
+            expected.insert(key, leaf).unwrap();
+        }
+
+        // Unsorted input is sorted first.
+        let mssmt = CompactMSSMT::<32, Sha256, ()>::from_sorted_leaves(
+            Box::new(MemoryDb::new()),
+            leaves.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            mssmt.root().unwrap().hash(),
+            expected.root().unwrap().hash()
+        );
+        assert_eq!(stored_nodes(&mssmt), stored_nodes(&expected));
+
+        leaves.sort_by_key(|(key, _)| key.map(u8::reverse_bits));
+        let mssmt =
+            CompactMSSMT::<32, Sha256, ()>::from_sorted_leaves(Box::new(MemoryDb::new()), leaves)
+                .unwrap();
+        assert_eq!(
+            mssmt.root().unwrap().hash(),
+            expected.root().unwrap().hash()
+        );
+        assert_eq!(stored_nodes(&mssmt), stored_nodes(&expected));
+
+        let mssmt =
+            CompactMSSMT::<32, Sha256, ()>::from_sorted_leaves(Box::new(MemoryDb::new()), vec![])
+                .unwrap();
+        assert_eq!(
+            mssmt.root().unwrap().hash(),
+            mssmt.db().empty_tree()[0].hash()
+        );
+        assert!(matches!(
+            CompactMSSMT::<32, Sha256, ()>::from_sorted_leaves(
+                Box::new(MemoryDb::new()),
+                vec![
+                    ([1; 32], Leaf::new(vec![1; 32], u64::MAX)),
+                    ([2; 32], Leaf::new(vec![2; 32], 1)),
+                ]
+            ),
+            Err(TreeError::SumOverflow)
+        ));
+
+        // The nodes of the tree already in the database would be orphaned.
+        let mut db = MemoryDb::new();
+        db.update_root(expected.root().unwrap()).unwrap();
+        assert!(matches!(
+            CompactMSSMT::<32, Sha256, ()>::from_sorted_leaves(
+                Box::new(db),
+                vec![([1; 32], Leaf::new(vec![1; 32], 1))]
+            ),
+            Err(TreeError::DbNotEmpty)
+        ));
+    }
 }
@@ -1,32 +1,56 @@
 //! Empty tree implementation for the Merkle Sum Sparse Merkle Tree
 
-use std::{cell::LazyCell, marker::PhantomData, sync::Arc};
-use typenum::{Prod, Sum, Unsigned, U1, U8};
+use std::{
+    any::{Any, TypeId},
+    cell::RefCell,
+    collections::HashMap,
+    marker::PhantomData,
+    sync::Arc,
+};
 
-use crate::node::{Hasher, Node};
-
-/// Define the empty tree array size as (HASH_SIZE * 8) + 1
-pub type TreeSize = Sum<Prod<U8, typenum::U32>, U1>;
+use crate::node::{Hasher, Node, Sum};
 
 /// Helper struct to create an empty mssmt.
-pub struct EmptyTree<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone>(PhantomData<H>);
+///
+/// The empty tree has a node for each height from the root to the leaves, that is
+/// `HASH_SIZE * 8 + 1` nodes.
+pub struct EmptyTree<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum = u64>(
+    PhantomData<(H, S)>,
+);
 
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> EmptyTree<HASH_SIZE, H> {
-    #[allow(clippy::declare_interior_mutable_const)]
-    const EMPTY_TREE: LazyCell<Arc<[Node<HASH_SIZE, H>; TreeSize::USIZE]>> =
-        LazyCell::new(|| Arc::new(Self::build_tree()));
+thread_local! {
+    /// Empty trees built on this thread, by [`EmptyTree`] type. They're kept per thread as a
+    /// hasher doesn't have to be `Send` or `Sync`.
+    static EMPTY_TREES: RefCell<HashMap<TypeId, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
 
-    /// Gets an empty mssmt.
-    pub fn empty_tree() -> Arc<[Node<HASH_SIZE, H>; TreeSize::USIZE]> {
-        #[allow(clippy::borrow_interior_mutable_const)]
-        Self::EMPTY_TREE.clone()
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum> EmptyTree<HASH_SIZE, H, S> {
+    /// Gets an empty mssmt, built on the first call for these hash size, hasher and sum type.
+    pub fn empty_tree() -> Arc<[Node<HASH_SIZE, H, S>]> {
+        EMPTY_TREES.with(|trees| {
+            let key = TypeId::of::<Self>();
+            if let Some(tree) = trees.borrow().get(&key) {
+                return tree
+                    .downcast_ref::<Arc<[Node<HASH_SIZE, H, S>]>>()
+                    .expect("Empty trees are stored under their type")
+                    .clone();
+            }
+            let tree: Arc<[Node<HASH_SIZE, H, S>]> = Self::build_tree().into();
+            trees.borrow_mut().insert(key, Box::new(tree.clone()));
+            tree
+        })
+    }
+
+    /// Number of nodes of the empty tree.
+    pub const fn size() -> usize {
+        HASH_SIZE * 8 + 1
     }
 
     /// builds the empty tree
-    fn build_tree() -> [Node<HASH_SIZE, H>; TreeSize::USIZE] {
+    fn build_tree() -> Vec<Node<HASH_SIZE, H, S>> {
         let max_height = HASH_SIZE * 8;
-        let mut empty_tree = Vec::with_capacity(max_height + 1);
-        let empty_leaf = Node::<HASH_SIZE, H>::new_empty_leaf();
+        let mut empty_tree = Vec::with_capacity(Self::size());
+        let empty_leaf = Node::<HASH_SIZE, H, S>::new_empty_leaf();
         empty_tree.push(empty_leaf);
 
         for i in 1..=max_height {
@@ -42,7 +66,28 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone> EmptyTree<HASH_SIZE,
         };
 
         empty_tree
-            .try_into()
-            .unwrap_or_else(|_| unreachable!("Incorrect array size"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use sha2::{Sha256, Sha512_256};
+
+    use super::EmptyTree;
+
+    #[test]
+    fn test_empty_tree_cache() {
+        // The second call gets the tree built by the first one.
+        let tree = EmptyTree::<32, Sha256>::empty_tree();
+        assert!(Arc::ptr_eq(&tree, &EmptyTree::<32, Sha256>::empty_tree()));
+        // Each type has its own tree.
+        let other = EmptyTree::<32, Sha512_256>::empty_tree();
+        assert_ne!(tree[0].hash(), other[0].hash());
+        assert!(Arc::ptr_eq(
+            &other,
+            &EmptyTree::<32, Sha512_256>::empty_tree()
+        ));
     }
 }
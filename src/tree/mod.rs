@@ -1,3 +1,6 @@
+#[cfg(feature = "async")]
+mod async_tree;
+mod audit;
 mod compact;
 mod empty;
 mod regular;
@@ -5,8 +8,11 @@ mod regular;
 use std::borrow::Borrow;
 use std::sync::Arc;
 
+#[cfg(feature = "async")]
+pub use async_tree::{AsyncCompactMSSMT, AsyncMSSMT};
+pub use audit::{AuditIssue, AuditReport, Corruption};
 pub use compact::CompactMSSMT;
-pub use empty::{EmptyTree, TreeSize};
+pub use empty::EmptyTree;
 pub use regular::bit_index;
 pub use regular::MSSMT;
 
@@ -14,37 +20,62 @@ use crate::Branch;
 use crate::Hasher;
 use crate::Leaf;
 use crate::Node;
+use crate::Proof;
+use crate::Sum;
 use crate::TreeError;
 
+/// Sorts a batch of leaves in the order of the tree paths, keeping only the last leaf
+/// of each key like sequential inserts would.
+pub(crate) fn sort_batch<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, S: Sum>(
+    leaves: impl IntoIterator<Item = ([u8; HASH_SIZE], Leaf<HASH_SIZE, H, S>)>,
+) -> Vec<([u8; HASH_SIZE], Leaf<HASH_SIZE, H, S>)> {
+    let mut leaves: Vec<_> = leaves.into_iter().collect();
+    // Paths read the bits of each byte from the least significant one.
+    leaves.sort_by_key(|(key, _)| key.map(u8::reverse_bits));
+    let mut batch: Vec<([u8; HASH_SIZE], Leaf<HASH_SIZE, H, S>)> = Vec::with_capacity(leaves.len());
+    for (key, leaf) in leaves {
+        match batch.last_mut() {
+            Some(last) if last.0 == key => last.1 = leaf,
+            _ => batch.push((key, leaf)),
+        }
+    }
+    batch
+}
+
 /// Walk up the tree from the node to the root node.
 /// * `key` - key of the node we want to reach.
 /// * `start` - starting leaf.
 /// * `siblings` - All the sibling nodes on the path (from the leaf to the target node).
 /// * `for_each` - Closure that is executed at each step of the traversal of the tree.
 ///     * `height: usize` - current height in the tree
-///     * `current: &Node<HASH_SIZE, H>` - current node on the way to the asked node
-///     * `sibling: &Node<HASH_SIZE, H>` - sibling node of the current node on the way to the asked node
-///     * `parent: &Node<HASH_SIZE, H>` - parent node of the current node on the way to the asked node
-pub fn walk_up<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>(
+///     * `current: &Node<HASH_SIZE, H, S>` - current node on the way to the asked node
+///     * `sibling: &Node<HASH_SIZE, H, S>` - sibling node of the current node on the way to the asked node
+///     * `parent: &Node<HASH_SIZE, H, S>` - parent node of the current node on the way to the asked node
+pub fn walk_up<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError, S: Sum>(
     key: [u8; HASH_SIZE],
-    start: Leaf<HASH_SIZE, H>,
-    siblings: Vec<Arc<Node<HASH_SIZE, H>>>,
-    mut for_each: impl FnMut(usize, &Node<HASH_SIZE, H>, &Node<HASH_SIZE, H>, &Node<HASH_SIZE, H>),
-) -> Result<Branch<HASH_SIZE, H>, TreeError<DbError>> {
+    start: Leaf<HASH_SIZE, H, S>,
+    siblings: Vec<Arc<Node<HASH_SIZE, H, S>>>,
+    mut for_each: impl FnMut(
+        usize,
+        &Node<HASH_SIZE, H, S>,
+        &Node<HASH_SIZE, H, S>,
+        &Node<HASH_SIZE, H, S>,
+    ),
+) -> Result<Branch<HASH_SIZE, H, S>, TreeError<DbError>> {
     let mut current = Arc::new(Node::Leaf(start));
-    for i in (0..MSSMT::<HASH_SIZE, H, DbError>::max_height()).rev() {
-        let sibling = siblings[MSSMT::<HASH_SIZE, H, DbError>::max_height() - 1 - i].clone();
+    for i in (0..MSSMT::<HASH_SIZE, H, DbError, S>::max_height()).rev() {
+        let sibling = siblings[MSSMT::<HASH_SIZE, H, DbError, S>::max_height() - 1 - i].clone();
         // order the children based on the path
         let parent = if bit_index(i, &key) == 0 {
-            Node::Branch(Branch::new_with_arc_children(
+            Node::Branch(Branch::try_new_with_arc_children(
                 current.clone(),
                 sibling.clone(),
-            ))
+            )?)
         } else {
-            Node::Branch(Branch::new_with_arc_children(
+            Node::Branch(Branch::try_new_with_arc_children(
                 sibling.clone(),
                 current.clone(),
-            ))
+            )?)
         };
         for_each(i, &current, &sibling, &parent);
         current = Arc::new(parent);
@@ -68,19 +99,19 @@ pub fn walk_up<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>(
 /// # Returns
 ///
 /// Returns `Ok(())` if the proof is valid, otherwise returns an error.
-pub fn verify_merkle_proof<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError>(
+pub fn verify_merkle_proof<
+    const HASH_SIZE: usize,
+    H: Hasher<HASH_SIZE> + Clone,
+    DbError,
+    S: Sum,
+>(
     key: [u8; HASH_SIZE],
-    leaf: Leaf<HASH_SIZE, H>,
-    proof: Vec<Node<HASH_SIZE, H>>,
+    leaf: Leaf<HASH_SIZE, H, S>,
+    proof: Proof<HASH_SIZE, H, S>,
     root_hash: [u8; HASH_SIZE],
 ) -> Result<(), TreeError<DbError>> {
     // Compute the root from the leaf and the proof
-    let got_root = walk_up(
-        key,
-        leaf,
-        proof.into_iter().map(Arc::new).collect(),
-        |_, _, _, _| {},
-    )?;
+    let got_root = proof.root(key, leaf)?;
     // Check if the computed root matches the expected root
     if got_root.hash() == root_hash {
         Ok(())
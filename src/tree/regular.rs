@@ -3,19 +3,24 @@
 use std::{marker::PhantomData, sync::Arc};
 
 use crate::{
-    db::Db,
-    node::{Branch, Hasher, Leaf, Node},
+    db::{Db, GcReport},
+    node::{Branch, EmptyLeaf, Hasher, Leaf, Node, Sum},
+    proof::{NonInclusionProof, Proof},
     TreeError,
 };
 
-use super::walk_up;
+use super::{
+    audit::{audit, AuditIssue, AuditReport, Corruption},
+    sort_batch, walk_up,
+};
 
 /// Merkle sum sparse merkle tree.
 /// * `KVStore` - Key value store for nodes.
 /// * `HASH_SIZE` - size of the hash digest in bytes.
 /// * `H` - Hasher that will be used to hash nodes.
-pub struct MSSMT<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError> {
-    db: Box<dyn Db<HASH_SIZE, H, DbError = DbError>>,
+/// * `S` - type of the sums, `u64` by default.
+pub struct MSSMT<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError, S: Sum = u64> {
+    db: Box<dyn Db<HASH_SIZE, H, S, DbError = DbError>>,
     _phantom: PhantomData<H>,
 }
 
@@ -27,15 +32,17 @@ pub fn bit_index(index: usize, key: &[u8]) -> u8 {
     (key[index / 8] >> (index % 8)) & 1
 }
 
-impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError> MSSMT<HASH_SIZE, H, DbError> {
+impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError, S: Sum>
+    MSSMT<HASH_SIZE, H, DbError, S>
+{
     /// Creates a new mssmt. This will build an empty tree which will involve a lot of hashing.
-    pub fn new(db: Box<dyn Db<HASH_SIZE, H, DbError = DbError>>) -> Self {
+    pub fn new(db: Box<dyn Db<HASH_SIZE, H, S, DbError = DbError>>) -> Self {
         Self {
             db,
             _phantom: PhantomData,
         }
     }
-    pub fn db(&self) -> &dyn Db<HASH_SIZE, H, DbError = DbError> {
+    pub fn db(&self) -> &dyn Db<HASH_SIZE, H, S, DbError = DbError> {
         self.db.as_ref()
     }
 
@@ -45,7 +52,7 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError> MSSMT<HASH_S
     }
 
     /// Root node of the tree.
-    pub fn root(&self) -> Result<Branch<HASH_SIZE, H>, TreeError<DbError>> {
+    pub fn root(&self) -> Result<Branch<HASH_SIZE, H, S>, TreeError<DbError>> {
         match self.db.get_root_node() {
             Some(branch) => Ok(branch),
             None => {
@@ -62,9 +69,29 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError> MSSMT<HASH_S
     pub fn walk_down(
         &self,
         key: [u8; HASH_SIZE],
-        mut for_each: impl FnMut(usize, &Node<HASH_SIZE, H>, Node<HASH_SIZE, H>, Node<HASH_SIZE, H>),
-    ) -> Result<Leaf<HASH_SIZE, H>, TreeError<DbError>> {
-        let mut current = Node::Branch(self.root()?);
+        for_each: impl FnMut(
+            usize,
+            &Node<HASH_SIZE, H, S>,
+            Node<HASH_SIZE, H, S>,
+            Node<HASH_SIZE, H, S>,
+        ),
+    ) -> Result<Leaf<HASH_SIZE, H, S>, TreeError<DbError>> {
+        self.walk_down_from(self.root()?, key, for_each)
+    }
+
+    /// Walk down the tree from `root` to the leaf at `key`, see [`MSSMT::walk_down`].
+    fn walk_down_from(
+        &self,
+        root: Branch<HASH_SIZE, H, S>,
+        key: [u8; HASH_SIZE],
+        mut for_each: impl FnMut(
+            usize,
+            &Node<HASH_SIZE, H, S>,
+            Node<HASH_SIZE, H, S>,
+            Node<HASH_SIZE, H, S>,
+        ),
+    ) -> Result<Leaf<HASH_SIZE, H, S>, TreeError<DbError>> {
+        let mut current = Node::Branch(root);
         for i in 0..Self::max_height() {
             let (left, right) = self.db.get_children(i, current.hash())?;
             let (next, sibling) = if bit_index(i, &key) == 0 {
@@ -83,16 +110,166 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError> MSSMT<HASH_S
         }
     }
 
-    /// Insert a leaf in the tree.
+    /// Get the leaf stored at `key`, or `None` if there is no leaf at `key`.
+    pub fn get(
+        &self,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Option<Leaf<HASH_SIZE, H, S>>, TreeError<DbError>> {
+        self.get_from(self.root()?, key)
+    }
+
+    /// Get the leaf stored at `key` in the tree of root `root`.
+    fn get_from(
+        &self,
+        root: Branch<HASH_SIZE, H, S>,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Option<Leaf<HASH_SIZE, H, S>>, TreeError<DbError>> {
+        let mut current = Node::Branch(root);
+        for i in 0..Self::max_height() {
+            let (left, right) = self.db.get_children(i, current.hash())?;
+            let next = if bit_index(i, &key) == 0 { left } else { right };
+            // Stop as soon as we reach an empty subtree.
+            if next.hash() == self.db.empty_tree()[i + 1].hash() {
+                return Ok(None);
+            }
+            current = next;
+        }
+        match current {
+            Node::Leaf(leaf) => Ok(Some(leaf)),
+            _ => Err(TreeError::ExpectedLeaf),
+        }
+    }
+
+    /// Returns `true` if a leaf is stored at `key`.
+    pub fn contains(&self, key: [u8; HASH_SIZE]) -> Result<bool, TreeError<DbError>> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Insert a leaf in the tree. Inserting an empty leaf is the same as deleting `key`.
     pub fn insert(
         &mut self,
         key: [u8; HASH_SIZE],
-        leaf: Leaf<HASH_SIZE, H>,
+        leaf: Leaf<HASH_SIZE, H, S>,
+    ) -> Result<(), TreeError<DbError>> {
+        if let Leaf::Empty(_) = leaf {
+            return self.delete(key).map(|_| ());
+        }
+        self.atomic(|tree| tree.update(key, leaf).map(|_| ()))
+    }
+
+    /// Insert a batch of leaves in the tree.
+    ///
+    /// The keys are sorted so the branches on common prefixes are only computed and written
+    /// once. The resulting tree is the same as inserting the leaves one by one in order.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if the sum of the updated tree overflows, the tree being left unchanged
+    pub fn insert_batch(
+        &mut self,
+        leaves: impl IntoIterator<Item = ([u8; HASH_SIZE], Leaf<HASH_SIZE, H, S>)>,
     ) -> Result<(), TreeError<DbError>> {
+        let batch = sort_batch(leaves);
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.atomic(|tree| {
+            // The root is read in the transaction, as another handle to a shared tree can
+            // update it until then.
+            let root = tree.root()?;
+            let Node::Branch(root) = tree.insert_batch_at(0, Node::Branch(root), &batch)? else {
+                return Err(TreeError::ExpectedBranch);
+            };
+            tree.db.update_root(root)
+        })
+    }
+
+    /// Inserts the sorted `batch` in the subtree `node` at `height` and returns the new subtree.
+    fn insert_batch_at(
+        &mut self,
+        height: usize,
+        node: Node<HASH_SIZE, H, S>,
+        batch: &[([u8; HASH_SIZE], Leaf<HASH_SIZE, H, S>)],
+    ) -> Result<Node<HASH_SIZE, H, S>, TreeError<DbError>> {
+        if height == Self::max_height() {
+            // Keys are unique in the batch so there is a single leaf here.
+            let leaf = batch[0].1.clone();
+            if let Node::Leaf(old_leaf @ Leaf::NonEmpty(_)) = node {
+                self.db.delete_leaf(&old_leaf.hash())?;
+            }
+            if !leaf.is_empty() {
+                self.db.insert_leaf(leaf.clone())?;
+            }
+            return Ok(Node::Leaf(leaf));
+        }
+
+        let (mut left, mut right) = self.db.get_children(height, node.hash())?;
+        let split = batch.partition_point(|(key, _)| bit_index(height, key) == 0);
+        let (left_batch, right_batch) = batch.split_at(split);
+        if !left_batch.is_empty() {
+            left = self.insert_batch_at(height + 1, left, left_batch)?;
+        }
+        if !right_batch.is_empty() {
+            right = self.insert_batch_at(height + 1, right, right_batch)?;
+        }
+
+        let empty_hash = self.db.empty_tree()[height].hash();
+        if node.hash() != empty_hash {
+            self.db.delete_branch(&node.hash())?;
+        }
+        let branch = Branch::try_new(left, right)?;
+        if branch.hash() != empty_hash {
+            self.db.insert_branch(branch.clone())?;
+        }
+        Ok(Node::Branch(branch))
+    }
+
+    /// Delete the leaf stored at `key` from the tree.
+    ///
+    /// Returns the removed leaf or `None` if there was no leaf at `key`.
+    pub fn delete(
+        &mut self,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Option<Leaf<HASH_SIZE, H, S>>, TreeError<DbError>> {
+        self.atomic(|tree| {
+            let old_leaf = tree.update(key, Leaf::Empty(EmptyLeaf::new()))?;
+            Ok((!old_leaf.is_empty()).then_some(old_leaf))
+        })
+    }
+
+    /// Runs `f` in a database transaction so the tree is left unchanged if it fails.
+    fn atomic<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, TreeError<DbError>>,
+    ) -> Result<T, TreeError<DbError>> {
+        self.db.begin()?;
+        match f(self) {
+            // A failed commit may leave some of the updates, they're rolled back too.
+            Ok(value) => match self.db.commit() {
+                Ok(()) => Ok(value),
+                Err(e) => {
+                    self.db.rollback()?;
+                    Err(e)
+                }
+            },
+            Err(e) => {
+                self.db.rollback()?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Replace the leaf at `key` and recompute the path to the root.
+    /// Returns the leaf that was previously stored at `key`.
+    fn update(
+        &mut self,
+        key: [u8; HASH_SIZE],
+        leaf: Leaf<HASH_SIZE, H, S>,
+    ) -> Result<Leaf<HASH_SIZE, H, S>, TreeError<DbError>> {
         let mut prev_parents = Vec::with_capacity(Self::max_height());
         let mut siblings = Vec::with_capacity(Self::max_height());
 
-        self.walk_down(key, |_, _next, sibling, parent| {
+        let old_leaf = self.walk_down(key, |_, _next, sibling, parent| {
             prev_parents.push(parent.hash());
             siblings.push(Arc::new(sibling));
         })?;
@@ -118,34 +295,157 @@ impl<const HASH_SIZE: usize, H: Hasher<HASH_SIZE> + Clone, DbError> MSSMT<HASH_S
             },
         )?;
 
-        for branch in branches_insertion {
-            self.db.insert_branch(branch)?;
-        }
+        // Delete the old branches first so that branches that didn't change are
+        // inserted back.
         for key in branches_delete {
             self.db.delete_branch(&key)?;
         }
+        for branch in branches_insertion {
+            self.db.insert_branch(branch)?;
+        }
 
-        self.db.insert_leaf(leaf)?;
-        self.db.update_root(root)
+        if !old_leaf.is_empty() {
+            self.db.delete_leaf(&old_leaf.hash())?;
+        }
+        if !leaf.is_empty() {
+            self.db.insert_leaf(leaf)?;
+        }
+        self.db.update_root(root)?;
+        Ok(old_leaf)
     }
 
+    /// Merkle proof of the leaf stored at `key`.
     pub fn merkle_proof(
         &self,
         key: [u8; HASH_SIZE],
-    ) -> Result<Vec<Node<HASH_SIZE, H>>, TreeError<DbError>> {
+    ) -> Result<Proof<HASH_SIZE, H, S>, TreeError<DbError>> {
+        self.merkle_proof_from(self.root()?, key)
+    }
+
+    /// Merkle proof of the leaf stored at `key` in the tree of root `root`.
+    fn merkle_proof_from(
+        &self,
+        root: Branch<HASH_SIZE, H, S>,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Proof<HASH_SIZE, H, S>, TreeError<DbError>> {
+        let mut proof = Vec::with_capacity(Self::max_height());
+        self.walk_down_from(root, key, |_, _next, sibling, _| {
+            proof.push(sibling);
+        })?;
+        proof.reverse();
+        Proof::new(proof)
+    }
+
+    /// Returns a proof that `key` is not in the tree.
+    ///
+    /// Returns [`TreeError::ExpectedEmptyLeaf`] if a leaf is stored at `key`.
+    pub fn non_inclusion_proof(
+        &self,
+        key: [u8; HASH_SIZE],
+    ) -> Result<NonInclusionProof<HASH_SIZE, H, S>, TreeError<DbError>> {
         let mut proof = Vec::with_capacity(Self::max_height());
-        self.walk_down(key, |_, _next, sibling, _| {
+        let leaf = self.walk_down(key, |_, _next, sibling, _| {
             proof.push(sibling);
         })?;
+        if !leaf.is_empty() {
+            return Err(TreeError::ExpectedEmptyLeaf);
+        }
         proof.reverse();
-        Ok(proof)
+        Proof::new(proof).map(NonInclusionProof::new)
+    }
+
+    /// Current version of the tree, if the database keeps versions like
+    /// [`VersionedDb`](crate::VersionedDb).
+    pub fn version(&self) -> Option<u64> {
+        self.db.version()
+    }
+
+    /// Root node of the tree at `version`.
+    ///
+    /// Returns [`TreeError::VersionNotFound`] if the database doesn't retain this version.
+    pub fn root_at(&self, version: u64) -> Result<Branch<HASH_SIZE, H, S>, TreeError<DbError>> {
+        match self.db.get_root_at(version)? {
+            Some(branch) => Ok(branch),
+            None => {
+                let Node::Branch(branch) = self.db.empty_tree().as_ref()[0].clone() else {
+                    return Err(TreeError::ExpectedBranch);
+                };
+                Ok(branch)
+            }
+        }
+    }
+
+    /// Get the leaf stored at `key` at `version`, or `None` if there was no leaf at `key`.
+    pub fn get_at(
+        &self,
+        version: u64,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Option<Leaf<HASH_SIZE, H, S>>, TreeError<DbError>> {
+        self.get_from(self.root_at(version)?, key)
+    }
+
+    /// Merkle proof of the leaf stored at `key` at `version`, to verify against
+    /// [`MSSMT::root_at`].
+    pub fn merkle_proof_at(
+        &self,
+        version: u64,
+        key: [u8; HASH_SIZE],
+    ) -> Result<Proof<HASH_SIZE, H, S>, TreeError<DbError>> {
+        self.merkle_proof_from(self.root_at(version)?, key)
+    }
+
+    /// Forget the versions before `version` and delete the nodes only they use.
+    pub fn prune_versions(&mut self, version: u64) -> Result<(), TreeError<DbError>> {
+        self.atomic(|tree| tree.db.prune_versions(version))
+    }
+
+    /// Delete the nodes of the database that no tree or retained version uses, or only
+    /// count them with `dry_run`.
+    ///
+    /// Returns [`TreeError::GcNotSupported`] if the database can't list its nodes.
+    pub fn gc(&mut self, dry_run: bool) -> Result<GcReport, TreeError<DbError>> {
+        self.atomic(|tree| tree.db.gc(dry_run))
+    }
+
+    /// Check every stored node of the tree: recompute the hash and sum of the branches and
+    /// the hash of the leaves, and look for the nodes that are referenced but not stored.
+    ///
+    /// All the corrupted nodes are reported, the tree is intact if the report
+    /// [`is_ok`](AuditReport::is_ok).
+    pub fn audit(&self) -> AuditReport<HASH_SIZE, DbError> {
+        match self.root() {
+            Ok(root) => audit(self.db.as_ref(), root, Self::max_height()),
+            Err(e) => AuditReport {
+                nodes: 0,
+                issues: vec![AuditIssue {
+                    height: 0,
+                    hash: [0; HASH_SIZE],
+                    corruption: Corruption::DbError(e),
+                }],
+            },
+        }
+    }
+
+    /// Audit the tree and return the report if it found a corrupted node, see
+    /// [`MSSMT::audit`].
+    pub fn verify_integrity(&self) -> Result<(), AuditReport<HASH_SIZE, DbError>> {
+        let report = self.audit();
+        if report.is_ok() {
+            Ok(())
+        } else {
+            Err(report)
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::MSSMT;
-    use crate::{tree::verify_merkle_proof, Leaf, MemoryDb, TreeError};
+    use crate::{
+        node::{ComputedNode, Node},
+        tree::verify_merkle_proof,
+        verify_compressed_merkle_proof, Leaf, MemoryDb, Proof, TreeError,
+    };
     use sha2::Sha256;
 
     #[test]
@@ -167,7 +467,7 @@ mod test {
         mssmt.insert([0; 32], leaf.clone()).unwrap();
         let proof = mssmt.merkle_proof([0; 32]).unwrap();
         let root = mssmt.root().unwrap();
-        verify_merkle_proof::<32, Sha256, ()>([0; 32], leaf, proof, root.hash()).unwrap();
+        verify_merkle_proof::<32, Sha256, (), _>([0; 32], leaf, proof, root.hash()).unwrap();
     }
 
     #[test]
@@ -180,8 +480,136 @@ mod test {
         let proof = mssmt.merkle_proof([1; 32]).unwrap();
         let root = mssmt.root().unwrap();
         assert_eq!(
-            verify_merkle_proof::<32, Sha256, ()>([0; 32], leaf, proof, root.hash()).unwrap_err(),
+            verify_merkle_proof::<32, Sha256, (), _>([0; 32], leaf, proof, root.hash())
+                .unwrap_err(),
             TreeError::InvalidMerkleProof
         );
     }
+
+    #[test]
+    fn test_mssmt_delete() {
+        let mut mssmt = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::<32, Sha256>::new()));
+        let mut expected = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::<32, Sha256>::new()));
+        let leaf1 = Leaf::new(vec![1; 32], 1);
+        let leaf2 = Leaf::new(vec![2; 32], 2);
+        mssmt.insert([1; 32], leaf1.clone()).unwrap();
+        mssmt.insert([2; 32], leaf2.clone()).unwrap();
+        expected.insert([1; 32], leaf1).unwrap();
+
+        assert_eq!(mssmt.delete([2; 32]).unwrap().unwrap().hash(), leaf2.hash());
+        assert!(mssmt.delete([2; 32]).unwrap().is_none());
+        assert_eq!(
+            mssmt.root().unwrap().hash(),
+            expected.root().unwrap().hash()
+        );
+
+        let db = mssmt
+            .db()
+            .as_any()
+            .downcast_ref::<MemoryDb<32, Sha256>>()
+            .unwrap();
+        let expected_db = expected
+            .db()
+            .as_any()
+            .downcast_ref::<MemoryDb<32, Sha256>>()
+            .unwrap();
+        assert_eq!(db.get_branches().len(), expected_db.get_branches().len());
+        assert!(!db.get_leaves().contains_key(&leaf2.hash()));
+    }
+
+    #[test]
+    fn test_mssmt_get() {
+        let mut mssmt = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::<32, Sha256>::new()));
+        assert!(mssmt.get([1; 32]).unwrap().is_none());
+        assert!(!mssmt.contains([1; 32]).unwrap());
+
+        let leaf = Leaf::new(vec![1; 32], 1);
+        mssmt.insert([1; 32], leaf.clone()).unwrap();
+        assert_eq!(mssmt.get([1; 32]).unwrap().unwrap().hash(), leaf.hash());
+        assert!(mssmt.contains([1; 32]).unwrap());
+        assert!(mssmt.get([2; 32]).unwrap().is_none());
+        assert!(!mssmt.contains([2; 32]).unwrap());
+    }
+
+    #[test]
+    fn test_mssmt_insert_batch() {
+        let mut mssmt = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::<32, Sha256>::new()));
+        let mut expected = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::<32, Sha256>::new()));
+        let batch: Vec<_> = (1..=10u8)
+            .map(|i| ([i; 32], Leaf::new(vec![i; 32], i as u64)))
+            .collect();
+        mssmt.insert([1; 32], Leaf::new(vec![0; 32], 42)).unwrap();
+        expected
+            .insert([1; 32], Leaf::new(vec![0; 32], 42))
+            .unwrap();
+        mssmt.insert_batch(batch.clone()).unwrap();
+        for (key, leaf) in batch {
+            expected.insert(key, leaf).unwrap();
+        }
+        assert_eq!(
+            mssmt.root().unwrap().hash(),
+            expected.root().unwrap().hash()
+        );
+
+        let db = mssmt
+            .db()
+            .as_any()
+            .downcast_ref::<MemoryDb<32, Sha256>>()
+            .unwrap();
+        let expected_db = expected
+            .db()
+            .as_any()
+            .downcast_ref::<MemoryDb<32, Sha256>>()
+            .unwrap();
+        assert_eq!(db.get_branches().len(), expected_db.get_branches().len());
+    }
+
+    #[test]
+    fn test_mssmt_sum_overflow() {
+        let mut mssmt = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::<32, Sha256>::new()));
+        mssmt
+            .insert([0; 32], Leaf::new(vec![1; 32], u64::MAX))
+            .unwrap();
+        let root = mssmt.root().unwrap().hash();
+        assert_eq!(
+            mssmt.insert([1; 32], Leaf::new(vec![1; 32], 1)),
+            Err(TreeError::SumOverflow)
+        );
+        assert_eq!(
+            mssmt.insert_batch(vec![([1; 32], Leaf::new(vec![1; 32], 1))]),
+            Err(TreeError::SumOverflow)
+        );
+        // The tree is left untouched.
+        assert_eq!(mssmt.root().unwrap().hash(), root);
+        assert!(!mssmt.contains([1; 32]).unwrap());
+        // Replacing the leaf doesn't overflow.
+        mssmt.insert([0; 32], Leaf::new(vec![1; 32], 1)).unwrap();
+    }
+
+    #[test]
+    fn test_verify_merkle_proof_sum_overflow() {
+        let mut mssmt = MSSMT::<32, Sha256, ()>::new(Box::new(MemoryDb::<32, Sha256>::new()));
+        let leaf = Leaf::new(vec![1; 32], 1);
+        mssmt.insert([0; 32], leaf.clone()).unwrap();
+        let root = mssmt.root().unwrap().hash();
+
+        // A sibling with a huge sum would wrap the sums on the path.
+        let mut nodes = mssmt.merkle_proof([0; 32]).unwrap().into_nodes();
+        nodes[0] = Node::Computed(ComputedNode::new([7; 32], u64::MAX));
+        let proof = Proof::<32, Sha256>::new::<()>(nodes).unwrap();
+        assert_eq!(
+            verify_compressed_merkle_proof::<32, Sha256, (), _>(
+                [0; 32],
+                leaf.clone(),
+                &proof.compress(),
+                root
+            )
+            .unwrap_err(),
+            TreeError::SumOverflow
+        );
+        assert_eq!(
+            verify_merkle_proof::<32, Sha256, (), _>([0; 32], leaf, proof, root).unwrap_err(),
+            TreeError::SumOverflow
+        );
+    }
 }